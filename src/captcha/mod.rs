@@ -9,16 +9,54 @@ pub struct Captcha {
 	answer: Hash,
 }
 
+/// The parameters with which captchas are generated.
+#[derive(Serialize, Hash, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct CaptchaParams {
+	/// The number of characters in the answer
+	pub length: usize,
+
+	/// The width of the image, in pixels
+	pub width: u32,
+
+	/// The height of the image, in pixels
+	pub height: u32,
+
+	/// The amount of noise obscuring the characters, from 1 to 10
+	pub complexity: u32,
+
+	/// The JPEG quality of the image, from 1 to 99
+	pub compression: u8,
+}
+
+impl Default for CaptchaParams {
+	fn default() -> Self {
+		Self {
+			length: 5,
+			width: 130,
+			height: 40,
+			complexity: 8,
+			compression: 50,
+		}
+	}
+}
+
 impl Default for Captcha {
 	// Constructs a captcha, returning its base64 data and hashed answer.
 	fn default() -> Self {
+		Self::new(&CaptchaParams::default())
+	}
+}
+
+impl Captcha {
+	/// Constructs a captcha with the given parameters.
+	pub fn new(params: &CaptchaParams) -> Self {
 		let captcha = CaptchaBuilder::new()
-			.length(5)
-			.width(130)
-			.height(40)
+			.length(params.length)
+			.width(params.width)
+			.height(params.height)
 			.dark_mode(true)
-			.complexity(8)
-			.compression(50)
+			.complexity(params.complexity)
+			.compression(params.compression)
 			.build();
 
 		Self {
@@ -26,9 +64,7 @@ impl Default for Captcha {
 			answer: blake3::hash(captcha.text.as_bytes()).into(),
 		}
 	}
-}
 
-impl Captcha {
 	/// Gets a slice of the image data underlying the captcha.
 	pub fn data(&self) -> &[u8] {
 		self.data.as_slice()
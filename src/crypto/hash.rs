@@ -6,7 +6,7 @@ use std::{fmt, ops::Deref};
 use wasm_bindgen::JsValue;
 
 /// A hex-encoded, non-0x padded SHA-256 hash.
-#[derive(Hash, PartialEq, Eq, Debug, Clone)]
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Debug, Clone)]
 pub struct Hash {
 	bytes: [u8; 32],
 }
@@ -16,11 +16,14 @@ use crypto::hash::Hash;
 #[cfg(target_arch = "wasm32")]
 use js_sys::Function;
 #[cfg(target_arch = "wasm32")]
-use net::client::{Error, NetworkClient};
+use net::{
+	client::{Error, NetworkClient},
+	msg::ValidationError,
+};
 #[cfg(target_arch = "wasm32")]
-use rpc::cmd::{Cmd, CmdResp, LoadMsgReq, SubmitMsgReq};
+use rpc::cmd::{Cmd, CmdResp, ComposeMsgReq, LoadMsgReq, StateReq, SubmitMsgReq};
 #[cfg(target_arch = "wasm32")]
-use sys::{msg::Message, rt::Rt};
+use sys::{msg::Message, rt::Rt, snapshot::TrustedSnapshot, spec::ChainSpec};
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::JsValue;
 
@@ -39,19 +42,38 @@ lazy_static::lazy_static! {
 
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen::prelude::wasm_bindgen]
-pub fn start(chain_id: usize, bootstrap_nodes: Vec<js_sys::JsString>) {
+pub fn start(
+	chain_id: usize,
+	bootstrap_nodes: Vec<js_sys::JsString>,
+	trusted_snapshot: JsValue,
+	chain_spec: JsValue,
+) -> Result<(), String> {
 	struct Client {
 		chain_id: usize,
+		trusted_snapshot: Option<TrustedSnapshot>,
+		chain_spec: Option<ChainSpec>,
 	}
 
 	impl NetworkClient for Client {
-		fn tx_follows_consensus_rules(&self, _rt: &Rt, _msg: &Message) -> bool {
-			true
+		fn tx_follows_consensus_rules(
+			&self,
+			_rt: &Rt,
+			_msg: &Message,
+		) -> Result<(), ValidationError> {
+			Ok(())
 		}
 
 		fn chain_id(&self) -> usize {
 			self.chain_id
 		}
+
+		fn trusted_snapshot(&self) -> Option<TrustedSnapshot> {
+			self.trusted_snapshot.clone()
+		}
+
+		fn chain_spec(&self) -> Option<ChainSpec> {
+			self.chain_spec.clone()
+		}
 	}
 
 	wasm_logger::init(wasm_logger::Config::default());
@@ -62,7 +84,24 @@ pub fn start(chain_id: usize, bootstrap_nodes: Vec<js_sys::JsString>) {
 		.filter_map(|js_str| js_str.as_string())
 		.collect::<Vec<String>>();
 
-	let mut client = Client { chain_id };
+	// Begin synchronizing from the trusted snapshot, if one is given
+	let trusted_snapshot = serde_wasm_bindgen::from_value(trusted_snapshot)
+		.map_err(|e| Error::SerdeWasmError(e))
+		.map_err(|e| e.to_string())?;
+
+	// Only accept chains rooted at the genesis of the spec, if one is given
+	let chain_spec: Option<ChainSpec> = serde_wasm_bindgen::from_value(chain_spec)
+		.map_err(|e| Error::SerdeWasmError(e))
+		.map_err(|e| e.to_string())?;
+	if let Some(spec) = &chain_spec {
+		spec.validate(chain_id).map_err(|e| e.to_string())?;
+	}
+
+	let mut client = Client {
+		chain_id,
+		trusted_snapshot,
+		chain_spec,
+	};
 	wasm_bindgen_futures::spawn_local(
 		client
 			.start(
@@ -75,6 +114,8 @@ pub fn start(chain_id: usize, bootstrap_nodes: Vec<js_sys::JsString>) {
 			)
 			.map(|_| ()),
 	);
+
+	Ok(())
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -113,6 +154,45 @@ pub async fn submit_message(msg_data: wasm_bindgen::JsValue) -> Result<String, S
 	}
 }
 
+/// Submits a message carrying the given data that extends the current HEAD,
+/// answering the current challenge with the given captcha answer. Returns the
+/// hex-encoded hash of the message.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen::prelude::wasm_bindgen]
+pub async fn compose_message(data: Vec<u8>, captcha_ans: Option<String>) -> Result<String, String> {
+	let req_id = instant::now() as usize;
+	CMD_RX_TX
+		.0
+		.send(Cmd::ComposeAndSubmit {
+			req: ComposeMsgReq::new(data, captcha_ans),
+			req_id,
+		})
+		.await
+		.map_err(|e| e.to_string())?;
+
+	loop {
+		match RESP_RX_TX.1.recv().await.map_err(|e| e.to_string())? {
+			CmdResp::MsgSubmitted {
+				hash: h,
+				req_id: resp_id,
+			} => {
+				if resp_id == req_id {
+					return Ok(hex::encode(h));
+				}
+			}
+			CmdResp::Error {
+				error,
+				req_id: resp_id,
+			} => {
+				if req_id == resp_id {
+					return Err(format!("Failed to submit the message: {}", error));
+				}
+			}
+			_ => continue,
+		}
+	}
+}
+
 /// Gets a JSON encoding of the message with the given hash.
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen::prelude::wasm_bindgen]
@@ -194,6 +274,79 @@ pub async fn get_head() -> Result<String, String> {
 	}
 }
 
+/// Gets a JSON encoding of the progress of the synchronization of the chain,
+/// including its stage, the height of the HEAD, and the height being synced to.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen::prelude::wasm_bindgen]
+pub async fn get_sync_status() -> Result<JsValue, String> {
+	let req_id = instant::now() as usize;
+	CMD_RX_TX
+		.0
+		.send(Cmd::GetSyncStatus { req_id })
+		.await
+		.map_err(|e| e.to_string())?;
+
+	loop {
+		match RESP_RX_TX.1.recv().await.map_err(|e| e.to_string())? {
+			CmdResp::SyncStatus {
+				status,
+				req_id: resp_id,
+			} => {
+				if resp_id == req_id {
+					return Ok(serde_wasm_bindgen::to_value(&status).map_err(|e| e.to_string())?);
+				}
+			}
+			CmdResp::Error {
+				error,
+				req_id: resp_id,
+			} => {
+				if resp_id == req_id {
+					return Err(format!(
+						"Error occurred while loading sync status: {}",
+						error
+					));
+				}
+			}
+			_ => continue,
+		}
+	}
+}
+
+/// Gets a JSON encoding of the challenge that the next message in the chain
+/// must answer, including the HEAD, the next height, and the captcha image.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen::prelude::wasm_bindgen]
+pub async fn get_challenge() -> Result<JsValue, String> {
+	let req_id = instant::now() as usize;
+	CMD_RX_TX
+		.0
+		.send(Cmd::GetChallenge { req_id })
+		.await
+		.map_err(|e| e.to_string())?;
+
+	loop {
+		match RESP_RX_TX.1.recv().await.map_err(|e| e.to_string())? {
+			CmdResp::Challenge {
+				challenge,
+				req_id: resp_id,
+			} => {
+				if resp_id == req_id {
+					return Ok(serde_wasm_bindgen::to_value(&challenge).map_err(|e| e.to_string())?);
+				}
+			}
+			CmdResp::Error {
+				error,
+				req_id: resp_id,
+			} => {
+				if resp_id == req_id {
+					return Err(format!("Error occurred while loading challenge: {}", error));
+				}
+			}
+			_ => continue,
+		}
+	}
+}
+
 /// Registers a callback to be executed every time a new message is received.
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen::prelude::wasm_bindgen]
@@ -222,6 +375,79 @@ pub async fn on_message(callback: Function) {
 	});
 }
 
+/// Registers a callback to be executed with the application state carried by
+/// the trusted snapshot once it is restored.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen::prelude::wasm_bindgen]
+pub async fn on_snapshot(callback: Function) {
+	wasm_bindgen_futures::spawn_local(async move {
+		loop {
+			match RESP_RX_TX.1.recv().await.map_err(|e| e.to_string()) {
+				Ok(CmdResp::SnapshotLoaded { state, .. }) => {
+					let json = match serde_wasm_bindgen::to_value(&state).map_err(|e| e.to_string())
+					{
+						Ok(v) => v,
+						Err(e) => {
+							error!("Error occurred while serializing snapshot: {}", e);
+							continue;
+						}
+					};
+
+					let this = JsValue::null();
+					callback.call1(&this, &json);
+				}
+				Err(e) => {
+					error!("Error occurred while listening to snapshots: {}", e);
+				}
+				_ => {}
+			}
+		}
+	});
+}
+
+/// Serves the application state along with snapshots of the chain ending at
+/// the message with the given hash.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen::prelude::wasm_bindgen]
+pub async fn set_snapshot_state(hash_str: &str, state: Vec<u8>) -> Result<(), String> {
+	let hash_bytes = hex::decode(hash_str).map_err(|e| e.to_string())?;
+	let hash_bytes_arr: [u8; 32] = hash_bytes
+		.try_into()
+		.map_err(|_| String::from("Invalid hash length."))?;
+
+	let req_id = instant::now() as usize;
+	CMD_RX_TX
+		.0
+		.send(Cmd::SetSnapshotState {
+			req: StateReq::new(hash_bytes_arr.into(), state),
+			req_id,
+		})
+		.await
+		.map_err(|e| e.to_string())?;
+
+	loop {
+		match RESP_RX_TX.1.recv().await.map_err(|e| e.to_string())? {
+			CmdResp::SnapshotStateSet { req_id: resp_id } => {
+				if resp_id == req_id {
+					return Ok(());
+				}
+			}
+			CmdResp::Error {
+				error,
+				req_id: resp_id,
+			} => {
+				if resp_id == req_id {
+					return Err(format!(
+						"Error occurred while setting snapshot state: {}",
+						error
+					));
+				}
+			}
+			_ => continue,
+		}
+	}
+}
+
 /// Clears the runtime.
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen::prelude::wasm_bindgen]
@@ -1,26 +1,38 @@
-use chud::net::client::{DefaultClient as Client, NetworkClient};
+use chud::{
+	net::client::{self, DefaultClient as Client, NetworkClient},
+	sys::spec::ChainSpec,
+};
 
 #[cfg(not(target_arch = "wasm32"))]
 use actix_web::{web::Data, App, HttpServer};
 #[cfg(not(target_arch = "wasm32"))]
-use chud::rpc::{get_head, health_check, load_msg, submit_msg, terminate};
+use chud::rpc::{
+	compose_msg, get_challenge, get_head, get_rejections, get_sync_status, health_check, load_msg,
+	set_snapshot_state, submit_msg, terminate,
+};
 #[cfg(not(target_arch = "wasm32"))]
-use clap::{arg, command, Parser};
+use clap::{arg, command, Parser, Subcommand};
 #[cfg(not(target_arch = "wasm32"))]
 use futures::TryFutureExt;
-use libp2p::Multiaddr;
+use libp2p::{Multiaddr, PeerId};
 #[cfg(not(target_arch = "wasm32"))]
-use std::error::Error;
+use std::{error::Error, fs};
 
 /// Arguments to chudd:
 /// --chain-id: The unique segregator for the blockchain. Should be the same
 /// across clients on the same network.
 /// --bootstrap-peers: A list of multiaddrs representing the peers to bootstrap
 /// the chain from.
+/// --spec: A path to the JSON chain spec of the blockchain. See `gen-spec`.
+/// --key-path: A path to the file holding the keypair identifying the node.
+/// Defaults to a file beside the runtime database. See `peer-id`.
 #[cfg(not(target_arch = "wasm32"))]
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+	#[command(subcommand)]
+	command: Option<Command>,
+
 	#[arg(short, long, default_value_t = 0)]
 	chain_id: usize,
 
@@ -38,6 +50,28 @@ struct Args {
 
 	#[arg(long)]
 	cert_path: Option<String>,
+
+	#[arg(long)]
+	spec: Option<String>,
+
+	#[arg(long)]
+	key_path: Option<String>,
+}
+
+/// Subcommands of chudd. Without one, chudd runs a node.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Subcommand, Debug)]
+enum Command {
+	/// Generates the spec of a new chain with the given --chain-id, and
+	/// writes it to the output path.
+	GenSpec {
+		#[arg(short, long, default_value = "chain_spec.json")]
+		output: String,
+	},
+
+	/// Prints the peer ID of the node, generating its keypair at the
+	/// --key-path if it does not exist.
+	PeerId,
 }
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -47,6 +81,44 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
 	let args = Args::parse();
 
+	match args.command {
+		Some(Command::GenSpec { output }) => {
+			let spec = ChainSpec::generate(args.chain_id)?;
+			fs::write(&output, serde_json::to_vec_pretty(&spec)?)?;
+
+			println!(
+				"wrote the spec of chain {} with genesis {} to {}",
+				args.chain_id,
+				hex::encode(spec.genesis_hash()),
+				output
+			);
+
+			return Ok(());
+		}
+		Some(Command::PeerId) => {
+			let key_path = args
+				.key_path
+				.unwrap_or_else(|| client::default_key_path(args.chain_id));
+			let local_key = client::load_keypair(&key_path).await?;
+
+			println!("{}", PeerId::from(local_key.public()));
+
+			return Ok(());
+		}
+		None => {}
+	}
+
+	let chain_spec = match &args.spec {
+		Some(path) => {
+			let spec = serde_json::from_slice::<ChainSpec>(&fs::read(path)?)?;
+			spec.validate(args.chain_id)
+				.map_err(|e| format!("invalid spec {}: {}", path, e))?;
+
+			Some(spec)
+		}
+		None => None,
+	};
+
 	// Start the client
 	let (tx, rx) = async_channel::unbounded();
 	let (tx_resp, rx_resp) = async_channel::unbounded();
@@ -57,15 +129,20 @@ async fn main() -> Result<(), Box<dyn Error>> {
 			.app_data(Data::new(tx.clone()))
 			.app_data(Data::new(rx_resp.clone()))
 			.service(get_head)
+			.service(get_challenge)
 			.service(submit_msg)
+			.service(compose_msg)
 			.service(load_msg)
 			.service(terminate)
 			.service(health_check)
+			.service(get_rejections)
+			.service(get_sync_status)
+			.service(set_snapshot_state)
 	})
 	.bind(("0.0.0.0", args.rpc_port))?
 	.run();
 
-	let mut client = Client::default();
+	let mut client = Client::new(args.chain_id, chain_spec, args.key_path);
 	let client_fut = client.start(
 		rx,
 		tx_resp,
@@ -1,6 +1,6 @@
 use super::req::{Request, Response};
 use libp2p::{
-	floodsub::Floodsub,
+	gossipsub::Behaviour as Gossipsub,
 	identify::Behaviour,
 	kad::{record::store::MemoryStore, Kademlia},
 	ping::Behaviour as PingBehavior,
@@ -9,11 +9,11 @@ use libp2p::{
 use libp2p_autonat::Behaviour as NATBehavior;
 use libp2p_request_response::cbor::Behaviour as RRBehavior;
 
-/// Network behavior combining floodsub, KAD DHT, and identify network behaviors.
+/// Network behavior combining gossipsub, KAD DHT, and identify network behaviors.
 #[derive(NetworkBehaviour)]
 pub struct Behavior {
 	kad: Kademlia<MemoryStore>,
-	floodsub: Floodsub,
+	gossipsub: Gossipsub,
 	identify: Behaviour,
 	rresponse: RRBehavior<Request, Response>,
 	ping: PingBehavior,
@@ -25,7 +25,7 @@ impl Behavior {
 	/// Creates a new network behavior with the given components.
 	pub fn new(
 		kad: Kademlia<MemoryStore>,
-		floodsub: Floodsub,
+		gossipsub: Gossipsub,
 		identify: Behaviour,
 		request_response: RRBehavior<Request, Response>,
 		ping: PingBehavior,
@@ -34,7 +34,7 @@ impl Behavior {
 	) -> Self {
 		Self {
 			kad,
-			floodsub,
+			gossipsub,
 			identify,
 			rresponse: request_response,
 			ping,
@@ -53,14 +53,14 @@ impl Behavior {
 		&mut self.kad
 	}
 
-	/// Gets a reference to the floodsub backing the behavior.
-	pub fn floodsub(&self) -> &Floodsub {
-		&self.floodsub
+	/// Gets a reference to the gossipsub backing the behavior.
+	pub fn gossipsub(&self) -> &Gossipsub {
+		&self.gossipsub
 	}
 
-	/// Gets a mutable reference to the floodsub backing the behavior.
-	pub fn floodsub_mut(&mut self) -> &mut Floodsub {
-		&mut self.floodsub
+	/// Gets a mutable reference to the gossipsub backing the behavior.
+	pub fn gossipsub_mut(&mut self) -> &mut Gossipsub {
+		&mut self.gossipsub
 	}
 
 	/// Gets a reference to the identify network behavior backing the behavior.
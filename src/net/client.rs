@@ -1,17 +1,30 @@
 use super::{
 	super::{
-		rpc::cmd::{Cmd, CmdResp, LoadMsgReq, SubmitMsgReq},
+		rpc::cmd::{
+			Challenge, Cmd, CmdResp, ComposeMsgReq, LoadMsgReq, Rejections, StateReq, SubmitMsgReq,
+		},
 		sys::{
+			fork::{ForkChoice, HighestChain},
 			msg::{Message, MessageData},
-			rt::Rt,
+			rt::{Checkpoint, Reorg, Rt},
+			snapshot::TrustedSnapshot,
+			spec::ChainSpec,
 		},
 		util::nonfatal,
 	},
 	behavior::{Behavior, BehaviorEvent},
-	msg::{ConsensusRule, Context as MsgContext, Event as MsgEvent},
+	chain_namespace, kad_protocol, message_topic,
+	msg::{
+		ConsensusRule, Context as MsgContext, Error as MsgError, Event as MsgEvent, ValidationError,
+	},
+	peers::Reputation,
+	rr_protocol,
 	sync::{Context as SyncContext, Error as SyncError, Event as SyncEvent},
-	DB_NAME, NET_PROTOCOL_PREFIX, RR_PROTOCOL_PREFIX, RUNTIME_STORE, STATE_KEY,
-	SYNCHRONIZATION_INTERVAL,
+	DB_NAME, DHT_RECORD_TTL, GOSSIPSUB_DUPLICATE_CACHE_TIME, GOSSIPSUB_HEARTBEAT_INTERVAL,
+	GOSSIPSUB_INVALID_MESSAGE_WEIGHT, GOSSIPSUB_MESH_N, GOSSIPSUB_MESH_N_HIGH,
+	GOSSIPSUB_MESH_N_LOW, HEAD_DISPUTE_PENALTY, INVALID_HEAD_PENALTY, INVALID_RECORD_PENALTY,
+	INVALID_SNAPSHOT_PENALTY, KEYPAIR_KEY, RUNTIME_STORE, STATE_KEY, SYNCHRONIZATION_INTERVAL,
+	SYNC_TICK_INTERVAL,
 };
 use async_channel::{Receiver, RecvError, Sender};
 use async_trait::async_trait;
@@ -23,11 +36,17 @@ use indexed_db_futures::{
 };
 use libp2p::{
 	core::{transport::Transport, upgrade::Version, ConnectedPoint},
-	floodsub::Floodsub,
 	futures::{Stream, StreamExt},
-	identify::{Behaviour, Config},
+	gossipsub::{
+		Behaviour as Gossipsub, ConfigBuilder as GossipsubConfigBuilder, IdentTopic,
+		Message as GossipsubMessage, MessageAuthenticity, MessageId, PeerScoreParams,
+		PeerScoreThresholds, TopicScoreParams, ValidationMode,
+	},
+	identify::{Behaviour, Config, Event as IdentifyEvent},
 	identity,
-	kad::{record::store::MemoryStore, Kademlia, KademliaConfig, NoKnownPeers},
+	kad::{
+		record::store::MemoryStore, Kademlia, KademliaConfig, KademliaStoreInserts, NoKnownPeers,
+	},
 	multiaddr::{Error as MultiaddrError, Protocol},
 	noise::{Config as NoiseConfig, Error as NoiseError},
 	ping::Behaviour as PingBehavior,
@@ -40,6 +59,7 @@ use libp2p::{
 };
 use libp2p_autonat::{Behaviour as NATBehavior, Config as NATConfig};
 use libp2p_mplex::MplexConfig;
+use rand::seq::SliceRandom;
 use serde_wasm_bindgen::Error as SerdeWasmError;
 
 use instant::Duration;
@@ -59,6 +79,7 @@ use openssl::{error::ErrorStack, pkcs12::Pkcs12};
 use serde::{Deserialize, Serialize};
 use std::{
 	cfg,
+	collections::BTreeMap,
 	error::Error as StdError,
 	fmt::{Debug, Display, Error as FmtError, Formatter},
 	future::Future,
@@ -77,7 +98,7 @@ use web_sys::DomException;
 
 #[cfg(not(target_arch = "wasm32"))]
 use tokio::{
-	fs::File,
+	fs::{self, File, OpenOptions},
 	io::{AsyncReadExt, AsyncWriteExt, Error as TokioError, Result as TokioResult},
 };
 
@@ -246,18 +267,119 @@ impl Display for Error {
 
 /// A client that imposes no extra consensus rules.
 #[derive(Default, Debug)]
-pub struct DefaultClient;
+pub struct DefaultClient {
+	chain_id: usize,
+	chain_spec: Option<ChainSpec>,
+	key_path: Option<String>,
+}
+
+impl DefaultClient {
+	/// Creates a client for the chain with the given ID, following the
+	/// chain spec, if any, and identified by the keypair in the key file,
+	/// if any.
+	pub fn new(chain_id: usize, chain_spec: Option<ChainSpec>, key_path: Option<String>) -> Self {
+		Self {
+			chain_id,
+			chain_spec,
+			key_path,
+		}
+	}
+}
 
 impl NetworkClient for DefaultClient {
-	fn tx_follows_consensus_rules(&self, _rt: &Rt, _msg: &Message) -> bool {
-		true
+	fn tx_follows_consensus_rules(&self, _rt: &Rt, _msg: &Message) -> Result<(), ValidationError> {
+		Ok(())
 	}
 
 	fn chain_id(&self) -> usize {
-		0
+		self.chain_id
+	}
+
+	fn chain_spec(&self) -> Option<ChainSpec> {
+		self.chain_spec.clone()
+	}
+
+	fn key_path(&self) -> Option<String> {
+		self.key_path.clone()
+	}
+}
+
+/// Gets the path of the file in which the keypair identifying a node of the
+/// chain with the given ID is kept by default, beside the runtime database.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn default_key_path(chain_id: usize) -> String {
+	format!("{}_{}.key", DB_NAME, chain_id)
+}
+
+/// Loads the keypair identifying the node from the key file at the path,
+/// such that the node keeps its peer ID across restarts. A new keypair is
+/// generated and saved to the file if it does not exist. Nodes racing to
+/// create the file all end up with the keypair of the first to do so.
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn load_keypair(path: &str) -> TokioResult<identity::Keypair> {
+	match read_keypair(path).await {
+		Err(e) if e.kind() == ErrorKind::NotFound => {}
+		res => return res,
+	}
+
+	let local_key = identity::Keypair::generate_ed25519();
+	let encoded = local_key
+		.to_protobuf_encoding()
+		.map_err(|e| TokioError::new(ErrorKind::InvalidData, e))?;
+
+	// Write the keypair to a temporary file only readable by its owner, such
+	// that the key file never holds a partial keypair
+	let tmp_path = format!("{}.{:016x}.tmp", path, rand::random::<u64>());
+	let mut options = OpenOptions::new();
+	options.write(true).create_new(true);
+	#[cfg(unix)]
+	options.mode(0o600);
+
+	let mut f = options.open(&tmp_path).await?;
+	f.write_all(encoded.as_slice()).await?;
+	f.sync_all().await?;
+
+	// Move the keypair into place, unless another node got there first.
+	// Unlike a rename, linking never replaces an existing key file.
+	let linked = fs::hard_link(&tmp_path, path).await;
+	fs::remove_file(&tmp_path).await?;
+
+	match linked {
+		Ok(_) => {
+			info!("generated a new keypair at {}", path);
+
+			Ok(local_key)
+		}
+		Err(e) if e.kind() == ErrorKind::AlreadyExists => read_keypair(path).await,
+		Err(e) => Err(e),
 	}
 }
 
+// Reads the keypair in the key file at the path
+#[cfg(not(target_arch = "wasm32"))]
+async fn read_keypair(path: &str) -> TokioResult<identity::Keypair> {
+	let contents = fs::read(path).await?;
+
+	identity::Keypair::from_protobuf_encoding(contents.as_slice())
+		.map_err(|e| TokioError::new(ErrorKind::InvalidData, e))
+}
+
+/// Opens the indexeddb database of the chain with the given ID, creating the
+/// object store the runtime and keypair are kept in if it doesn't exist.
+#[cfg(target_arch = "wasm32")]
+async fn open_db(chain_id: usize) -> Result<IdbDatabase, DomException> {
+	let mut db_req = IdbDatabase::open(format!("{}_{}", DB_NAME, chain_id).as_str())?;
+	db_req.set_on_upgrade_needed(Some(|e: &IdbVersionChangeEvent| -> Result<(), JsValue> {
+		if let None = e.db().object_store_names().find(|n| n == RUNTIME_STORE) {
+			e.db().create_object_store(RUNTIME_STORE)?;
+		}
+
+		Ok(())
+	}));
+
+	db_req.into_future().await
+}
+
 /// An interface with the CHUD network.
 #[derive(Serialize, Deserialize)]
 struct Client {
@@ -267,6 +389,14 @@ struct Client {
 	// State variables
 	bootstrapped: bool,
 
+	// The number of messages rejected by consensus, per rejection kind
+	#[serde(skip_serializing, skip_deserializing)]
+	rejections: Rejections,
+
+	// The scores of peers by their behavior
+	#[serde(skip_serializing, skip_deserializing)]
+	reputation: Reputation,
+
 	// Pseudo-network behaviors
 	#[serde(skip_serializing, skip_deserializing)]
 	sync_context: SyncContext,
@@ -282,6 +412,8 @@ impl Client {
 			chain_id,
 			runtime: Rt::default(),
 			bootstrapped: false,
+			rejections: BTreeMap::new(),
+			reputation: Reputation::default(),
 			sync_context: SyncContext::default(),
 			msg_context: MsgContext::default(),
 		}
@@ -293,23 +425,7 @@ impl Client {
 		let mut client = Client::new(chain_id);
 
 		let rec_fut = {
-			// Open the database
-			let db: IdbDatabase = {
-				// Create the object store if it doesn't exist
-				let mut db_req = IdbDatabase::open(format!("{}_{}", DB_NAME, chain_id).as_str())?;
-				db_req.set_on_upgrade_needed(Some(
-					|e: &IdbVersionChangeEvent| -> Result<(), JsValue> {
-						if let None = e.db().object_store_names().find(|n| n == RUNTIME_STORE) {
-							e.db().create_object_store(RUNTIME_STORE)?;
-						}
-
-						Ok(())
-					},
-				));
-
-				db_req.into_future()
-			}
-			.await?;
+			let db = open_db(chain_id).await?;
 
 			// Read the state from the database
 			let tx: IdbTransaction =
@@ -348,21 +464,150 @@ impl Client {
 		Ok(client)
 	}
 
-	/// Writes the blockchain to indexeddb.
+	/// Loads the keypair identifying the node from indexeddb, such that the
+	/// node keeps its peer ID across restarts. A new keypair is generated
+	/// and saved if none is stored.
 	#[cfg(target_arch = "wasm32")]
-	pub async fn write_to_disk(&self) -> Result<(), DomException> {
-		// Create the object store if it doesn't exist
-		let mut db_req = IdbDatabase::open(format!("{}_{}", DB_NAME, self.chain_id).as_str())?;
-		db_req.set_on_upgrade_needed(Some(|e: &IdbVersionChangeEvent| -> Result<(), JsValue> {
-			if let None = e.db().object_store_names().find(|n| n == RUNTIME_STORE) {
-				e.db().create_object_store(RUNTIME_STORE)?;
+	pub async fn load_keypair(&self) -> Result<identity::Keypair, DomException> {
+		let into_dom_exception = |e: String| DomException::from(JsValue::from_str(e.as_str()));
+
+		let db = open_db(self.chain_id).await?;
+
+		// Read the keypair from the database, if there is one
+		let stored = {
+			let tx: IdbTransaction =
+				db.transaction_on_one_with_mode(RUNTIME_STORE, IdbTransactionMode::Readonly)?;
+			let store = tx.object_store(RUNTIME_STORE)?;
+			store.get_owned(KEYPAIR_KEY)?
+		}
+		.await?;
+
+		if let Some(val) = stored {
+			let encoded = serde_wasm_bindgen::from_value::<Vec<u8>>(val)
+				.map_err(|e| into_dom_exception(e.to_string()))?;
+
+			return identity::Keypair::from_protobuf_encoding(encoded.as_slice())
+				.map_err(|e| into_dom_exception(e.to_string()));
+		}
+
+		let local_key = identity::Keypair::generate_ed25519();
+		let encoded = local_key
+			.to_protobuf_encoding()
+			.map_err(|e| into_dom_exception(e.to_string()))?;
+
+		// Write the keypair to the database
+		let tx: IdbTransaction =
+			db.transaction_on_one_with_mode(RUNTIME_STORE, IdbTransactionMode::Readwrite)?;
+		let store = tx.object_store(RUNTIME_STORE)?;
+		store.put_key_val_owned(
+			KEYPAIR_KEY,
+			&serde_wasm_bindgen::to_value(&encoded)
+				.map_err(|e| into_dom_exception(e.to_string()))?,
+		)?;
+
+		Ok(local_key)
+	}
+
+	/// Records a consensus rejection for the given reason.
+	fn record_rejection(&mut self, error: &ValidationError) {
+		*self.rejections.entry(error.kind().to_owned()).or_default() += 1;
+	}
+
+	/// Deducts the penalty from the peer's reputation, disconnecting from
+	/// the peer if it is now banned.
+	fn penalize(&mut self, swarm: &mut Swarm<Behavior>, peer: &PeerId, penalty: i64) {
+		if self.reputation.penalize(peer, penalty) {
+			warn!("banning peer {}", peer);
+
+			let _ = swarm.disconnect_peer_id(*peer);
+		}
+	}
+
+	/// Gets the connected peers that may be sampled for synchronization.
+	fn sampling_pool(&self, swarm: &Swarm<Behavior>) -> Vec<PeerId> {
+		swarm
+			.connected_peers()
+			.filter(|peer| !self.reputation.is_banned(peer))
+			.cloned()
+			.collect()
+	}
+
+	/// Checks the message against consensus and publishes it, recording the
+	/// rejection if it is refused.
+	fn submit_message(
+		&mut self,
+		msg: Message,
+		consensus_rule: ConsensusRule,
+		gossipsub: &mut Gossipsub,
+	) -> Result<(), MsgError> {
+		let hash = msg.hash().clone();
+		let res =
+			self.msg_context
+				.submit_message(&mut self.runtime, consensus_rule, msg, gossipsub);
+
+		if let Err(e) = &res {
+			error!("Failed to submit message {}: {}", hex::encode(&hash), e);
+
+			if let MsgError::ValidationError(error) = e {
+				self.record_rejection(error);
 			}
+		}
+
+		res
+	}
 
-			Ok(())
-		}));
+	/// Handles events emitted by the message context, connecting any buffered
+	/// messages that were waiting on newly received messages, and fetching
+	/// the missing predecessors of newly buffered messages.
+	fn handle_msg_events(
+		&mut self,
+		mut events: Vec<MsgEvent>,
+		consensus_rule: ConsensusRule,
+		behavior: &mut Behavior,
+	) -> Result<(), Error> {
+		while let Some(event) = events.pop() {
+			match event {
+				MsgEvent::MessageReceived(h) => {
+					info!("Message received: {}", hex::encode(&h));
+
+					events.extend(self.msg_context.connect_orphans(
+						&mut self.runtime,
+						consensus_rule,
+						&h,
+						behavior.gossipsub_mut(),
+					));
+				}
+				MsgEvent::MessageRejected { hash, error } => {
+					warn!("Message {} rejected: {}", hex::encode(hash), error);
+
+					self.record_rejection(&error);
+				}
+				MsgEvent::MessageOrphaned { hash, prev, height } => {
+					info!(
+						"Message {} is waiting on missing message {}",
+						hex::encode(hash),
+						hex::encode(&prev)
+					);
+
+					// The missing message may itself be waiting on its
+					// ancestors, whose download is already underway
+					if self.msg_context.is_orphaned(&prev) {
+						continue;
+					}
 
-		// Open the database
-		let db: IdbDatabase = db_req.into_future().await?;
+					self.sync_context
+						.download_msg(&prev, height, behavior.kad_mut())?;
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Writes the blockchain to indexeddb.
+	#[cfg(target_arch = "wasm32")]
+	pub async fn write_to_disk(&self) -> Result<(), DomException> {
+		let db = open_db(self.chain_id).await?;
 
 		// Write the state to the database
 		let tx: IdbTransaction =
@@ -391,11 +636,64 @@ impl Client {
 		Ok(())
 	}
 
+	/// Creates the gossipsub behavior through which new messages are
+	/// propagated, subscribed to the messages topic. Received messages are
+	/// only forwarded once validated by the message context.
+	fn build_gossipsub(&self, local_key: &identity::Keypair) -> Result<Gossipsub, Error> {
+		let topic = IdentTopic::new(message_topic(self.chain_id));
+
+		// Messages are identified by their contents, such that copies
+		// received from different peers are deduplicated
+		let config = GossipsubConfigBuilder::default()
+			.heartbeat_interval(Duration::from_millis(GOSSIPSUB_HEARTBEAT_INTERVAL))
+			.mesh_n(GOSSIPSUB_MESH_N)
+			.mesh_n_low(GOSSIPSUB_MESH_N_LOW)
+			.mesh_n_high(GOSSIPSUB_MESH_N_HIGH)
+			.duplicate_cache_time(Duration::from_millis(GOSSIPSUB_DUPLICATE_CACHE_TIME))
+			.validation_mode(ValidationMode::Strict)
+			.validate_messages()
+			.message_id_fn(|msg: &GossipsubMessage| {
+				MessageId::from(blake3::hash(&msg.data).as_bytes().to_vec())
+			})
+			.build()
+			.map_err(|e| IoError::new(ErrorKind::Other, e))?;
+
+		let mut gossipsub = Gossipsub::new(MessageAuthenticity::Signed(local_key.clone()), config)
+			.map_err(|e| IoError::new(ErrorKind::Other, e))?;
+
+		// Peers are scored by the validity of the messages they forward.
+		// Messages are too infrequent to expect mesh peers to deliver any, and
+		// browser peers commonly share addresses, so neither is penalized.
+		let mut params = PeerScoreParams::default();
+		params.ip_colocation_factor_weight = 0.0;
+		params.topics.insert(
+			topic.hash(),
+			TopicScoreParams {
+				invalid_message_deliveries_weight: GOSSIPSUB_INVALID_MESSAGE_WEIGHT,
+				mesh_message_deliveries_weight: 0.0,
+				mesh_failure_penalty_weight: 0.0,
+				..Default::default()
+			},
+		);
+		gossipsub
+			.with_peer_score(params, PeerScoreThresholds::default())
+			.map_err(|e| IoError::new(ErrorKind::Other, e))?;
+
+		gossipsub
+			.subscribe(&topic)
+			.map_err(|e| IoError::new(ErrorKind::Other, e))?;
+
+		Ok(gossipsub)
+	}
+
 	#[cfg(target_arch = "wasm32")]
-	fn build_swarm(&self, cert_path: Option<String>) -> Result<Swarm<Behavior>, Error> {
+	fn build_swarm(
+		&self,
+		cert_path: Option<String>,
+		local_key: identity::Keypair,
+	) -> Result<Swarm<Behavior>, Error> {
 		// Use WebSockets as a transport.
 		// TODO: Use webrtc in the future for p2p in browsers
-		let local_key = identity::Keypair::generate_ed25519();
 		let local_peer_id = PeerId::from(local_key.public());
 
 		let transport = WebsocketTransport::default()
@@ -409,15 +707,22 @@ impl Client {
 			let store = MemoryStore::new(local_peer_id);
 			let mut kad_conf = KademliaConfig::default();
 			kad_conf.set_max_packet_size(30 * 1024);
+			kad_conf.set_protocol_names(vec![stream_protocol(kad_protocol(self.chain_id))?]);
+			kad_conf.set_record_filtering(KademliaStoreInserts::FilterBoth);
+
+			// Messages are republished by the sync context, which tracks
+			// their publication
+			kad_conf.set_record_ttl(Some(Duration::from_millis(DHT_RECORD_TTL)));
+			kad_conf.set_publication_interval(None);
 			let kad = Kademlia::with_config(local_peer_id, store, kad_conf);
-			let floodsub = Floodsub::new(local_peer_id);
+			let gossipsub = self.build_gossipsub(&local_key)?;
 			let identify = Behaviour::new(Config::new(
-				format!("{}{}", NET_PROTOCOL_PREFIX, self.chain_id),
+				chain_namespace(self.chain_id),
 				local_key.public(),
 			));
 			let rresponse = RRBehavior::new(
 				[(
-					StreamProtocol::new(RR_PROTOCOL_PREFIX),
+					stream_protocol(rr_protocol(self.chain_id))?,
 					ProtocolSupport::Full,
 				)],
 				RRConfig::default(),
@@ -429,7 +734,7 @@ impl Client {
 			Ok(SwarmBuilder::with_wasm_executor(
 				transport,
 				Behavior::new(
-					kad, floodsub, identify, rresponse, ping, keep_alive, autonat,
+					kad, gossipsub, identify, rresponse, ping, keep_alive, autonat,
 				),
 				local_peer_id,
 			)
@@ -438,10 +743,13 @@ impl Client {
 	}
 
 	#[cfg(not(target_arch = "wasm32"))]
-	fn build_swarm(&self, cert_path: Option<String>) -> Result<Swarm<Behavior>, Error> {
+	fn build_swarm(
+		&self,
+		cert_path: Option<String>,
+		local_key: identity::Keypair,
+	) -> Result<Swarm<Behavior>, Error> {
 		// Use WebSockets as a transport.
 		// TODO: Use webrtc in the future for p2p in browsers
-		let local_key = identity::Keypair::generate_ed25519();
 		let local_peer_id = PeerId::from(local_key.public());
 
 		let mut conf = WsConfig::new(TokioDnsConfig::system(TcpTransport::new(TcpConfig::new()))?);
@@ -474,15 +782,22 @@ impl Client {
 			let store = MemoryStore::new(local_peer_id);
 			let mut kad_conf = KademliaConfig::default();
 			kad_conf.set_max_packet_size(30 * 1024);
+			kad_conf.set_protocol_names(vec![stream_protocol(kad_protocol(self.chain_id))?]);
+			kad_conf.set_record_filtering(KademliaStoreInserts::FilterBoth);
+
+			// Messages are republished by the sync context, which tracks
+			// their publication
+			kad_conf.set_record_ttl(Some(Duration::from_millis(DHT_RECORD_TTL)));
+			kad_conf.set_publication_interval(None);
 			let kad = Kademlia::with_config(local_peer_id, store, kad_conf);
-			let floodsub = Floodsub::new(local_peer_id);
+			let gossipsub = self.build_gossipsub(&local_key)?;
 			let identify = Behaviour::new(Config::new(
-				format!("{}{}", NET_PROTOCOL_PREFIX, self.chain_id),
+				chain_namespace(self.chain_id),
 				local_key.public(),
 			));
 			let rresponse = RRBehavior::new(
 				[(
-					StreamProtocol::new(RR_PROTOCOL_PREFIX),
+					stream_protocol(rr_protocol(self.chain_id))?,
 					ProtocolSupport::Full,
 				)],
 				RRConfig::default(),
@@ -494,7 +809,7 @@ impl Client {
 			Ok(SwarmBuilder::with_tokio_executor(
 				transport,
 				Behavior::new(
-					kad, floodsub, identify, rresponse, ping, keep_alive, autonat,
+					kad, gossipsub, identify, rresponse, ping, keep_alive, autonat,
 				),
 				local_peer_id,
 			)
@@ -503,6 +818,12 @@ impl Client {
 	}
 }
 
+// Parses the name of a protocol negotiated with peers
+fn stream_protocol(name: String) -> Result<StreamProtocol, Error> {
+	StreamProtocol::try_from_owned(name)
+		.map_err(|e| IoError::new(ErrorKind::InvalidInput, e).into())
+}
+
 /// A client for a CHUD blockchain network.
 pub trait NetworkClient: Send + Sync + Sized + 'static {
 	/// Used for determining whether a transaction follows consensus rules.
@@ -512,6 +833,9 @@ pub trait NetworkClient: Send + Sync + Sized + 'static {
 	/// - That the transaction's chosen captcha conforms to the lookback consensus algorithm
 	/// - That the hash of the transaction is valid
 	///
+	/// Rejections should be reported through [`ValidationError::RuleFailed`]
+	/// with a human-readable reason.
+	///
 	/// # Arguments
 	///
 	/// * `msg` - A referene to the message to be verified.
@@ -520,7 +844,7 @@ pub trait NetworkClient: Send + Sync + Sized + 'static {
 	/// # Examples
 	///
 	/// ```
-	/// use chud::{net::client::NetworkClient, sys::{rt::Rt, msg::Message}};
+	/// use chud::{net::{client::NetworkClient, msg::ValidationError}, sys::{rt::Rt, msg::Message}};
 	/// use serde::Deserialize;
 	///
 	/// #[derive(Deserialize)]
@@ -531,8 +855,11 @@ pub trait NetworkClient: Send + Sync + Sized + 'static {
 	/// struct MyChainClient;
 	///
 	/// impl NetworkClient for MyChainClient {
-	///     fn tx_follows_consensus_rules(&self, rt: &Rt, msg: &Message) -> bool {
-	///         msg.as_data().map(|msg: MyMessage| msg.flag).unwrap_or_default()
+	///     fn tx_follows_consensus_rules(&self, rt: &Rt, msg: &Message) -> Result<(), ValidationError> {
+	///         match msg.as_data() {
+	///             Ok(MyMessage { flag: true }) => Ok(()),
+	///             _ => Err(ValidationError::RuleFailed("the flag must be set".into())),
+	///         }
 	///     }
 	///
 	///     fn chain_id(&self) -> usize {
@@ -540,7 +867,7 @@ pub trait NetworkClient: Send + Sync + Sized + 'static {
 	///     }
 	/// }
 	/// ```
-	fn tx_follows_consensus_rules(&self, rt: &Rt, msg: &Message) -> bool;
+	fn tx_follows_consensus_rules(&self, rt: &Rt, msg: &Message) -> Result<(), ValidationError>;
 
 	/// A unique identifier for the blockchain differentiating it from other chains.
 	/// Currently used chain ID's include:
@@ -553,6 +880,60 @@ pub trait NetworkClient: Send + Sync + Sized + 'static {
 	/// existing network.
 	fn chain_id(&self) -> usize;
 
+	/// The parameters of the chain every node must agree on: its genesis
+	/// message, captcha parameters, consensus constants, and checkpoints.
+	/// Only chains rooted at the genesis message are accepted. Without a
+	/// spec, any message with no previous message may root a chain, which
+	/// is only suitable for testing. Defaults to no spec. See [`ChainSpec`].
+	fn chain_spec(&self) -> Option<ChainSpec> {
+		None
+	}
+
+	/// The rule used to choose the canonical chain among competing forks,
+	/// both locally and among the HEADs reported by peers. Defaults to
+	/// [`HighestChain`]. See [`ForkChoice`] for more.
+	fn fork_choice(&self) -> Box<dyn ForkChoice> {
+		Box::new(HighestChain)
+	}
+
+	/// Messages which every valid chain must include, hard-coded or
+	/// configured per chain. Forks contradicting a checkpoint are never
+	/// accepted. Defaults to no checkpoints.
+	fn checkpoints(&self) -> Vec<Checkpoint> {
+		Vec::new()
+	}
+
+	/// The maximum number of messages a reorg may detach from the longest
+	/// chain, protecting history from peers reporting long alternative
+	/// chains. Defaults to no limit.
+	fn max_reorg_depth(&self) -> Option<usize> {
+		None
+	}
+
+	/// A snapshot of the chain trusted by the client, from which it begins
+	/// synchronizing instead of downloading the chain from its first
+	/// message. The snapshot is also treated as a checkpoint. Defaults to
+	/// no snapshot.
+	fn trusted_snapshot(&self) -> Option<TrustedSnapshot> {
+		None
+	}
+
+	/// The path of the file holding the keypair which identifies the node,
+	/// and from which its peer ID is derived. A new keypair is generated and
+	/// saved there if the file does not exist. Only used by native builds;
+	/// browsers keep the keypair in indexeddb. Defaults to a file beside the
+	/// runtime database.
+	fn key_path(&self) -> Option<String> {
+		None
+	}
+
+	/// Whether the client runs as a light client, which follows the chain
+	/// by its headers and only downloads the payloads of messages it is
+	/// asked to load. Defaults to false.
+	fn light_client(&self) -> bool {
+		false
+	}
+
 	/// Synchronizes and keeps the client in sync with the network. Accepts
 	/// commands on a receiving channel for operations to perform.
 	/// Available commands are listed in the [`Cmd`] enum. Responses are
@@ -585,13 +966,13 @@ pub trait NetworkClient: Send + Sync + Sized + 'static {
 	/// # Examples
 	///
 	/// ```
-	/// use chud::{net::client::NetworkClient, sys::{rt::Rt, msg::Message}, rpc::cmd::Cmd};
+	/// use chud::{net::{client::NetworkClient, msg::ValidationError}, sys::{rt::Rt, msg::Message}, rpc::cmd::Cmd};
 	///
 	/// struct MyChainClient;
 	///
 	/// impl NetworkClient for MyChainClient {
-	///     fn tx_follows_consensus_rules(&self, _rt: &Rt, _msg: &Message) -> bool {
-	///         true
+	///     fn tx_follows_consensus_rules(&self, _rt: &Rt, _msg: &Message) -> Result<(), ValidationError> {
+	///         Ok(())
 	///     }
 	///
 	///     fn chain_id(&self) -> usize {
@@ -632,9 +1013,42 @@ pub trait NetworkClient: Send + Sync + Sized + 'static {
 
 		Box::pin(async move {
 			let mut client = Client::load_from_disk(self.chain_id()).await?;
+			client.sync_context.set_chain_id(client.chain_id);
+			client.msg_context.set_chain_id(client.chain_id);
+			client.runtime.set_fork_choice(self.fork_choice());
+			let chain_spec = self.chain_spec();
+			let trusted_snapshot = self.trusted_snapshot();
+			client.runtime.set_checkpoints(
+				self.checkpoints()
+					.into_iter()
+					.chain(chain_spec.iter().flat_map(|spec| spec.checkpoints.clone()))
+					.chain(trusted_snapshot.as_ref().map(Checkpoint::from)),
+			);
+			client.runtime.set_max_reorg_depth(self.max_reorg_depth());
+			client.runtime.set_light(self.light_client());
+			client.runtime.set_chain_id(client.chain_id);
+			if let Some(chain_spec) = &chain_spec {
+				client.runtime.set_spec(chain_spec);
+			}
+			client
+				.sync_context
+				.set_trusted_snapshot(&client.runtime, trusted_snapshot);
+			let consensus_rule = |rt: &Rt, msg: &Message| self.tx_follows_consensus_rules(rt, msg);
 
 			let is_secure = cert_path.is_some();
-			let mut swarm = client.build_swarm(cert_path)?;
+			// Keep the same peer ID across restarts
+			#[cfg(target_arch = "wasm32")]
+			let local_key = client.load_keypair().await?;
+			#[cfg(not(target_arch = "wasm32"))]
+			let local_key = load_keypair(
+				&self
+					.key_path()
+					.unwrap_or_else(|| default_key_path(client.chain_id)),
+			)
+			.await?;
+			info!("local peer ID is {}", PeerId::from(local_key.public()));
+
+			let mut swarm = client.build_swarm(cert_path, local_key)?;
 
 			for external_addr in external_addresses {
 				swarm.add_external_address(external_addr);
@@ -667,39 +1081,100 @@ pub trait NetworkClient: Send + Sync + Sized + 'static {
 				info!("p2p client listening on {}", address);
 			}
 
-			// Write all transactions to the DHT and synchronize the chain
+			// Publish new messages to the DHT and synchronize the chain
 			// every n minutes
 			let mut sync_fut =
 				Interval::new(Duration::from_millis(SYNCHRONIZATION_INTERVAL)).fuse();
 
+			// Expire, retry, and report on synchronization rounds more often
+			let mut tick_fut = Interval::new(Duration::from_millis(SYNC_TICK_INTERVAL)).fuse();
+			let mut sync_status = client.sync_context.status(&client.runtime);
+
 			loop {
 				select! {
 					event = swarm.select_next_some() => {
 						match event {
 						SwarmEvent::Behaviour(event) => {
+							// Drop peers of other chains as soon as they identify themselves
+							if let BehaviorEvent::Identify(IdentifyEvent::Received { peer_id, info }) = &event {
+								if info.protocol_version != chain_namespace(client.chain_id) {
+									warn!("disconnecting from peer {} of chain {}", peer_id, info.protocol_version);
+
+									let _ = swarm.disconnect_peer_id(*peer_id);
+
+									continue;
+								}
+							}
+
 							// Check if the sync context has something to say about this
-							let (out_event, in_event) = client.sync_context.poll(&mut client.runtime, swarm.behaviour_mut().request_response_mut(), Some(event));
-							match out_event {
-								Ok(Some(e)) => match e {
+							let (out_events, in_event) = client.sync_context.poll(&mut client.runtime, &consensus_rule, swarm.behaviour_mut(), Some(event));
+							match out_events {
+								Ok(events) => for e in events { match e {
 									SyncEvent::MessageCommitted(h) => {
-										info!("message {} successfully committed to the DHT", hex::encode(h));
+										debug!("message {} successfully committed to the DHT", hex::encode(h));
+									},
+									SyncEvent::MessagePublishFailed { hash, failures } => {
+										warn!("failed to commit message {} to the DHT ({} attempts)", hex::encode(hash), failures);
+									},
+									SyncEvent::LongestChainUpdated { hash, height } => {
+										// The headers of the chain are downloaded first, and its payloads as they connect
+										info!("got new longest chain {} at height {}", hex::encode(&hash), height);
+									},
+									SyncEvent::HeadDisputed { peer, hash } => {
+										warn!("peer {} claimed false HEAD {} above the agreed HEAD", peer, hex::encode(&hash));
+
+										client.penalize(&mut swarm, &peer, HEAD_DISPUTE_PENALTY);
 									},
-									SyncEvent::LongestChainUpdated { hash, .. } => {
-										info!("got new longest chain {}", hex::encode(&hash));
+									SyncEvent::HeadClaimRejected { peer, hash } => {
+										warn!("peer {} claimed unverifiable HEAD {}", peer, hex::encode(&hash));
 
-										client.sync_context.download_msg(&hash, swarm.behaviour_mut().kad_mut())?;
+										client.penalize(&mut swarm, &peer, INVALID_HEAD_PENALTY);
 									},
 									SyncEvent::MessageLoaded(msg) => {
 										info!("message {} loaded", hex::encode(msg.hash()));
 
-										// Download the message if it doesn't exist locally
-										if let Some(prev) = msg.data().prev() {
-											if client.runtime.get_message(prev).is_none() {
-												client.sync_context.download_msg(&prev, swarm.behaviour_mut().kad_mut())?;
-											}
+										// Connect any messages that were waiting on this one
+										let events = client.msg_context.connect_orphans(&mut client.runtime, &consensus_rule, msg.hash(), swarm.behaviour_mut().gossipsub_mut());
+										client.handle_msg_events(events, &consensus_rule, swarm.behaviour_mut())?;
+									},
+									SyncEvent::HeaderLoaded(header) => {
+										debug!("header {} loaded at height {}", hex::encode(header.hash()), header.height());
+									},
+									SyncEvent::MessagePending { hash, prev, height } => {
+										info!("message {} is waiting on missing message {}", hex::encode(hash), hex::encode(&prev));
+
+										// Keep walking the chain until it connects to a known message
+										let sampling_pool = client.sampling_pool(&swarm);
+										client.sync_context.download_ancestors(&prev, height, swarm.behaviour_mut(), sampling_pool.iter().collect())?;
+									},
+									SyncEvent::MessageRejected { hash, error } => {
+										warn!("downloaded message {} rejected: {}", hex::encode(hash), error);
+
+										client.record_rejection(&error);
+									},
+									SyncEvent::RecordRejected { peer, error } => {
+										warn!("rejected DHT record: {}", error);
+
+										if let Some(peer) = peer {
+											client.penalize(&mut swarm, &peer, INVALID_RECORD_PENALTY);
+										}
+									},
+									SyncEvent::SnapshotLoaded { height, hash, state } => {
+										info!("restored snapshot {} at height {}", hex::encode(&hash), height);
+
+										if let Err(e) = resp_tx.send(CmdResp::SnapshotLoaded { height, hash, state }).await {
+											error!("Failed to report snapshot: {}", e);
 										}
 									},
+									SyncEvent::SnapshotRejected { peer, error } => {
+										warn!("peer {} served an invalid snapshot: {}", peer, error);
+
+										client.penalize(&mut swarm, &peer, INVALID_SNAPSHOT_PENALTY);
+									},
 									SyncEvent::MessageLoadCompleted{ msg, req_id } => {
+										let events = client.msg_context.connect_orphans(&mut client.runtime, &consensus_rule, msg.hash(), swarm.behaviour_mut().gossipsub_mut());
+										client.handle_msg_events(events, &consensus_rule, swarm.behaviour_mut())?;
+
 										nonfatal!(resp_tx.send(CmdResp::MsgLoaded { msg, req_id }).await, req_id, resp_tx);
 									},
 									SyncEvent::MessageLoadFailed { req_id } => {
@@ -707,25 +1182,27 @@ pub trait NetworkClient: Send + Sync + Sized + 'static {
 
 										nonfatal!(resp_tx.send(CmdResp::Error { error: "Failed to load the message.".into(), req_id}).await, req_id, resp_tx);
 									}
-								},
+								}},
 								Err(e) => error!("synchronization failed: {}", e),
-								_ => {},
 							}
 
 							// Check if the message context has something to say about this
-							let (out_event, _) = client.msg_context.poll(&mut client.runtime, &(|rt, msg| self.tx_follows_consensus_rules(rt, msg)), swarm.behaviour_mut().floodsub_mut(), in_event);
+							let (out_event, _) = client.msg_context.poll(&mut client.runtime, &consensus_rule, swarm.behaviour_mut().gossipsub_mut(), in_event);
 							match out_event {
-								Ok(Some(e)) => match e {
-									MsgEvent::MessageReceived(h) => {
-										info!("Message received: {}", hex::encode(h));
-									}
-								},
+								Ok(Some(e)) => client.handle_msg_events(vec![e], &consensus_rule, swarm.behaviour_mut())?,
 								Err(e) => error!("message handling failed: {}", e),
 								_ => {},
 							}
 						},
 						SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
-							// Register peers in the kademlia DHT and floodsub once they're found
+							// Refuse peers that have been banned
+							if client.reputation.is_banned(&peer_id) {
+								let _ = swarm.disconnect_peer_id(peer_id);
+
+								continue;
+							}
+
+							// Register peers in the kademlia DHT once they're found
 							match endpoint {
 								ConnectedPoint::Dialer {
 									address, ..
@@ -733,9 +1210,15 @@ pub trait NetworkClient: Send + Sync + Sized + 'static {
 									swarm.behaviour_mut().kad_mut().add_address(&peer_id, address.clone());
 									swarm.behaviour_mut().request_response_mut().add_address(&peer_id, address.clone());
 
+									// Start from the trusted snapshot if we have yet to restore it
+									client.sync_context.download_snapshot(&client.runtime, swarm.behaviour_mut().request_response_mut(), &peer_id);
+
+									// Catch up on whatever we missed while apart from the peer
+									client.sync_context.reconcile(&client.runtime, swarm.behaviour_mut().request_response_mut(), &peer_id);
+
 									// Bootstrap the DHT if we connected to one of the bootstrap addresses
 									if !client.bootstrapped && bootstrap_peers.contains(&address.to_string().into()) {
-										let sampling_pool = swarm.connected_peers().map(|x| x.clone()).collect::<Vec<PeerId>>();
+										let sampling_pool = client.sampling_pool(&swarm);
 
 										swarm.behaviour_mut().kad_mut().bootstrap().map_err(<NoKnownPeers as Into<Error>>::into)?;
 										if let Err(e) = client
@@ -745,12 +1228,9 @@ pub trait NetworkClient: Send + Sync + Sized + 'static {
 											error!("Failed to download chain: {}", e);
 										};
 
-										if let Err(e) = client
+										client
 											.sync_context
-											.upload_chain(&client.runtime, swarm.behaviour_mut().kad_mut())
-										{
-											error!("Failed to upload chain: {}", e);
-										};
+											.publish_chain(&client.runtime, swarm.behaviour_mut().kad_mut());
 
 										client.bootstrapped = true;
 
@@ -760,13 +1240,10 @@ pub trait NetworkClient: Send + Sync + Sized + 'static {
 								},
 								_ => {}
 							}
-
-							swarm.behaviour_mut().floodsub_mut().add_node_to_partial_view(peer_id);
 						},
 						SwarmEvent::ConnectionClosed { peer_id, endpoint, .. } => {
 							// Remove disconnected peers
 							swarm.behaviour_mut().kad_mut().remove_peer(&peer_id);
-							swarm.behaviour_mut().floodsub_mut().remove_node_from_partial_view(&peer_id);
 
 							// Remove the request-response peer
 							if let ConnectedPoint::Dialer { address, .. } = endpoint {
@@ -778,14 +1255,17 @@ pub trait NetworkClient: Send + Sync + Sized + 'static {
 					cmd = cmd_rx.select_next_some() => match cmd {
 						Cmd::Terminate => break Ok(()),
 						Cmd::SubmitMsg{ req: SubmitMsgReq{ data, prev, captcha_ans,captcha_src, height, timestamp}, req_id } => {
-							let msg = nonfatal!(Message::try_from(MessageData::new(data, prev, captcha_ans, captcha_src, height, timestamp)), req_id, resp_tx);
+							let data = MessageData::builder(data).prev(prev).captcha_ans(captcha_ans).captcha_src(captcha_src).height(height).timestamp(timestamp).chain_id(client.chain_id).captcha(client.runtime.captcha_params().clone()).build();
+							let msg = nonfatal!(Message::try_from(data), req_id, resp_tx);
 							let hash = msg.hash().clone();
-							match client.msg_context.submit_message(&mut client.runtime, msg, swarm.behaviour_mut().floodsub_mut()) {
-								Ok(_) => {
-									nonfatal!(resp_tx.send(CmdResp::MsgSubmitted{ hash, req_id }).await, req_id, resp_tx);
-								},
-								Err(e) => error!("Failed to submit message {}: {}", hex::encode(hash), e),
-							}
+							nonfatal!(client.submit_message(msg, &consensus_rule, swarm.behaviour_mut().gossipsub_mut()), req_id, resp_tx);
+							nonfatal!(resp_tx.send(CmdResp::MsgSubmitted{ hash, req_id }).await, req_id, resp_tx);
+						},
+						Cmd::ComposeAndSubmit { req: ComposeMsgReq { data, captcha_ans }, req_id } => {
+							let msg = nonfatal!(Message::try_from(MessageData::builder(data).with_chain(&client.runtime).captcha_ans(captcha_ans).build()), req_id, resp_tx);
+							let hash = msg.hash().clone();
+							nonfatal!(client.submit_message(msg, &consensus_rule, swarm.behaviour_mut().gossipsub_mut()), req_id, resp_tx);
+							nonfatal!(resp_tx.send(CmdResp::MsgSubmitted{ hash, req_id }).await, req_id, resp_tx);
 						},
 						Cmd::LoadMsg { req: LoadMsgReq { hash }, req_id } => {
 							// If the message exists locally, just use that
@@ -805,17 +1285,95 @@ pub trait NetworkClient: Send + Sync + Sized + 'static {
 
 							nonfatal!(resp_tx.send(CmdResp::Error{ error: String::from("Missing chain HEAD."), req_id}).await, req_id, resp_tx);
 						},
+						Cmd::GetChallenge { req_id } => {
+							nonfatal!(resp_tx.send(CmdResp::Challenge { challenge: Challenge::from(&client.runtime), req_id }).await, req_id, resp_tx);
+						},
+						Cmd::GetRejections { req_id } => {
+							nonfatal!(resp_tx.send(CmdResp::RejectionsLoaded { counts: client.rejections.clone(), req_id }).await, req_id, resp_tx);
+						},
+						Cmd::SetSnapshotState { req: StateReq { hash, state }, req_id } => {
+							client.sync_context.set_snapshot_state(hash, state);
+							nonfatal!(resp_tx.send(CmdResp::SnapshotStateSet { req_id }).await, req_id, resp_tx);
+						},
+						Cmd::GetSyncStatus { req_id } => {
+							nonfatal!(resp_tx.send(CmdResp::SyncStatus { status: client.sync_context.status(&client.runtime), req_id }).await, req_id, resp_tx);
+						},
 						Cmd::Flush { req_id } => {
 							client.runtime.flush();
 							nonfatal!(resp_tx.send(CmdResp::RuntimeFlushed { req_id }).await, req_id, resp_tx);
 						}
 					},
 					_ = sync_fut.next() => {
-						if let Err(e) = client.sync_context.upload_chain(&client.runtime, swarm.behaviour_mut().kad_mut()) {
-							error!("Failed to upload chain: {}", e);
+						client.sync_context.publish_chain(&client.runtime, swarm.behaviour_mut().kad_mut());
+
+						// Catch up with any HEAD our peers have since agreed on
+						if client.bootstrapped {
+							let sampling_pool = client.sampling_pool(&swarm);
+
+							if let Err(e) = client
+								.sync_context
+								.download_head(swarm.behaviour_mut().request_response_mut(), sampling_pool.iter().collect::<Vec<&PeerId>>())
+							{
+								error!("Failed to download chain: {}", e);
+							}
+
+							// Exchange any messages outside the longest chain with a random peer
+							if let Some(peer) = sampling_pool.choose(&mut rand::thread_rng()) {
+								client.sync_context.reconcile(&client.runtime, swarm.behaviour_mut().request_response_mut(), peer);
+							}
+						}
+					}
+					_ = tick_fut.next() => {
+						let sampling_pool = client.sampling_pool(&swarm);
+
+						if let Err(e) = client
+							.sync_context
+							.tick(&client.runtime, swarm.behaviour_mut().request_response_mut(), sampling_pool.iter().collect::<Vec<&PeerId>>())
+						{
+							error!("Failed to advance synchronization: {}", e);
+						}
+
+						// Stop holding on to gossiped orphans whose parents never arrived
+						client.msg_context.prune_orphans(swarm.behaviour_mut().gossipsub_mut());
+
+						// Notify the application of any progress made
+						let status = client.sync_context.status(&client.runtime);
+						if status != sync_status {
+							sync_status = status.clone();
+
+							if let Err(e) = resp_tx.send(CmdResp::SyncProgress { status }).await {
+								error!("Failed to report synchronization progress: {}", e);
+							}
 						}
 					}
 				}
+
+				// Notify the application of any forks the chain switched to
+				for Reorg {
+					old_head,
+					new_head,
+					detached,
+					attached,
+				} in client.runtime.take_reorgs()
+				{
+					info!(
+						"chain reorganized from {} to {}",
+						hex::encode(&old_head),
+						hex::encode(&new_head)
+					);
+
+					if let Err(e) = resp_tx
+						.send(CmdResp::Reorg {
+							old_head,
+							new_head,
+							detached,
+							attached,
+						})
+						.await
+					{
+						error!("Failed to report reorg: {}", e);
+					}
+				}
 			}
 		})
 	}
@@ -823,7 +1381,8 @@ pub trait NetworkClient: Send + Sync + Sized + 'static {
 
 #[cfg(test)]
 mod tests {
-	use super::*;
+	use super::{super::super::sys::fork::Head, *};
+	use std::cmp::Ordering;
 
 	#[cfg(not(target_arch = "wasm32"))]
 	use tokio::task::JoinError;
@@ -834,6 +1393,66 @@ mod tests {
 		assert_eq!(client.chain_id, 0);
 	}
 
+	#[test]
+	fn test_record_rejection() {
+		let mut client = Client::new(0);
+		client.record_rejection(&ValidationError::InvalidHeight);
+		client.record_rejection(&ValidationError::RuleFailed("spam".into()));
+		client.record_rejection(&ValidationError::InvalidHeight);
+		client.record_rejection(&ValidationError::RuleFailed("too long".into()));
+
+		// Rejections are counted per kind, as served by GetRejections
+		assert_eq!(
+			client.rejections,
+			Rejections::from([
+				(String::from("invalid_height"), 2),
+				(String::from("rule_failed"), 2),
+			])
+		);
+	}
+
+	// Builds a client with a key file of its own under the temporary
+	// directory, such that tests running at once do not share one
+	#[cfg(not(target_arch = "wasm32"))]
+	fn client_with_key(name: &str) -> DefaultClient {
+		let key_path =
+			std::env::temp_dir().join(format!("chud_test_{}_{}.key", name, std::process::id()));
+
+		DefaultClient::new(0, None, Some(key_path.to_string_lossy().into_owned()))
+	}
+
+	#[cfg(not(target_arch = "wasm32"))]
+	#[tokio::test]
+	async fn test_load_keypair() -> Result<(), Box<dyn StdError>> {
+		let path = std::env::temp_dir().join(format!("chud_test_{}.key", std::process::id()));
+		let path = path.to_str().ok_or("invalid key path")?;
+		let _ = std::fs::remove_file(path);
+
+		// The node keeps its identity across restarts
+		let generated = load_keypair(path).await?;
+		let loaded = load_keypair(path).await?;
+		assert_eq!(
+			PeerId::from(generated.public()),
+			PeerId::from(loaded.public())
+		);
+
+		// Nodes racing to create the key file agree on its keypair
+		std::fs::remove_file(path)?;
+		let (first, second) = tokio::join!(load_keypair(path), load_keypair(path));
+		assert_eq!(
+			PeerId::from(first?.public()),
+			PeerId::from(second?.public())
+		);
+
+		// Corrupted key files are refused rather than replaced
+		std::fs::write(path, [0; 8])?;
+		assert!(load_keypair(path).await.is_err());
+
+		std::fs::remove_file(path)?;
+
+		Ok(())
+	}
+
 	#[cfg(not(target_arch = "wasm32"))]
 	#[tokio::test]
 	async fn test_start() -> Result<(), Box<dyn StdError>> {
@@ -841,7 +1460,7 @@ mod tests {
 		let (tx_resp, _) = async_channel::unbounded();
 		tx.send(Cmd::Terminate).await?;
 
-		let client = DefaultClient::default();
+		let client = client_with_key("start");
 		client
 			.start(
 				rx,
@@ -865,7 +1484,7 @@ mod tests {
 		let local = tokio::task::LocalSet::new();
 		local
 			.run_until(async {
-				let client = DefaultClient::default();
+				let client = client_with_key("submit_message");
 				let join = tokio::task::spawn_local(async move {
 					client
 						.start(
@@ -904,6 +1523,312 @@ mod tests {
 			.await
 	}
 
+	#[cfg(not(target_arch = "wasm32"))]
+	#[tokio::test]
+	async fn test_submit_invalid_message() -> Result<(), Box<dyn StdError>> {
+		let (tx, rx) = async_channel::unbounded();
+		let (tx_resp, rx_resp) = async_channel::unbounded();
+
+		let local = tokio::task::LocalSet::new();
+		local
+			.run_until(async {
+				let client = client_with_key("submit_invalid_message");
+				let join = tokio::task::spawn_local(async move {
+					client
+						.start(
+							rx,
+							tx_resp,
+							<Vec<String>>::new(),
+							Some(6224),
+							Vec::new(),
+							None,
+						)
+						.await
+						.map_err(|e| e.to_string())
+				});
+
+				// A message building on an unknown message should be refused
+				tx.send(Cmd::SubmitMsg {
+					req: SubmitMsgReq {
+						data: Vec::new(),
+						prev: Some([1; 32].into()),
+						captcha_ans: None,
+						captcha_src: None,
+						height: 1,
+						timestamp: 0,
+					},
+					req_id: 0,
+				})
+				.await?;
+				let resp = rx_resp.recv().await?;
+
+				assert!(matches!(resp, CmdResp::Error { req_id: 0, .. }));
+
+				tx.send(Cmd::GetRejections { req_id: 1 }).await?;
+				let resp = rx_resp.recv().await?;
+				tx.send(Cmd::Terminate).await?;
+
+				match resp {
+					CmdResp::RejectionsLoaded { counts, .. } => {
+						assert_eq!(counts.get("unknown_parent"), Some(&1));
+					}
+					_ => {
+						panic!("Invalid response. Expected rejection counts.");
+					}
+				}
+
+				join.await
+					.map_err(|e| <JoinError as Into<Box<dyn StdError>>>::into(e))?
+					.map_err(|e| e.into())
+			})
+			.await
+	}
+
+	#[cfg(not(target_arch = "wasm32"))]
+	#[tokio::test]
+	async fn test_get_challenge() -> Result<(), Box<dyn StdError>> {
+		let (tx, rx) = async_channel::unbounded();
+		let (tx_resp, rx_resp) = async_channel::unbounded();
+
+		let local = tokio::task::LocalSet::new();
+		local
+			.run_until(async {
+				let client = client_with_key("get_challenge");
+				let join = tokio::task::spawn_local(async move {
+					client
+						.start(
+							rx,
+							tx_resp,
+							<Vec<String>>::new(),
+							Some(6224),
+							Vec::new(),
+							None,
+						)
+						.await
+						.map_err(|e| e.to_string())
+				});
+
+				tx.send(Cmd::Flush { req_id: 0 }).await?;
+				rx_resp.recv().await?;
+
+				tx.send(Cmd::SubmitMsg {
+					req: SubmitMsgReq {
+						data: Vec::new(),
+						prev: None,
+						captcha_ans: None,
+						captcha_src: None,
+						height: 0,
+						timestamp: 0,
+					},
+					req_id: 1,
+				})
+				.await?;
+				let hash = match rx_resp.recv().await? {
+					CmdResp::MsgSubmitted { hash, .. } => hash,
+					_ => {
+						panic!("Invalid response. Expected hash.");
+					}
+				};
+
+				// The next message must build on, and answer the captcha of, the only message
+				tx.send(Cmd::GetChallenge { req_id: 2 }).await?;
+				let resp = rx_resp.recv().await?;
+				tx.send(Cmd::Terminate).await?;
+
+				match resp {
+					CmdResp::Challenge { challenge, .. } => {
+						assert_eq!(challenge.head, Some(hash.clone()));
+						assert_eq!(challenge.height, 1);
+						assert_eq!(challenge.captcha_src, Some(hash));
+						assert!(challenge.captcha.is_some());
+					}
+					_ => {
+						panic!("Invalid response. Expected challenge.");
+					}
+				}
+
+				join.await
+					.map_err(|e| <JoinError as Into<Box<dyn StdError>>>::into(e))?
+					.map_err(|e| e.into())
+			})
+			.await
+	}
+
+	#[cfg(not(target_arch = "wasm32"))]
+	#[tokio::test]
+	async fn test_compose_and_submit() -> Result<(), Box<dyn StdError>> {
+		let (tx, rx) = async_channel::unbounded();
+		let (tx_resp, rx_resp) = async_channel::unbounded();
+
+		let local = tokio::task::LocalSet::new();
+		local
+			.run_until(async {
+				let client = client_with_key("compose_and_submit");
+				let join = tokio::task::spawn_local(async move {
+					client
+						.start(
+							rx,
+							tx_resp,
+							<Vec<String>>::new(),
+							Some(6224),
+							Vec::new(),
+							None,
+						)
+						.await
+						.map_err(|e| e.to_string())
+				});
+
+				tx.send(Cmd::Flush { req_id: 0 }).await?;
+				rx_resp.recv().await?;
+
+				// The first message in an empty chain needs no captcha answer
+				tx.send(Cmd::ComposeAndSubmit {
+					req: ComposeMsgReq::new(Vec::new(), None),
+					req_id: 1,
+				})
+				.await?;
+				let hash = match rx_resp.recv().await? {
+					CmdResp::MsgSubmitted { hash, .. } => hash,
+					_ => {
+						panic!("Invalid response. Expected hash.");
+					}
+				};
+
+				tx.send(Cmd::GetHead { req_id: 2 }).await?;
+				let resp = rx_resp.recv().await?;
+				assert_eq!(resp, CmdResp::HeadLoaded { hash, req_id: 2 });
+
+				// Subsequent messages must answer the captcha correctly
+				tx.send(Cmd::ComposeAndSubmit {
+					req: ComposeMsgReq::new(Vec::new(), Some("wrong".into())),
+					req_id: 3,
+				})
+				.await?;
+				let resp = rx_resp.recv().await?;
+				tx.send(Cmd::Terminate).await?;
+
+				assert!(matches!(resp, CmdResp::Error { req_id: 3, .. }));
+
+				join.await
+					.map_err(|e| <JoinError as Into<Box<dyn StdError>>>::into(e))?
+					.map_err(|e| e.into())
+			})
+			.await
+	}
+
+	#[cfg(not(target_arch = "wasm32"))]
+	#[tokio::test]
+	async fn test_report_reorg() -> Result<(), Box<dyn StdError>> {
+		let (tx, rx) = async_channel::unbounded();
+		let (tx_resp, rx_resp) = async_channel::unbounded();
+
+		// Prefers the chain seen last, such that every new root is a fork
+		// the chain switches to
+		#[derive(Debug)]
+		struct LastSeen;
+
+		impl ForkChoice for LastSeen {
+			fn compare(&self, rt: &Rt, a: Head, b: Head) -> Ordering {
+				a.height
+					.cmp(&b.height)
+					.then_with(|| rt.seen_at(a.hash).cmp(&rt.seen_at(b.hash)))
+					.then_with(|| a.hash.cmp(b.hash))
+			}
+		}
+
+		struct MockClient;
+
+		impl NetworkClient for MockClient {
+			fn tx_follows_consensus_rules(
+				&self,
+				_rt: &Rt,
+				_msg: &Message,
+			) -> Result<(), ValidationError> {
+				Ok(())
+			}
+
+			fn chain_id(&self) -> usize {
+				2
+			}
+
+			fn fork_choice(&self) -> Box<dyn ForkChoice> {
+				Box::new(LastSeen)
+			}
+
+			fn key_path(&self) -> Option<String> {
+				client_with_key("report_reorg").key_path
+			}
+		}
+
+		let local = tokio::task::LocalSet::new();
+		local
+			.run_until(async {
+				let client = MockClient;
+				let join = tokio::task::spawn_local(async move {
+					client
+						.start(
+							rx,
+							tx_resp,
+							<Vec<String>>::new(),
+							Some(6224),
+							Vec::new(),
+							None,
+						)
+						.await
+						.map_err(|e| e.to_string())
+				});
+
+				tx.send(Cmd::Flush { req_id: 0 }).await?;
+
+				// Submit two competing roots, switching the chain to the latter
+				let mut hashes = Vec::new();
+				for req_id in 1..=2 {
+					tx.send(Cmd::SubmitMsg {
+						req: SubmitMsgReq::new(vec![req_id as u8], None, None, None, 0, 0),
+						req_id,
+					})
+					.await?;
+
+					loop {
+						if let CmdResp::MsgSubmitted {
+							hash,
+							req_id: resp_id,
+						} = rx_resp.recv().await?
+						{
+							if resp_id == req_id {
+								hashes.push(hash);
+
+								break;
+							}
+						}
+					}
+				}
+
+				// The application is told which message to roll back and which to replay
+				let reorg = loop {
+					if let reorg @ CmdResp::Reorg { .. } = rx_resp.recv().await? {
+						break reorg;
+					}
+				};
+				tx.send(Cmd::Terminate).await?;
+
+				assert_eq!(
+					reorg,
+					CmdResp::Reorg {
+						old_head: hashes[0].clone(),
+						new_head: hashes[1].clone(),
+						detached: vec![hashes[0].clone()],
+						attached: vec![hashes[1].clone()],
+					}
+				);
+
+				join.await
+					.map_err(|e| <JoinError as Into<Box<dyn StdError>>>::into(e))?
+					.map_err(|e| e.into())
+			})
+			.await
+	}
+
 	#[cfg(not(target_arch = "wasm32"))]
 	#[tokio::test]
 	async fn test_load_message() -> Result<(), Box<dyn StdError>> {
@@ -913,8 +1838,12 @@ mod tests {
 		struct MockClient;
 
 		impl NetworkClient for MockClient {
-			fn tx_follows_consensus_rules(&self, _rt: &Rt, _msg: &Message) -> bool {
-				true
+			fn tx_follows_consensus_rules(
+				&self,
+				_rt: &Rt,
+				_msg: &Message,
+			) -> Result<(), ValidationError> {
+				Ok(())
 			}
 
 			fn chain_id(&self) -> usize {
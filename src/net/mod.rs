@@ -1,6 +1,10 @@
 pub mod behavior;
 pub mod client;
 pub mod msg;
+pub mod orphan;
+pub mod peers;
+pub mod reconcile;
+pub mod record;
 pub mod req;
 pub mod sync;
 
@@ -22,17 +26,170 @@ pub const RUNTIME_STORE: &'static str = "runtime";
 /// The key under the runtime store under which the state is stored.
 pub const STATE_KEY: &'static str = "state";
 
+/// The key under the runtime store under which the keypair identifying the
+/// node is stored.
+pub const KEYPAIR_KEY: &'static str = "keypair";
+
 /// The name to be broadcasted by P2P peers to identify each other.
 pub const NET_PROTOCOL_PREFIX: &'static str = "chud_";
 
 /// The binary request response protocol name.
 pub const RR_PROTOCOL_PREFIX: &'static str = "/chud_bin";
 
+/// The version of the Kademlia protocol spoken by peers.
+pub const KAD_PROTOCOL_VERSION: &'static str = "1.0.0";
+
 /// The number of milliseconds to wait between synchronizing with peers.
 pub const SYNCHRONIZATION_INTERVAL: u64 = 120000;
 
 /// The number of milliseconds before a synchronization request is invalid.
 pub const SYNCHRONIZATION_TIMEOUT: u64 = 30000;
 
-/// The name of the floodsub topic to which new messages are published.
-pub const FLOODSUB_MESSAGE_TOPIC: &'static str = "new_msg";
+/// The name of the gossipsub topic to which new messages are published,
+/// within the namespace of a chain. See [`message_topic`].
+pub const GOSSIPSUB_MESSAGE_TOPIC: &'static str = "new_msg";
+
+/// The number of milliseconds between gossipsub heartbeats, in which the
+/// mesh is maintained and gossip is emitted.
+pub const GOSSIPSUB_HEARTBEAT_INTERVAL: u64 = 1000;
+
+/// The number of peers gossipsub aims to forward messages to directly.
+pub const GOSSIPSUB_MESH_N: usize = 6;
+
+/// The number of mesh peers below which gossipsub grafts more peers.
+pub const GOSSIPSUB_MESH_N_LOW: usize = 4;
+
+/// The number of mesh peers above which gossipsub prunes peers.
+pub const GOSSIPSUB_MESH_N_HIGH: usize = 12;
+
+/// The number of milliseconds the IDs of seen messages are remembered, such
+/// that duplicates are neither validated nor forwarded again.
+pub const GOSSIPSUB_DUPLICATE_CACHE_TIME: u64 = 120000;
+
+/// The score weight of each invalid message a peer forwards, which is
+/// squared over the number of such messages.
+pub const GOSSIPSUB_INVALID_MESSAGE_WEIGHT: f64 = -10.0;
+
+/// The maximum number of received messages buffered while waiting for their
+/// previous message to arrive.
+pub const ORPHAN_POOL_SIZE: usize = 256;
+
+/// The number of milliseconds a message is buffered while waiting for its
+/// previous message to arrive.
+pub const ORPHAN_EXPIRY: u64 = 120000;
+
+/// The maximum number of downloaded messages, and separately of downloaded
+/// headers, buffered while their ancestors are downloaded and validated.
+pub const SYNC_PENDING_POOL_SIZE: usize = 16384;
+
+/// The number of milliseconds a downloaded message or header is buffered
+/// while its ancestors are downloaded and validated.
+pub const SYNC_PENDING_EXPIRY: u64 = 600000;
+
+/// The score at or below which a peer is banned. See [`peers::Reputation`].
+pub const BAN_THRESHOLD: i64 = -100;
+
+/// The score deducted from a peer that serves or stores a DHT record which
+/// does not match its key.
+pub const INVALID_RECORD_PENALTY: i64 = 25;
+
+/// The minimum number of distinct sampled peers that must respond before
+/// the HEAD they agree on is accepted. Rounds sampling fewer peers require
+/// every sampled peer to respond.
+pub const MIN_HEAD_RESPONDERS: usize = 3;
+
+/// The score deducted from a peer that claims a HEAD above the one agreed
+/// upon by the majority of sampled peers, at a height it is not at.
+pub const HEAD_DISPUTE_PENALTY: i64 = 5;
+
+/// The score deducted from a peer that claims a HEAD which cannot be
+/// verified.
+pub const INVALID_HEAD_PENALTY: i64 = 50;
+
+/// The score deducted from a peer that serves a snapshot which does not
+/// match the trusted snapshot.
+pub const INVALID_SNAPSHOT_PENALTY: i64 = 50;
+
+/// The maximum number of messages exchanged in a single range or ancestor
+/// request.
+pub const SYNC_BATCH_SIZE: usize = 64;
+
+/// The maximum number of headers exchanged in a single headers request.
+pub const SYNC_HEADERS_BATCH_SIZE: usize = 2048;
+
+/// The maximum number of range requests in flight at once, across all peers.
+pub const SYNC_MAX_INFLIGHT_BATCHES: usize = 8;
+
+/// The number of milliseconds between advancing the synchronization state
+/// machine, expiring rounds and retrying failed synchronizations.
+pub const SYNC_TICK_INTERVAL: u64 = 5000;
+
+/// The maximum number of times a failed synchronization is retried before
+/// waiting for the next synchronization interval.
+pub const SYNC_MAX_RETRIES: usize = 3;
+
+/// The number of milliseconds after which records expire from the DHT.
+pub const DHT_RECORD_TTL: u64 = 129600000;
+
+/// The number of milliseconds after which published messages are published
+/// again, ahead of the expiry of their records.
+pub const DHT_REPUBLISH_INTERVAL: u64 = 86400000;
+
+/// The maximum number of messages being published to the DHT at once.
+pub const DHT_MAX_INFLIGHT_PUTS: usize = 16;
+
+/// The number of milliseconds before the first retry of a failed
+/// publication. The delay doubles with each consecutive failure.
+pub const DHT_PUT_RETRY_BASE: u64 = 30000;
+
+/// The maximum number of milliseconds between retries of a failed
+/// publication.
+pub const DHT_PUT_RETRY_MAX: u64 = 3600000;
+
+/// The number of buckets a range of heights is split into when peers
+/// reconciling their messages disagree on it.
+pub const RECONCILE_FANOUT: usize = 16;
+
+/// The maximum number of messages in a range of heights whose hashes are
+/// listed, rather than split further, when peers disagree on it.
+pub const RECONCILE_LEAF_SIZE: usize = 64;
+
+/// The maximum number of buckets compared in a single reconciliation
+/// request.
+pub const RECONCILE_MAX_BUCKETS: usize = 256;
+
+/// The maximum number of hashes listed in response to a single
+/// reconciliation request.
+pub const RECONCILE_MAX_HASHES: usize = RECONCILE_LEAF_SIZE * RECONCILE_MAX_BUCKETS;
+
+/// The maximum number of requests a single reconciliation may take.
+pub const RECONCILE_MAX_ROUNDS: usize = 8;
+
+/// Gets the name of the chain with the given ID, advertised by peers of
+/// the chain to identify each other, and under which its topics and DHT
+/// records are namespaced.
+pub fn chain_namespace(chain_id: usize) -> String {
+	format!("{}{}", NET_PROTOCOL_PREFIX, chain_id)
+}
+
+/// Gets the name of the gossipsub topic to which new messages of the chain
+/// with the given ID are published.
+pub fn message_topic(chain_id: usize) -> String {
+	format!("{}/{}", chain_namespace(chain_id), GOSSIPSUB_MESSAGE_TOPIC)
+}
+
+/// Gets the name of the request-response protocol of the chain with the
+/// given ID.
+pub fn rr_protocol(chain_id: usize) -> String {
+	format!("{}/{}", RR_PROTOCOL_PREFIX, chain_id)
+}
+
+/// Gets the name of the Kademlia protocol of the chain with the given ID,
+/// such that peers of different chains keep separate DHTs.
+pub fn kad_protocol(chain_id: usize) -> String {
+	format!(
+		"/{}/kad/{}",
+		chain_namespace(chain_id),
+		KAD_PROTOCOL_VERSION
+	)
+}
@@ -1,31 +1,144 @@
 use super::{
 	super::{
 		crypto::hash::Hash,
-		sys::{msg::Message, rt::Rt},
+		sys::{
+			msg::{Message, MessageHeader},
+			rt::Rt,
+		},
 	},
 	behavior::BehaviorEvent,
-	FLOODSUB_MESSAGE_TOPIC,
+	message_topic,
+	orphan::OrphanPool,
+};
+use libp2p::{
+	gossipsub::{
+		Behaviour as Gossipsub, Event as GossipsubEvent, IdentTopic, MessageAcceptance, MessageId,
+		PublishError,
+	},
+	PeerId,
 };
-use libp2p::floodsub::{Floodsub, FloodsubEvent, Topic};
 use serde_json::Error as SerdeError;
 use std::{
+	collections::HashMap,
 	error::Error as StdError,
 	fmt::{Display, Error as FmtError, Formatter},
 };
 
-pub type ConsensusRule<'a> = &'a (dyn Fn(&Rt, &Message) -> bool + Send);
+pub type ConsensusRule<'a> = &'a (dyn Fn(&Rt, &Message) -> Result<(), ValidationError> + Send);
 
 /// Events emitted by the message behavior
 #[derive(Debug)]
 pub enum Event {
 	/// Emitted when a message gets received
 	MessageReceived(Hash),
+
+	/// Emitted when a received message fails a consensus check
+	MessageRejected { hash: Hash, error: ValidationError },
+
+	/// Emitted when a received message is buffered until its previous
+	/// message, which is expected to sit at the given height, arrives
+	MessageOrphaned {
+		hash: Hash,
+		prev: Hash,
+		height: usize,
+	},
 }
 
+/// The reason a message was rejected by the consensus rules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+	/// The hash of the message does not match its contents
+	InvalidHash,
+
+	/// The message belongs to a chain other than the runtime's
+	WrongChain,
+
+	/// The message was made after the current time
+	FutureTimestamp,
+
+	/// The message was not made strictly after its predecessor
+	NonMonotonicTimestamp,
+
+	/// The message answers a captcha other than the one derived by the lookback rule
+	WrongCaptchaSource,
+
+	/// The message's captcha answer is missing or incorrect
+	WrongCaptchaAnswer,
+
+	/// The message references a previous message that is not known
+	UnknownParent,
+
+	/// The message's height is not one more than its parent's, or is nonzero for a root
+	InvalidHeight,
+
+	/// The message has no previous message, but is not the genesis message of the chain
+	WrongGenesis,
+
+	/// The message sits at the height of a checkpoint, but is not the checkpointed message
+	CheckpointMismatch,
+
+	/// The message forks the chain deeper than the maximum reorg depth
+	ReorgTooDeep,
+
+	/// The message was rejected by an application-defined rule
+	RuleFailed(String),
+}
+
+impl ValidationError {
+	/// Gets a short, stable name for the kind of rejection, used for counting rejections.
+	pub fn kind(&self) -> &'static str {
+		match self {
+			Self::InvalidHash => "invalid_hash",
+			Self::WrongChain => "wrong_chain",
+			Self::FutureTimestamp => "future_timestamp",
+			Self::NonMonotonicTimestamp => "non_monotonic_timestamp",
+			Self::WrongCaptchaSource => "wrong_captcha_source",
+			Self::WrongCaptchaAnswer => "wrong_captcha_answer",
+			Self::UnknownParent => "unknown_parent",
+			Self::InvalidHeight => "invalid_height",
+			Self::WrongGenesis => "wrong_genesis",
+			Self::CheckpointMismatch => "checkpoint_mismatch",
+			Self::ReorgTooDeep => "reorg_too_deep",
+			Self::RuleFailed(_) => "rule_failed",
+		}
+	}
+}
+
+impl Display for ValidationError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+		match self {
+			Self::InvalidHash => write!(f, "the message hash does not match its contents"),
+			Self::WrongChain => write!(f, "the message belongs to another chain"),
+			Self::FutureTimestamp => write!(f, "the message timestamp is in the future"),
+			Self::NonMonotonicTimestamp => write!(
+				f,
+				"the message timestamp is not after the previous message's timestamp"
+			),
+			Self::WrongCaptchaSource => {
+				write!(f, "the message answers the wrong captcha source")
+			}
+			Self::WrongCaptchaAnswer => write!(f, "the message's captcha answer is incorrect"),
+			Self::UnknownParent => write!(f, "the previous message is unknown"),
+			Self::InvalidHeight => write!(
+				f,
+				"the message height does not follow the previous message's height"
+			),
+			Self::WrongGenesis => write!(f, "the message is a root other than the genesis"),
+			Self::CheckpointMismatch => write!(f, "the message contradicts a checkpoint"),
+			Self::ReorgTooDeep => write!(f, "the message forks the chain too deeply"),
+			Self::RuleFailed(reason) => write!(f, "application rule failed: {}", reason),
+		}
+	}
+}
+
+impl StdError for ValidationError {}
+
 /// Errors emitted by the message behavior
 #[derive(Debug)]
 pub enum Error {
 	SerializationError(SerdeError),
+	ValidationError(ValidationError),
+	PublishError(PublishError),
 }
 
 impl Display for Error {
@@ -34,6 +147,10 @@ impl Display for Error {
 			Self::SerializationError(e) => {
 				write!(f, "encountered an error in serialization: {}", e)
 			}
+			Self::ValidationError(e) => {
+				write!(f, "the message was rejected by consensus: {}", e)
+			}
+			Self::PublishError(e) => write!(f, "failed to publish the message: {}", e),
 		}
 	}
 }
@@ -42,6 +159,8 @@ impl StdError for Error {
 	fn source(&self) -> Option<&(dyn StdError + 'static)> {
 		match self {
 			Self::SerializationError(e) => Some(e),
+			Self::ValidationError(e) => Some(e),
+			Self::PublishError(e) => Some(e),
 		}
 	}
 }
@@ -52,125 +171,782 @@ impl From<SerdeError> for Error {
 	}
 }
 
+impl From<ValidationError> for Error {
+	fn from(e: ValidationError) -> Self {
+		Self::ValidationError(e)
+	}
+}
+
+impl From<PublishError> for Error {
+	fn from(e: PublishError) -> Self {
+		Self::PublishError(e)
+	}
+}
+
 /// A context that handles swarm events dealing with messages.
 #[derive(Default)]
-pub struct Context;
+pub struct Context {
+	// The ID of the chain, whose topic messages are published to
+	chain_id: usize,
+
+	// Messages received before their previous message
+	orphans: OrphanPool,
+
+	// Gossiped orphans whose validation has yet to be reported
+	unvalidated: HashMap<Hash, (MessageId, PeerId)>,
+}
 
 impl Context {
+	/// Sets the ID of the chain whose messages are received and published.
+	pub fn set_chain_id(&mut self, chain_id: usize) {
+		self.chain_id = chain_id;
+	}
+
+	/// Determines whether the message with the given hash was received, but
+	/// is waiting for its previous message.
+	pub fn is_orphaned(&self, hash: &Hash) -> bool {
+		self.orphans.contains(hash)
+	}
+
 	pub fn poll(
 		&mut self,
 		rt: &mut Rt,
 		consensus_rule: ConsensusRule,
-		floodsub: &mut Floodsub,
+		gossipsub: &mut Gossipsub,
 		in_event: Option<BehaviorEvent>,
 	) -> (Result<Option<Event>, Error>, Option<BehaviorEvent>) {
 		match in_event {
-			// Possible floodsub message topics:
-			// - new_msg
-			Some(BehaviorEvent::Floodsub(FloodsubEvent::Message(fs_msg))) => {
+			// Possible gossipsub message topics:
+			// - chud_<chain_id>/new_msg
+			//
+			// Gossipsub holds on to received messages until they are
+			// reported, and only forwards those which are accepted
+			Some(BehaviorEvent::Gossipsub(GossipsubEvent::Message {
+				propagation_source,
+				message_id,
+				message,
+			})) => {
+				if message.topic != IdentTopic::new(message_topic(self.chain_id)).hash() {
+					report(
+						gossipsub,
+						&message_id,
+						&propagation_source,
+						MessageAcceptance::Ignore,
+					);
+
+					return (Ok(None), None);
+				}
+
 				// A new message has been received
-				if fs_msg.topics.contains(&Topic::new(FLOODSUB_MESSAGE_TOPIC)) {
-					if let Ok(msg) = serde_json::from_slice::<Message>(&fs_msg.data) {
-						let hash = msg.hash().clone();
-
-						if self.follows_consensus_rules(rt, &msg, consensus_rule) {
-							info!(
-								"Added message {} to the blockchain at height {}",
-								hex::encode(msg.hash()),
-								msg.data().height()
-							);
-
-							rt.insert_message(msg);
-						} else {
-							error!("Rejecting message {}", hex::encode(msg.hash()));
+				let msg = match serde_json::from_slice::<Message>(&message.data) {
+					Ok(msg) => msg,
+					Err(e) => {
+						report(
+							gossipsub,
+							&message_id,
+							&propagation_source,
+							MessageAcceptance::Reject,
+						);
+
+						return (Err(e.into()), None);
+					}
+				};
+				let hash = msg.hash().clone();
+
+				match validate(rt, &msg, consensus_rule) {
+					Ok(_) => {}
+					Err(ValidationError::UnknownParent) => {
+						// Hold on to the message until its parent can be found.
+						// It is forwarded once it connects.
+						let prev = msg.data().prev().cloned();
+						let height = msg.data().height().checked_sub(1);
+
+						if let (Some(prev), Some(height)) = (prev, height) {
+							self.prune_orphans(gossipsub);
+
+							if self.orphans.insert(msg) {
+								self.unvalidated
+									.insert(hash.clone(), (message_id, propagation_source));
+
+								return (
+									Ok(Some(Event::MessageOrphaned { hash, prev, height })),
+									None,
+								);
+							}
 						}
 
-						return (Ok(Some(Event::MessageReceived(hash))), None);
-					} else {
-						(Ok(None), None)
+						// The message may yet be valid, so its sender isn't penalized
+						report(
+							gossipsub,
+							&message_id,
+							&propagation_source,
+							MessageAcceptance::Ignore,
+						);
+
+						let error = ValidationError::UnknownParent;
+						error!("Rejecting message {}: {}", hex::encode(&hash), error);
+
+						return (Ok(Some(Event::MessageRejected { hash, error })), None);
+					}
+					Err(error) => {
+						report(
+							gossipsub,
+							&message_id,
+							&propagation_source,
+							MessageAcceptance::Reject,
+						);
+
+						error!("Rejecting message {}: {}", hex::encode(&hash), error);
+
+						return (Ok(Some(Event::MessageRejected { hash, error })), None);
 					}
-				} else {
-					(Ok(None), None)
 				}
+
+				// Light clients keep messages whose captchas they could not
+				// verify, but do not forward them
+				let acceptance = if captcha_verified(rt, &msg) {
+					MessageAcceptance::Accept
+				} else {
+					MessageAcceptance::Ignore
+				};
+				report(gossipsub, &message_id, &propagation_source, acceptance);
+
+				info!(
+					"Added message {} to the blockchain at height {}",
+					hex::encode(msg.hash()),
+					msg.data().height()
+				);
+
+				insert_received(rt, msg);
+
+				(Ok(Some(Event::MessageReceived(hash))), None)
 			}
 			_ => (Ok(None), in_event),
 		}
 	}
 
-	/// Publishes a message to the floodsub messages topic.
+	/// Drops the buffered messages whose previous message did not arrive in
+	/// time. Gossiped messages among them are neither forwarded nor held
+	/// against their senders.
+	pub fn prune_orphans(&mut self, gossipsub: &mut Gossipsub) {
+		for hash in self.orphans.prune() {
+			if let Some((message_id, source)) = self.unvalidated.remove(&hash) {
+				report(gossipsub, &message_id, &source, MessageAcceptance::Ignore);
+			}
+		}
+	}
+
+	/// Re-validates and inserts all buffered messages whose previous message
+	/// is the message with the given hash, now that it is known. Returns an
+	/// event describing the outcome for each buffered message. Gossiped
+	/// messages are forwarded only once they connect.
+	pub fn connect_orphans(
+		&mut self,
+		rt: &mut Rt,
+		consensus_rule: ConsensusRule,
+		parent: &Hash,
+		gossipsub: &mut Gossipsub,
+	) -> Vec<Event> {
+		self.orphans
+			.take_children(parent)
+			.into_iter()
+			.map(|msg| {
+				let hash = msg.hash().clone();
+				let res = validate(rt, &msg, consensus_rule);
+
+				if let Some((message_id, source)) = self.unvalidated.remove(&hash) {
+					let acceptance = match res {
+						Ok(_) if captcha_verified(rt, &msg) => MessageAcceptance::Accept,
+						Ok(_) => MessageAcceptance::Ignore,
+						Err(_) => MessageAcceptance::Reject,
+					};
+					report(gossipsub, &message_id, &source, acceptance);
+				}
+
+				if let Err(error) = res {
+					error!("Rejecting message {}: {}", hex::encode(&hash), error);
+
+					return Event::MessageRejected { hash, error };
+				}
+
+				info!(
+					"Connected message {} to the blockchain at height {}",
+					hex::encode(&hash),
+					msg.data().height()
+				);
+
+				insert_received(rt, msg);
+
+				Event::MessageReceived(hash)
+			})
+			.collect()
+	}
+
+	/// Publishes a message to the gossipsub messages topic. The message is
+	/// checked against the same consensus rules as received messages, and is
+	/// not published if it violates any of them. Messages are still accepted
+	/// if no peers are subscribed, as they are later published to the DHT.
 	pub fn submit_message(
 		&mut self,
 		rt: &mut Rt,
+		consensus_rule: ConsensusRule,
 		msg: Message,
-		floodsub: &mut Floodsub,
+		gossipsub: &mut Gossipsub,
 	) -> Result<(), Error> {
+		validate(rt, &msg, consensus_rule)?;
+
 		let serialized = serde_json::to_vec(&msg)?;
-		floodsub.publish(Topic::new(FLOODSUB_MESSAGE_TOPIC), serialized);
+		match gossipsub.publish(IdentTopic::new(message_topic(self.chain_id)), serialized) {
+			Ok(_) | Err(PublishError::Duplicate) => {}
+			Err(PublishError::InsufficientPeers) => {
+				warn!("No peers to gossip message {} to", hex::encode(msg.hash()));
+			}
+			Err(e) => return Err(e.into()),
+		}
 
 		rt.insert_message(msg);
 
 		Ok(())
 	}
+}
 
-	/// Determines whether:
-	/// - The hash of the message is valid
-	/// - The timestamp of the message is valid
-	/// - The message is at the front of the current longest_chain
-	/// - The captcha answer in the message is valid
-	/// - The captcha src is derived properly from the hash
-	fn follows_consensus_rules(
-		&self,
-		rt: &Rt,
-		msg: &Message,
-		consensus_rule: ConsensusRule,
-	) -> bool {
-		// Ensure the message was made before now
-		if instant::now() < msg.data().timestamp() as f64 {
-			return false;
-		};
-
-		if let Some(prev) = msg.data().prev() {
-			if !rt
-				.get_message(prev)
-				.map(|prev_message| prev_message.data().timestamp() < msg.data().timestamp())
-				// That the transaction from which the captcha is sourced is the correct source
-				.and_then(|cond| {
-					let lookback = msg.data().lookback()?;
-					let mut curr = msg;
-
-					for _ in 0..lookback {
-						if let Some(prev) = curr.data().prev() {
-							curr = rt.get_message(prev)?;
-						} else {
-							curr = rt.get_message(rt.longest_chain()?)?;
-						}
-					}
+// Reports the outcome of validating a gossiped message, upon which it is
+// forwarded or dropped
+fn report(
+	gossipsub: &mut Gossipsub,
+	message_id: &MessageId,
+	source: &PeerId,
+	acceptance: MessageAcceptance,
+) {
+	let _ = gossipsub.report_message_validation_result(message_id, source, acceptance);
+}
+
+// Inserts a message received from a peer. Light clients keep only the
+// header of messages they did not ask for.
+fn insert_received(rt: &mut Rt, msg: Message) {
+	if rt.is_light() {
+		rt.insert_header(msg.header());
+	} else {
+		rt.insert_message(msg);
+	}
+}
+
+/// Determines whether:
+/// - The header of the message is valid, per [`validate_header`]
+/// - The payloads of all ancestors of the message are known, unless the
+///   runtime is that of a light client
+/// - The captcha answer in the message is valid
+///
+/// Returns the first rule the message violates, if any. Applies equally to
+/// gossiped, submitted and synchronized messages.
+///
+/// Light clients do not know the payloads of most messages, so they only
+/// verify captcha answers whose captcha source happens to be known. Whether
+/// this was the case is reported by [`captcha_verified`].
+pub fn validate(
+	rt: &Rt,
+	msg: &Message,
+	consensus_rule: ConsensusRule,
+) -> Result<(), ValidationError> {
+	validate_header(rt, &msg.header())?;
+
+	if let Some(prev) = msg.data().prev() {
+		// Payloads are validated in ancestor order, such that the captcha
+		// source is always known. Light clients may not know it.
+		if !rt.is_light() && !rt.is_complete(prev) {
+			return Err(ValidationError::UnknownParent);
+		}
 
-					Some(cond && curr.data().captcha_src() == Some(curr.hash()))
-				})
-				// And that its captcha answer is valid
-				.and_then(|cond| {
-					rt.get_message(msg.data().captcha_src()?)
-						.and_then(|captcha_src| {
-							Some(
-								cond && (&(<blake3::Hash as Into<Hash>>::into(blake3::hash(
-									msg.data().captcha_ans()?.as_bytes(),
-								))) == captcha_src.data().new_captcha().answer()),
-							)
-						})
-				})
-				.unwrap_or_default()
-			{
-				return false;
+		// Ensure the captcha answer is valid. Full nodes know the payloads
+		// of the whole chain, including the captcha source.
+		match rt.captcha_source(prev) {
+			Some(captcha_src) => {
+				let captcha_ans = msg
+					.data()
+					.captcha_ans()
+					.ok_or(ValidationError::WrongCaptchaAnswer)?;
+
+				if &<blake3::Hash as Into<Hash>>::into(blake3::hash(captcha_ans.as_bytes()))
+					!= captcha_src.data().new_captcha().answer()
+				{
+					return Err(ValidationError::WrongCaptchaAnswer);
+				}
 			}
+			None if !rt.is_light() => return Err(ValidationError::WrongCaptchaSource),
+			None => {}
 		}
+	}
+
+	consensus_rule(rt, msg)
+}
+
+/// Determines whether [`validate`] checked the captcha answer of the
+/// message. This is always the case outside of light clients, and for
+/// messages with no previous message. Messages whose captcha was not
+/// checked should not be vouched for to other peers.
+pub fn captcha_verified(rt: &Rt, msg: &Message) -> bool {
+	msg.data()
+		.prev()
+		.map(|prev| !rt.is_light() || rt.captcha_source(prev).is_some())
+		.unwrap_or(true)
+}
 
-		// Ensure the hash is valid
-		msg.data()
-			.hashed()
-			.ok()
-			.and_then(|hashed| Some(msg.hash() != &hashed))
-			.unwrap_or_default()
-			&& consensus_rule(rt, msg)
+/// Determines whether:
+/// - The hash of the message is valid
+/// - The message belongs to the chain of the runtime
+/// - The timestamp of the message is valid, allowing for the timestamp
+///   tolerance of the chain
+/// - The message honors every checkpoint, and does not fork the chain
+///   deeper than the maximum reorg depth
+/// - The height of the message is one more than its parent's, or zero if it has none
+/// - The message is the genesis message of the chain, if it has no parent and
+///   the chain has a spec
+/// - The captcha src is derived properly from the hash
+///
+/// Only the headers of the message's ancestors need be known. Returns the
+/// first rule the header violates, if any.
+pub fn validate_header(rt: &Rt, header: &MessageHeader) -> Result<(), ValidationError> {
+	// Ensure the hash is valid
+	if header.hashed().ok().as_ref() != Some(header.hash()) {
+		return Err(ValidationError::InvalidHash);
+	}
+
+	// Ensure the message was not made for another chain
+	if header.chain_id() != rt.chain_id() {
+		return Err(ValidationError::WrongChain);
+	}
+
+	// Ensure the message was made before now
+	if instant::now() + (rt.timestamp_tolerance() as f64) < header.timestamp() as f64 {
+		return Err(ValidationError::FutureTimestamp);
+	};
+
+	if rt.contradicts_checkpoint(header.hash(), header.height()) {
+		return Err(ValidationError::CheckpointMismatch);
+	}
+
+	if rt.exceeds_reorg_depth(header.hash(), header.height()) {
+		return Err(ValidationError::ReorgTooDeep);
+	}
+
+	if let Some(prev) = header.prev() {
+		let prev_header = rt.get_header(prev).ok_or(ValidationError::UnknownParent)?;
+
+		if prev_header.height().checked_add(1) != Some(header.height()) {
+			return Err(ValidationError::InvalidHeight);
+		}
+
+		if prev_header.timestamp() >= header.timestamp() {
+			return Err(ValidationError::NonMonotonicTimestamp);
+		}
+
+		// That the transaction from which the captcha is sourced is the correct source
+		let captcha_src = rt
+			.captcha_source_hash(prev)
+			.ok_or(ValidationError::WrongCaptchaSource)?;
+		if header.captcha_src() != Some(captcha_src) {
+			return Err(ValidationError::WrongCaptchaSource);
+		}
+	} else if header.height() != 0 {
+		return Err(ValidationError::InvalidHeight);
+	} else if rt
+		.genesis()
+		.map(|genesis| genesis != header.hash())
+		.unwrap_or_default()
+	{
+		return Err(ValidationError::WrongGenesis);
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{
+		super::{
+			super::sys::{
+				msg::{lookback, MessageData},
+				spec::{ChainSpec, Genesis},
+			},
+			ORPHAN_POOL_SIZE,
+		},
+		*,
+	};
+	use instant::Duration;
+	use libp2p::{
+		gossipsub::{Config, MessageAuthenticity},
+		identity::Keypair,
+	};
+	use std::{collections::HashSet, error::Error, thread};
+
+	#[test]
+	fn test_validation_error_kind() {
+		let errors = [
+			ValidationError::InvalidHash,
+			ValidationError::WrongChain,
+			ValidationError::FutureTimestamp,
+			ValidationError::NonMonotonicTimestamp,
+			ValidationError::WrongCaptchaSource,
+			ValidationError::WrongCaptchaAnswer,
+			ValidationError::UnknownParent,
+			ValidationError::InvalidHeight,
+			ValidationError::WrongGenesis,
+			ValidationError::CheckpointMismatch,
+			ValidationError::ReorgTooDeep,
+			ValidationError::RuleFailed("too long".into()),
+		];
+
+		// Every kind of rejection is counted separately
+		let kinds = errors.iter().map(|e| e.kind()).collect::<HashSet<&str>>();
+		assert_eq!(kinds.len(), errors.len());
+		assert_eq!(ValidationError::InvalidHeight.kind(), "invalid_height");
+
+		// Application rules share a kind, but keep their reason
+		assert_eq!(
+			ValidationError::RuleFailed("spam".into()).kind(),
+			ValidationError::RuleFailed("too long".into()).kind()
+		);
+		assert_eq!(
+			ValidationError::RuleFailed("spam".into()).to_string(),
+			"application rule failed: spam"
+		);
+		assert_eq!(
+			super::Error::ValidationError(ValidationError::UnknownParent).to_string(),
+			"the message was rejected by consensus: the previous message is unknown"
+		);
+	}
+
+	#[test]
+	fn test_root_height() -> Result<(), Box<dyn Error>> {
+		let rt = Rt::default();
+
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		assert_eq!(validate(&rt, &root, &(|_, _| Ok(()))), Ok(()));
+
+		// A root claiming to be deep in the chain is invalid
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 5, 0, 0))?;
+		assert_eq!(
+			validate(&rt, &root, &(|_, _| Ok(()))),
+			Err(ValidationError::InvalidHeight)
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_inflated_height() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		rt.insert_message(root.clone());
+
+		// A message claiming an arbitrary height cannot take over the chain
+		let inflated = Message::try_from(MessageData::new(
+			Vec::new(),
+			Some(root.hash().clone()),
+			None,
+			None,
+			usize::MAX,
+			1,
+			0,
+		))?;
+		assert_eq!(
+			validate(&rt, &inflated, &(|_, _| Ok(()))),
+			Err(ValidationError::InvalidHeight)
+		);
+
+		// Neither can a message that skips heights
+		let skipping = Message::try_from(MessageData::new(
+			Vec::new(),
+			Some(root.hash().clone()),
+			None,
+			None,
+			2,
+			1,
+			0,
+		))?;
+		assert_eq!(
+			validate(&rt, &skipping, &(|_, _| Ok(()))),
+			Err(ValidationError::InvalidHeight)
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_inflated_fork() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		rt.insert_message(root.clone());
+
+		let honest = Message::try_from(MessageData::new(
+			Vec::new(),
+			Some(root.hash().clone()),
+			None,
+			None,
+			1,
+			1,
+			0,
+		))?;
+		rt.insert_message(honest.clone());
+
+		// A fork from the root claiming a greater height is rejected, and the
+		// honest chain remains the longest
+		let fork = Message::try_from(MessageData::new(
+			Vec::new(),
+			Some(root.hash().clone()),
+			None,
+			None,
+			1000,
+			2,
+			0,
+		))?;
+		assert_eq!(
+			validate(&rt, &fork, &(|_, _| Ok(()))),
+			Err(ValidationError::InvalidHeight)
+		);
+		assert_eq!(rt.longest_chain(), Some(honest.hash()));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_genesis() -> Result<(), Box<dyn Error>> {
+		let genesis = Message::try_from(MessageData::new(vec![1], None, None, None, 0, 0, 0))?;
+		let rival = Message::try_from(MessageData::new(vec![2], None, None, None, 0, 0, 0))?;
+
+		let mut rt = Rt::default();
+		rt.set_spec(&ChainSpec::new(Genesis::Hash(genesis.hash().clone())));
+
+		// Only the genesis may start the chain
+		assert_eq!(validate(&rt, &genesis, &(|_, _| Ok(()))), Ok(()));
+		assert_eq!(
+			validate(&rt, &rival, &(|_, _| Ok(()))),
+			Err(ValidationError::WrongGenesis)
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_timestamp_tolerance() -> Result<(), Box<dyn Error>> {
+		let future = instant::now() as u128 + 60000;
+		let msg = Message::try_from(MessageData::builder(Vec::new()).timestamp(future).build())?;
+
+		let mut rt = Rt::default();
+		assert_eq!(
+			validate(&rt, &msg, &(|_, _| Ok(()))),
+			Err(ValidationError::FutureTimestamp)
+		);
+
+		// Clocks may drift by as much as the chain tolerates
+		let mut spec = ChainSpec::new(Genesis::Hash(msg.hash().clone()));
+		spec.timestamp_tolerance = 120000;
+		rt.set_spec(&spec);
+		assert_eq!(validate(&rt, &msg, &(|_, _| Ok(()))), Ok(()));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_wrong_chain() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+		rt.set_chain_id(69);
+
+		// Messages of other chains are refused, even if otherwise valid
+		let foreign = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		assert_eq!(
+			validate(&rt, &foreign, &(|_, _| Ok(()))),
+			Err(ValidationError::WrongChain)
+		);
+
+		let root = Message::try_from(MessageData::builder(Vec::new()).chain_id(69).build())?;
+		assert_eq!(validate(&rt, &root, &(|_, _| Ok(()))), Ok(()));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_validate_header() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		rt.insert_header(root.header());
+
+		let child = Message::try_from(MessageData::new(
+			Vec::new(),
+			Some(root.hash().clone()),
+			Some("answer".into()),
+			Some(root.hash().clone()),
+			1,
+			1,
+			0,
+		))?;
+
+		// The structure of the chain is verifiable from headers alone
+		assert_eq!(validate_header(&rt, &child.header()), Ok(()));
+
+		// But payloads are only accepted once their ancestors' are known
+		assert_eq!(
+			validate(&rt, &child, &(|_, _| Ok(()))),
+			Err(ValidationError::UnknownParent)
+		);
+
+		rt.insert_message(root);
+		assert_eq!(
+			validate(&rt, &child, &(|_, _| Ok(()))),
+			Err(ValidationError::WrongCaptchaAnswer)
+		);
+
+		Ok(())
+	}
+
+	// Wraps the message in a gossipsub event, as if received from a peer
+	fn gossiped(msg: &Message, topic: &str) -> Result<BehaviorEvent, Box<dyn Error>> {
+		let data = serde_json::to_vec(msg)?;
+
+		Ok(BehaviorEvent::Gossipsub(GossipsubEvent::Message {
+			propagation_source: PeerId::random(),
+			message_id: MessageId::from(blake3::hash(&data).as_bytes().to_vec()),
+			message: libp2p::gossipsub::Message {
+				source: None,
+				data,
+				sequence_number: None,
+				topic: IdentTopic::new(topic).hash(),
+			},
+		}))
+	}
+
+	#[test]
+	fn test_missing_captcha_source() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+
+		// Root the chain at a snapshot whose captcha source is missing its
+		// payload, and which must be answered by the next message
+		let src = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		let root = (1..)
+			.map(|timestamp| {
+				Message::try_from(MessageData::new(
+					Vec::new(),
+					Some(src.hash().clone()),
+					None,
+					Some(src.hash().clone()),
+					1,
+					timestamp,
+					0,
+				))
+			})
+			.find(|root| {
+				root.as_ref()
+					.map(|root| lookback(root.hash(), 2, rt.lookback_factor()) == Some(1))
+					.unwrap_or(true)
+			})
+			.unwrap()?;
+		rt.insert_header(src.header());
+		rt.insert_root(root.clone());
+
+		let child = Message::try_from(MessageData::new(
+			Vec::new(),
+			Some(root.hash().clone()),
+			Some("answer".into()),
+			Some(src.hash().clone()),
+			2,
+			root.data().timestamp() + 1,
+			0,
+		))?;
+
+		// Full nodes must be able to check every captcha answer
+		assert_eq!(
+			validate(&rt, &child, &(|_, _| Ok(()))),
+			Err(ValidationError::WrongCaptchaSource)
+		);
+		assert!(captcha_verified(&rt, &child));
+
+		// Light clients may not, and say so
+		rt.set_light(true);
+		assert_eq!(validate(&rt, &child, &(|_, _| Ok(()))), Ok(()));
+		assert!(!captcha_verified(&rt, &child));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_gossip_validation() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+		let mut ctx = Context::default();
+		let mut gossipsub = Gossipsub::new(
+			MessageAuthenticity::Signed(Keypair::generate_ed25519()),
+			Config::default(),
+		)?;
+
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		let child = Message::try_from(MessageData::new(
+			Vec::new(),
+			Some(root.hash().clone()),
+			Some("answer".into()),
+			Some(root.hash().clone()),
+			1,
+			1,
+			0,
+		))?;
+
+		// Messages on other topics, including those of other chains, are ignored
+		let (res, _) = ctx.poll(
+			&mut rt,
+			&(|_, _| Ok(())),
+			&mut gossipsub,
+			Some(gossiped(&root, &message_topic(1))?),
+		);
+		assert!(matches!(res, Ok(None)));
+		assert_eq!(rt.longest_chain(), None);
+
+		// Orphans are held back from propagation until they connect
+		let (res, _) = ctx.poll(
+			&mut rt,
+			&(|_, _| Ok(())),
+			&mut gossipsub,
+			Some(gossiped(&child, &message_topic(0))?),
+		);
+		assert!(matches!(res, Ok(Some(Event::MessageOrphaned { .. }))));
+		assert!(ctx.unvalidated.contains_key(child.hash()));
+
+		// And their validation is reported once they do
+		rt.insert_message(root.clone());
+		let events = ctx.connect_orphans(&mut rt, &(|_, _| Ok(())), root.hash(), &mut gossipsub);
+		assert!(matches!(
+			events.as_slice(),
+			[Event::MessageRejected {
+				error: ValidationError::WrongCaptchaAnswer,
+				..
+			}]
+		));
+		assert!(ctx.unvalidated.is_empty());
+
+		// Orphans that expire are dropped along with their pending reports
+		let grandchild = Message::try_from(MessageData::new(
+			Vec::new(),
+			Some(child.hash().clone()),
+			None,
+			None,
+			2,
+			2,
+			0,
+		))?;
+		ctx.orphans = OrphanPool::new(ORPHAN_POOL_SIZE, Duration::ZERO);
+		ctx.poll(
+			&mut rt,
+			&(|_, _| Ok(())),
+			&mut gossipsub,
+			Some(gossiped(&grandchild, &message_topic(0))?),
+		);
+		assert_eq!(ctx.unvalidated.len(), 1);
+
+		thread::sleep(Duration::from_millis(1));
+		ctx.prune_orphans(&mut gossipsub);
+		assert!(ctx.orphans.is_empty());
+		assert!(ctx.unvalidated.is_empty());
+
+		Ok(())
 	}
 }
@@ -0,0 +1,273 @@
+use super::{
+	super::{
+		crypto::hash::Hash,
+		sys::msg::{Message, MessageHeader},
+	},
+	ORPHAN_EXPIRY, ORPHAN_POOL_SIZE,
+};
+use instant::{Duration, Instant};
+use std::collections::{HashMap, HashSet};
+
+/// An item of a chain that may be buffered in an [`OrphanPool`]: a message,
+/// or the header of one.
+pub trait Linked {
+	/// Gets the hash of the message.
+	fn hash(&self) -> &Hash;
+
+	/// Gets the hash of the previous message, if there is one.
+	fn prev(&self) -> Option<&Hash>;
+}
+
+impl Linked for Message {
+	fn hash(&self) -> &Hash {
+		Message::hash(self)
+	}
+
+	fn prev(&self) -> Option<&Hash> {
+		self.data().prev()
+	}
+}
+
+impl Linked for MessageHeader {
+	fn hash(&self) -> &Hash {
+		MessageHeader::hash(self)
+	}
+
+	fn prev(&self) -> Option<&Hash> {
+		MessageHeader::prev(self)
+	}
+}
+
+/// A bounded buffer of messages whose previous message is not yet known,
+/// keyed by the hash of the missing message. Messages are dropped after
+/// ORPHAN_EXPIRY milliseconds if their previous message never arrives,
+/// unless the pool was created with a different expiry. Headers may be
+/// buffered in place of messages.
+pub struct OrphanPool<T = Message> {
+	orphans: HashMap<Hash, Vec<Orphan<T>>>,
+	hashes: HashSet<Hash>,
+	capacity: usize,
+	expiry: Duration,
+}
+
+impl<T: Linked> Default for OrphanPool<T> {
+	fn default() -> Self {
+		Self::new(ORPHAN_POOL_SIZE, Duration::from_millis(ORPHAN_EXPIRY))
+	}
+}
+
+// A message waiting for its previous message
+struct Orphan<T> {
+	received_at: Instant,
+	msg: T,
+}
+
+impl<T: Linked> OrphanPool<T> {
+	/// Creates a pool holding at most capacity messages, each for at most
+	/// expiry.
+	pub fn new(capacity: usize, expiry: Duration) -> Self {
+		Self {
+			orphans: HashMap::default(),
+			hashes: HashSet::default(),
+			capacity,
+			expiry,
+		}
+	}
+
+	/// Buffers the message until its previous message arrives. Returns false
+	/// if the message has no previous message, is already buffered, or if
+	/// the pool is full. Expired messages only make room once dropped by
+	/// [`OrphanPool::prune`].
+	pub fn insert(&mut self, msg: T) -> bool {
+		let prev = if let Some(prev) = msg.prev() {
+			prev.clone()
+		} else {
+			return false;
+		};
+
+		if self.hashes.len() >= self.capacity || self.hashes.contains(msg.hash()) {
+			return false;
+		}
+
+		self.hashes.insert(msg.hash().clone());
+		self.orphans.entry(prev).or_default().push(Orphan {
+			received_at: Instant::now(),
+			msg,
+		});
+
+		true
+	}
+
+	/// Removes and returns all buffered messages whose previous message is
+	/// the message with the given hash.
+	pub fn take_children(&mut self, parent: &Hash) -> Vec<T> {
+		let children = self
+			.orphans
+			.remove(parent)
+			.unwrap_or_default()
+			.into_iter()
+			.map(|orphan| orphan.msg)
+			.collect::<Vec<T>>();
+		for child in children.iter() {
+			self.hashes.remove(child.hash());
+		}
+
+		children
+	}
+
+	/// Determines whether the message with the given hash is buffered.
+	pub fn contains(&self, hash: &Hash) -> bool {
+		self.hashes.contains(hash)
+	}
+
+	/// Determines whether any buffered message is waiting on the message
+	/// with the given hash.
+	pub fn is_missing(&self, hash: &Hash) -> bool {
+		self.orphans.contains_key(hash)
+	}
+
+	/// Gets the number of buffered messages.
+	pub fn len(&self) -> usize {
+		self.hashes.len()
+	}
+
+	/// Determines whether no messages are buffered.
+	pub fn is_empty(&self) -> bool {
+		self.hashes.is_empty()
+	}
+
+	/// Drops all buffered messages.
+	pub fn clear(&mut self) {
+		self.orphans.clear();
+		self.hashes.clear();
+	}
+
+	/// Drops all messages that have been buffered for longer than the
+	/// pool's expiry. Returns the hashes of the dropped messages.
+	pub fn prune(&mut self) -> Vec<Hash> {
+		self.prune_older_than(self.expiry)
+	}
+
+	/// Drops all messages that have been buffered for longer than max_age.
+	fn prune_older_than(&mut self, max_age: Duration) -> Vec<Hash> {
+		let now = Instant::now();
+
+		let mut dropped = Vec::new();
+		for siblings in self.orphans.values_mut() {
+			siblings.retain(|orphan| {
+				let fresh = now - orphan.received_at <= max_age;
+				if !fresh {
+					dropped.push(orphan.msg.hash().clone());
+				}
+
+				fresh
+			});
+		}
+
+		self.orphans.retain(|_, siblings| !siblings.is_empty());
+		for hash in dropped.iter() {
+			self.hashes.remove(hash);
+		}
+
+		dropped
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{super::super::sys::msg::MessageData, *};
+	use std::error::Error;
+
+	#[test]
+	fn test_insert() -> Result<(), Box<dyn Error>> {
+		let mut pool = OrphanPool::default();
+
+		// Messages without a predecessor can never be orphans
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		assert!(!pool.insert(root.clone()));
+
+		let child = Message::try_from(MessageData::new(
+			Vec::new(),
+			Some(root.hash().clone()),
+			None,
+			None,
+			1,
+			1,
+			0,
+		))?;
+		assert!(pool.insert(child.clone()));
+		assert!(!pool.insert(child.clone()));
+		assert!(pool.contains(child.hash()));
+		assert!(!pool.contains(root.hash()));
+		assert!(pool.is_missing(root.hash()));
+		assert_eq!(pool.len(), 1);
+
+		assert_eq!(pool.take_children(root.hash()), vec![child]);
+		assert!(!pool.is_missing(root.hash()));
+		assert!(pool.is_empty());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_bounded() -> Result<(), Box<dyn Error>> {
+		let mut pool = OrphanPool::default();
+
+		for i in 0..ORPHAN_POOL_SIZE {
+			let msg = Message::try_from(MessageData::new(
+				Vec::new(),
+				Some([0; 32].into()),
+				None,
+				None,
+				1,
+				i as u128,
+				0,
+			))?;
+			assert!(pool.insert(msg));
+		}
+
+		let msg = Message::try_from(MessageData::new(
+			Vec::new(),
+			Some([1; 32].into()),
+			None,
+			None,
+			1,
+			0,
+			0,
+		))?;
+		assert!(!pool.insert(msg));
+		assert_eq!(pool.len(), ORPHAN_POOL_SIZE);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_prune() -> Result<(), Box<dyn Error>> {
+		let mut pool = OrphanPool::default();
+
+		let msg = Message::try_from(MessageData::new(
+			Vec::new(),
+			Some([0; 32].into()),
+			None,
+			None,
+			1,
+			0,
+			0,
+		))?;
+		let msg_hash = msg.hash().clone();
+		pool.insert(msg);
+
+		assert!(pool.prune().is_empty());
+		assert_eq!(pool.len(), 1);
+
+		assert_eq!(
+			pool.prune_older_than(Duration::ZERO),
+			vec![msg_hash.clone()]
+		);
+		assert!(pool.is_empty());
+		assert!(!pool.is_missing(&[0; 32].into()));
+		assert!(!pool.contains(&msg_hash));
+
+		Ok(())
+	}
+}
@@ -0,0 +1,53 @@
+use super::BAN_THRESHOLD;
+use libp2p::PeerId;
+use std::collections::HashMap;
+
+/// Scores peers by their behavior. Peers start with a score of zero, lose
+/// points for each offense, and are banned once their score reaches
+/// BAN_THRESHOLD.
+#[derive(Default, Debug)]
+pub struct Reputation {
+	scores: HashMap<PeerId, i64>,
+}
+
+impl Reputation {
+	/// Deducts the penalty from the peer's score. Returns true if the peer
+	/// is now banned.
+	pub fn penalize(&mut self, peer: &PeerId, penalty: i64) -> bool {
+		let score = self.scores.entry(*peer).or_default();
+		*score = score.saturating_sub(penalty);
+
+		*score <= BAN_THRESHOLD
+	}
+
+	/// Gets the score of the peer.
+	pub fn score(&self, peer: &PeerId) -> i64 {
+		self.scores.get(peer).copied().unwrap_or_default()
+	}
+
+	/// Determines whether the peer has misbehaved enough to be banned.
+	pub fn is_banned(&self, peer: &PeerId) -> bool {
+		self.score(peer) <= BAN_THRESHOLD
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_penalize() {
+		let mut reputation = Reputation::default();
+		let peer = PeerId::random();
+
+		assert_eq!(reputation.score(&peer), 0);
+		assert!(!reputation.is_banned(&peer));
+
+		assert!(!reputation.penalize(&peer, -BAN_THRESHOLD - 1));
+		assert!(reputation.penalize(&peer, 1));
+		assert!(reputation.is_banned(&peer));
+
+		// Other peers are unaffected
+		assert!(!reputation.is_banned(&PeerId::random()));
+	}
+}
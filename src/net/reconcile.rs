@@ -0,0 +1,330 @@
+use super::{
+	super::{crypto::hash::Hash, sys::rt::Rt},
+	RECONCILE_FANOUT, RECONCILE_LEAF_SIZE, RECONCILE_MAX_BUCKETS, RECONCILE_MAX_HASHES,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// A digest of the messages a peer knows at a range of heights. Two peers
+/// knowing the same messages in the range produce the same bucket.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Bucket {
+	/// The first height in the range
+	pub from_height: usize,
+
+	/// The height after the last height in the range
+	pub to_height: usize,
+
+	/// The number of messages known in the range
+	pub len: usize,
+
+	/// The hash of the sorted hashes of the messages known in the range
+	pub digest: Hash,
+}
+
+impl Bucket {
+	// Determines whether the buckets summarize the same messages
+	fn matches(&self, other: &Bucket) -> bool {
+		self.len == other.len && self.digest == other.digest
+	}
+}
+
+/// A peer's view of a range of heights in which it disagrees with another
+/// peer.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Summary {
+	/// Digests of subranges of a range with too many messages to list
+	Buckets(Vec<Bucket>),
+
+	/// The hashes of all messages known in a range
+	Hashes {
+		from_height: usize,
+		to_height: usize,
+		hashes: Vec<Hash>,
+	},
+}
+
+/// Summarizes the messages known to the runtime with heights from
+/// from_height up to, but not including, to_height.
+pub fn summarize(rt: &Rt, from_height: usize, to_height: usize) -> Bucket {
+	let mut hasher = blake3::Hasher::new();
+	let mut len = 0;
+
+	for hash in rt.messages_between(from_height, to_height) {
+		hasher.update(hash.as_ref());
+		len += 1;
+	}
+
+	Bucket {
+		from_height,
+		to_height,
+		len,
+		digest: hasher.finalize().into(),
+	}
+}
+
+/// Splits the range of heights into at most [`RECONCILE_FANOUT`] buckets.
+/// Heights above any message known to the runtime are left out.
+pub fn split(rt: &Rt, from_height: usize, to_height: usize) -> Vec<Bucket> {
+	let top = rt
+		.max_height()
+		.map(|height| height.saturating_add(1))
+		.unwrap_or(from_height);
+	let to_height = to_height.min(top.max(from_height));
+	if to_height <= from_height {
+		return Vec::new();
+	}
+
+	let span = (to_height - from_height + RECONCILE_FANOUT - 1) / RECONCILE_FANOUT;
+
+	(from_height..to_height)
+		.step_by(span)
+		.map(|bucket_from| summarize(rt, bucket_from, (bucket_from + span).min(to_height)))
+		.collect()
+}
+
+/// Gets the buckets opening a reconciliation: the known chain split by
+/// [`split`], followed by a bucket for every greater height, which the
+/// other peer may know messages at.
+pub fn initial(rt: &Rt) -> Vec<Bucket> {
+	let top = rt
+		.max_height()
+		.map(|height| height.saturating_add(1))
+		.unwrap_or_default();
+
+	let mut buckets = split(rt, 0, top);
+	buckets.push(summarize(rt, top, usize::MAX));
+
+	buckets
+}
+
+/// Compares a peer's buckets against the runtime. Ranges that differ are
+/// answered with the hashes known in them if there are at most
+/// [`RECONCILE_LEAF_SIZE`], or if the range covers a single height, and are
+/// split into smaller buckets otherwise.
+///
+/// At most [`RECONCILE_MAX_HASHES`] hashes are listed in total, such that
+/// ranges listed past the limit are left out or cut short. Returns None if
+/// the peer sent more than [`RECONCILE_MAX_BUCKETS`] buckets, or buckets
+/// which are not sorted by height or which overlap, in which case the
+/// request should be dropped.
+pub fn respond(rt: &Rt, buckets: &[Bucket]) -> Option<Vec<Summary>> {
+	if buckets.len() > RECONCILE_MAX_BUCKETS
+		|| buckets
+			.windows(2)
+			.any(|pair| pair[0].to_height > pair[1].from_height)
+	{
+		return None;
+	}
+
+	let mut budget = RECONCILE_MAX_HASHES;
+	let mut summaries = Vec::new();
+
+	for theirs in buckets
+		.iter()
+		.filter(|theirs| theirs.from_height < theirs.to_height)
+	{
+		let ours = summarize(rt, theirs.from_height, theirs.to_height);
+		if ours.matches(theirs) {
+			continue;
+		}
+
+		if ours.len > RECONCILE_LEAF_SIZE && theirs.to_height - theirs.from_height > 1 {
+			summaries.push(Summary::Buckets(split(
+				rt,
+				theirs.from_height,
+				theirs.to_height,
+			)));
+
+			continue;
+		}
+
+		if budget == 0 {
+			continue;
+		}
+
+		let hashes = rt
+			.messages_between(theirs.from_height, theirs.to_height)
+			.take(budget)
+			.cloned()
+			.collect::<Vec<Hash>>();
+		budget -= hashes.len();
+
+		summaries.push(Summary::Hashes {
+			from_height: theirs.from_height,
+			to_height: theirs.to_height,
+			hashes,
+		});
+	}
+
+	Some(summaries)
+}
+
+/// Compares a peer's summaries against the runtime. Returns the buckets of
+/// the runtime for the ranges which still differ, with which to continue
+/// reconciling, and the hashes of messages the peer knows but the runtime
+/// does not.
+pub fn diff(rt: &Rt, summaries: Vec<Summary>) -> (Vec<Bucket>, Vec<Hash>) {
+	let mut buckets = Vec::new();
+	let mut missing = Vec::new();
+
+	for summary in summaries {
+		match summary {
+			Summary::Buckets(theirs) => {
+				buckets.extend(
+					theirs
+						.into_iter()
+						.filter(|theirs| theirs.from_height < theirs.to_height)
+						.map(|theirs| (summarize(rt, theirs.from_height, theirs.to_height), theirs))
+						.filter(|(ours, theirs)| !ours.matches(theirs))
+						.map(|(ours, _)| ours),
+				);
+			}
+			Summary::Hashes {
+				from_height,
+				to_height,
+				hashes,
+			} => {
+				let known = rt
+					.messages_between(from_height, to_height)
+					.collect::<HashSet<&Hash>>();
+
+				missing.extend(hashes.into_iter().filter(|hash| !known.contains(hash)));
+			}
+		}
+	}
+
+	buckets.truncate(RECONCILE_MAX_BUCKETS);
+
+	(buckets, missing)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{
+		super::{
+			super::sys::msg::{Message, MessageData},
+			RECONCILE_MAX_ROUNDS,
+		},
+		*,
+	};
+	use std::error::Error;
+
+	// Builds a chain of the given length with no data
+	fn chain(len: usize) -> Result<Vec<Message>, Box<dyn Error>> {
+		let mut chain = vec![Message::try_from(MessageData::new(
+			Vec::new(),
+			None,
+			None,
+			None,
+			0,
+			0,
+			0,
+		))?];
+
+		for height in 1..len {
+			let prev = chain[height - 1].hash().clone();
+			chain.push(Message::try_from(MessageData::new(
+				Vec::new(),
+				Some(prev),
+				None,
+				None,
+				height,
+				height as u128,
+				0,
+			))?);
+		}
+
+		Ok(chain)
+	}
+
+	#[test]
+	fn test_summarize() -> Result<(), Box<dyn Error>> {
+		let chain = chain(4)?;
+
+		// Summaries do not depend on the order in which messages arrived
+		let mut a = Rt::default();
+		let mut b = Rt::default();
+		for msg in chain.iter() {
+			a.insert_message(msg.clone());
+		}
+		for msg in chain.iter().rev() {
+			b.insert_message(msg.clone());
+		}
+
+		assert_eq!(summarize(&a, 0, 4), summarize(&b, 0, 4));
+		assert_eq!(summarize(&a, 1, 3).len, 2);
+		assert_eq!(respond(&a, &initial(&b)), Some(Vec::new()));
+
+		b.flush();
+		assert!(!summarize(&a, 0, 4).matches(&summarize(&b, 0, 4)));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_reconcile() -> Result<(), Box<dyn Error>> {
+		let chain = chain(RECONCILE_LEAF_SIZE * 4)?;
+
+		// The peer knows every message, but we miss some of the middle and
+		// more of the end of the chain than can be listed at once
+		let mut peer = Rt::default();
+		let mut rt = Rt::default();
+		for (height, msg) in chain.iter().enumerate() {
+			peer.insert_message(msg.clone());
+
+			if height % 100 != 7 && height < chain.len() - 100 {
+				rt.insert_message(msg.clone());
+			}
+		}
+
+		let mut buckets = initial(&rt);
+		let mut missing = HashSet::new();
+		let mut rounds = 0;
+		while !buckets.is_empty() {
+			let summaries = respond(&peer, &buckets).ok_or("the request was dropped")?;
+			let (next, found) = diff(&rt, summaries);
+			buckets = next;
+			missing.extend(found);
+			rounds += 1;
+
+			assert!(rounds < RECONCILE_MAX_ROUNDS);
+		}
+
+		assert_eq!(
+			missing,
+			chain
+				.iter()
+				.enumerate()
+				.filter(|(height, _)| height % 100 == 7 || *height >= chain.len() - 100)
+				.map(|(_, msg)| msg.hash().clone())
+				.collect::<HashSet<Hash>>()
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_respond_limits() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+		for msg in chain(4)? {
+			rt.insert_message(msg);
+		}
+
+		let bucket = |from_height, to_height| summarize(&Rt::default(), from_height, to_height);
+		assert!(respond(&rt, &[bucket(0, 2), bucket(2, 4)]).is_some());
+
+		// Buckets must be sorted and may not overlap
+		assert_eq!(respond(&rt, &[bucket(2, 4), bucket(0, 2)]), None);
+		assert_eq!(respond(&rt, &[bucket(0, 3), bucket(2, 4)]), None);
+
+		// Nor may there be too many of them
+		let buckets = (0..=RECONCILE_MAX_BUCKETS)
+			.map(|height| bucket(height, height + 1))
+			.collect::<Vec<Bucket>>();
+		assert_eq!(respond(&rt, &buckets), None);
+		assert!(respond(&rt, &buckets[..RECONCILE_MAX_BUCKETS]).is_some());
+
+		Ok(())
+	}
+}
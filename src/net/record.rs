@@ -0,0 +1,112 @@
+use super::{
+	super::{crypto::hash::Hash, sys::msg::Message},
+	chain_namespace,
+};
+use libp2p::kad::{Record, RecordKey};
+use serde_json::Error as SerdeError;
+use std::{
+	error::Error as StdError,
+	fmt::{Display, Error as FmtError, Formatter},
+};
+
+/// The reason a DHT record was refused.
+#[derive(Debug)]
+pub enum Error {
+	/// The record value is not a message
+	Malformed(SerdeError),
+
+	/// The record is not stored under the hash of the message it contains,
+	/// within the namespace of the chain
+	KeyMismatch,
+
+	/// The hash of the message does not match its contents
+	InvalidHash,
+}
+
+impl Display for Error {
+	fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+		match self {
+			Self::Malformed(e) => write!(f, "the record is not a message: {}", e),
+			Self::KeyMismatch => write!(f, "the record key is not the hash of its message"),
+			Self::InvalidHash => write!(f, "the message hash does not match its contents"),
+		}
+	}
+}
+
+impl StdError for Error {
+	fn source(&self) -> Option<&(dyn StdError + 'static)> {
+		match self {
+			Self::Malformed(e) => Some(e),
+			Self::KeyMismatch | Self::InvalidHash => None,
+		}
+	}
+}
+
+impl From<SerdeError> for Error {
+	fn from(e: SerdeError) -> Self {
+		Self::Malformed(e)
+	}
+}
+
+/// Gets the key under which the message with the given hash is stored in
+/// the DHT of the chain with the given ID.
+pub fn record_key(chain_id: usize, hash: &Hash) -> RecordKey {
+	let namespace = format!("{}/", chain_namespace(chain_id));
+
+	RecordKey::new(&[namespace.as_bytes(), hash.as_ref()].concat())
+}
+
+/// Decodes the message stored in a DHT record, ensuring that the record is
+/// keyed by the hash of the message in the chain with the given ID, and
+/// that the hash matches the message's contents.
+pub fn validate_record(record: &Record, chain_id: usize) -> Result<Message, Error> {
+	let msg = serde_json::from_slice::<Message>(record.value.as_slice())?;
+
+	if record.key != record_key(chain_id, msg.hash()) {
+		return Err(Error::KeyMismatch);
+	}
+
+	if msg.data().hashed().ok().as_ref() != Some(msg.hash()) {
+		return Err(Error::InvalidHash);
+	}
+
+	Ok(msg)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{super::super::sys::msg::MessageData, *};
+	use std::error::Error as StdError;
+
+	#[test]
+	fn test_validate_record() -> Result<(), Box<dyn StdError>> {
+		let msg = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		let value = serde_json::to_vec(&msg)?;
+
+		let record = Record::new(record_key(0, msg.hash()), value.clone());
+		assert_eq!(validate_record(&record, 0)?, msg);
+
+		// Records may not be stored under another message's key
+		let other = Message::try_from(MessageData::new(vec![1], None, None, None, 0, 0, 0))?;
+		let poisoned = Record::new(record_key(0, other.hash()), value.clone());
+		assert!(matches!(
+			validate_record(&poisoned, 0),
+			Err(Error::KeyMismatch)
+		));
+
+		// Nor under the key of another chain
+		let foreign = Record::new(record_key(1, msg.hash()), value);
+		assert!(matches!(
+			validate_record(&foreign, 0),
+			Err(Error::KeyMismatch)
+		));
+
+		let garbage = Record::new(record_key(0, msg.hash()), vec![0]);
+		assert!(matches!(
+			validate_record(&garbage, 0),
+			Err(Error::Malformed(_))
+		));
+
+		Ok(())
+	}
+}
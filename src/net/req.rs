@@ -1,4 +1,13 @@
-use super::super::crypto::hash::Hash;
+use super::{
+	super::{
+		crypto::hash::Hash,
+		sys::{
+			msg::{Message, MessageHeader},
+			snapshot::Snapshot,
+		},
+	},
+	reconcile::{Bucket, Summary},
+};
 use serde::{Deserialize, Serialize};
 
 /// A request for some information from a peer.
@@ -6,6 +15,28 @@ use serde::{Deserialize, Serialize};
 pub enum Request {
 	/// Requests the peer for the hash of the longest chain
 	LongestChain { query_round: usize },
+
+	/// Requests up to limit messages of the peer's longest chain, starting
+	/// with the message at from_height
+	GetMessages { from_height: usize, limit: usize },
+
+	/// Requests the message with the given hash, followed by up to limit - 1
+	/// of its ancestors
+	GetAncestors { hash: Hash, limit: usize },
+
+	/// Requests the headers of up to limit messages of the peer's longest
+	/// chain, starting with the message at from_height
+	GetHeaders { from_height: usize, limit: usize },
+
+	/// Requests the messages with the given hashes, including their payloads
+	GetBodies { hashes: Vec<Hash> },
+
+	/// Requests the peer to compare its messages against the given buckets
+	Reconcile { buckets: Vec<Bucket> },
+
+	/// Requests a snapshot of the peer's chain ending at the message with
+	/// the given hash
+	GetSnapshot { hash: Hash },
 }
 
 /// A response for some information from a peer.
@@ -16,4 +47,18 @@ pub enum Response {
 		hash: Hash,
 		query_round: usize,
 	},
+
+	/// Messages in ascending order of height for GetMessages, from the
+	/// requested message back towards the root for GetAncestors, or those
+	/// known to the peer for GetBodies
+	Messages { messages: Vec<Message> },
+
+	/// The headers of the peer's longest chain, in ascending order of height
+	Headers { headers: Vec<MessageHeader> },
+
+	/// The peer's view of the ranges in which it disagrees with the buckets
+	Reconcile { summaries: Vec<Summary> },
+
+	/// The requested snapshot, if the peer has the messages it consists of
+	Snapshot { snapshot: Option<Snapshot> },
 }
@@ -1,38 +1,94 @@
 use super::{
 	super::{
 		crypto::hash::Hash,
-		sys::{msg::Message, rt::Rt},
+		sys::{
+			fork::Head,
+			msg::{Message, MessageHeader},
+			rt::Rt,
+			snapshot::{Error as SnapshotError, Snapshot, TrustedSnapshot},
+		},
 	},
-	behavior::BehaviorEvent,
+	behavior::{Behavior, BehaviorEvent},
+	msg::{self, ConsensusRule, ValidationError},
+	orphan::OrphanPool,
+	reconcile,
+	record::{record_key, validate_record, Error as RecordError},
 	req::{Request, Response},
-	DHT_QUORUM, SAMPLING_SIZE, SYNCHRONIZATION_TIMEOUT,
+	DHT_MAX_INFLIGHT_PUTS, DHT_PUT_RETRY_BASE, DHT_PUT_RETRY_MAX, DHT_QUORUM,
+	DHT_REPUBLISH_INTERVAL, MIN_HEAD_RESPONDERS, RECONCILE_MAX_ROUNDS, SAMPLING_SIZE,
+	SYNCHRONIZATION_TIMEOUT, SYNC_BATCH_SIZE, SYNC_HEADERS_BATCH_SIZE, SYNC_MAX_INFLIGHT_BATCHES,
+	SYNC_MAX_RETRIES, SYNC_PENDING_EXPIRY, SYNC_PENDING_POOL_SIZE,
 };
 use instant::{Duration, Instant};
 use libp2p::{
 	kad::{
-		record::store::Error as KadError, store::MemoryStore, GetRecordOk, Kademlia, KademliaEvent,
-		QueryId, QueryResult, Record, RecordKey,
+		record::store::{Error as KadError, RecordStore},
+		store::MemoryStore,
+		GetRecordOk, InboundRequest, Kademlia, KademliaEvent, QueryId, QueryResult, Record,
+	},
+	request_response::{
+		cbor::Behaviour as RRBehavior, Event as RREvent, Message as RRMessage, RequestId,
 	},
-	request_response::{cbor::Behaviour as RRBehavior, Event as RREvent, Message as RRMessage},
 	PeerId,
 };
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
 use serde_json::Error as SerdeError;
 use std::{
-	collections::{HashMap, HashSet},
+	collections::{HashMap, HashSet, VecDeque},
 	error::Error as StdError,
 	fmt::{Display, Error as FmtError, Formatter},
+	iter,
 };
 
 /// Events emitted by the synchronization behavior
 #[derive(Debug)]
 pub enum Event {
-	/// Emitted when the entire blockchain has been committed
+	/// Emitted when a message has been published to the DHT
 	MessageCommitted(Hash),
 
-	/// Emitted when a message is successfully loaded
+	/// Emitted when a message could not be published to the DHT. It is
+	/// retried with exponential backoff.
+	MessagePublishFailed { hash: Hash, failures: u32 },
+
+	/// Emitted when a downloaded message passes consensus and is inserted
+	/// into the runtime
 	MessageLoaded(Message),
 
-	/// Emitted when the longest chain has been updated
+	/// Emitted when a downloaded header passes consensus and is inserted
+	/// into the runtime, ahead of its payload
+	HeaderLoaded(MessageHeader),
+
+	/// Emitted when a downloaded message is buffered until its previous
+	/// message, which is expected to sit at the given height, is downloaded
+	MessagePending {
+		hash: Hash,
+		prev: Hash,
+		height: usize,
+	},
+
+	/// Emitted when a downloaded message or header fails a consensus check
+	MessageRejected { hash: Hash, error: ValidationError },
+
+	/// Emitted when a sampled peer claimed a HEAD higher than the one agreed
+	/// upon, which turned out not to be at the claimed height. Claims which
+	/// are merely not found yet, and peers lagging behind the agreed HEAD,
+	/// are not reported.
+	HeadDisputed { peer: PeerId, hash: Hash },
+
+	/// Emitted when a peer claimed a HEAD which could not be fetched, or was
+	/// not what the peer claimed it to be
+	HeadClaimRejected { peer: PeerId, hash: Hash },
+
+	/// Emitted when a DHT record served or stored by a peer does not match
+	/// its key
+	RecordRejected {
+		peer: Option<PeerId>,
+		error: RecordError,
+	},
+
+	/// Emitted when a HEAD agreed upon by sampled peers has been fetched and
+	/// verified, and the headers of its chain are being downloaded
 	LongestChainUpdated {
 		/// The hash of the HEAD of the longest chain
 		hash: Hash,
@@ -41,6 +97,18 @@ pub enum Event {
 		height: usize,
 	},
 
+	/// Emitted when the trusted snapshot has been downloaded, verified, and
+	/// restored, along with the application state it carried, if any
+	SnapshotLoaded {
+		height: usize,
+		hash: Hash,
+		state: Option<Vec<u8>>,
+	},
+
+	/// Emitted when a peer served a snapshot which does not match the
+	/// trusted snapshot
+	SnapshotRejected { peer: PeerId, error: SnapshotError },
+
 	/// Emitted when a message is successfully loaded
 	MessageLoadCompleted { msg: Message, req_id: usize },
 
@@ -48,6 +116,41 @@ pub enum Event {
 	MessageLoadFailed { req_id: usize },
 }
 
+/// The stage of the synchronization of the chain with the network.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SyncState {
+	/// No synchronization round is in progress
+	Idle,
+
+	/// Sampled peers are being polled for their HEAD
+	Polling,
+
+	/// The HEAD agreed upon by sampled peers is being fetched and verified
+	Verifying,
+
+	/// The chain of the agreed upon HEAD is being downloaded
+	Downloading,
+
+	/// The chain of the agreed upon HEAD has been downloaded
+	Synced,
+}
+
+/// A snapshot of the progress of the synchronization of the chain.
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncStatus {
+	/// The stage of synchronization
+	pub state: SyncState,
+
+	/// The height of the HEAD of the longest chain
+	pub height: Option<usize>,
+
+	/// The height of the HEAD agreed upon by sampled peers, if any
+	pub target_height: Option<usize>,
+
+	/// The number of times the current synchronization has been retried
+	pub retries: usize,
+}
+
 /// Any error that may occur while synchronizing the blockchain.
 #[derive(Debug)]
 pub enum Error {
@@ -85,64 +188,334 @@ impl From<KadError> for Error {
 /// A NetworkBehavior implementing synchronization utilities including:
 /// - uploading the blockchain
 /// - downloading the blockchain
-#[derive(Default)]
 pub struct Context {
+	// The ID of the chain, under which DHT records are namespaced
+	chain_id: usize,
+
 	// Questioning rounds for synchronization of the longest chain hash
-	chain_downloads: Vec<SynchronizationRequest>,
+	chain_downloads: HashMap<usize, SynchronizationRequest>,
+	next_query_round: usize,
+
+	// The stage of synchronization
+	state: SyncState,
+
+	// The HEAD agreed upon by the last successful round, and its height
+	target: Option<(Hash, usize)>,
 
-	// Upload requests
+	// The number of times the current synchronization has been retried, and
+	// whether it should be retried
+	retries: usize,
+	retry: bool,
+
+	// Publications of messages to the DHT in flight
 	chain_uploads: HashMap<QueryId, Hash>,
 
-	// Download requests
-	message_downloads: HashSet<QueryId>,
+	// The publication state of every message of the chain we have seen
+	publications: HashMap<Hash, Publication>,
+
+	// Messages waiting to be published, in the order they became due
+	publish_queue: VecDeque<Hash>,
+
+	// Download requests, and the hash and height expected of the downloaded
+	// message
+	message_downloads: HashMap<QueryId, (Hash, usize)>,
 
 	// Load requests
 	message_loads: HashMap<QueryId, usize>,
+
+	// Fetches of HEADs agreed upon by peers
+	head_verifications: HashMap<QueryId, HeadVerification>,
+
+	// Ranges of headers of the longest chain being downloaded, and the
+	// peers serving them
+	batches: HashMap<RequestId, (PeerId, Batch)>,
+
+	// Ranges waiting to be requested
+	batch_queue: VecDeque<Batch>,
+
+	// The peers ranges and payloads are requested from, in turn
+	batch_peers: Vec<PeerId>,
+	next_batch_peer: usize,
+
+	// Downloaded headers waiting for the header of their previous message
+	pending_headers: OrphanPool<MessageHeader>,
+
+	// Payloads being downloaded, and the peers serving them
+	body_requests: HashMap<RequestId, (PeerId, Vec<Hash>)>,
+	requested_bodies: HashSet<Hash>,
+
+	// Requests for a message and its ancestors, and the expected height of
+	// the message
+	ancestor_requests: HashMap<RequestId, (Hash, usize)>,
+
+	// Reconciliations of our messages with a peer's, and the number of
+	// requests each has taken
+	reconciliations: HashMap<RequestId, (PeerId, usize)>,
+
+	// Downloaded messages waiting for their ancestors to be downloaded and
+	// validated
+	pending: OrphanPool,
+
+	// The snapshot to begin synchronizing from, whether it has been
+	// restored, and the request for it in flight
+	trusted_snapshot: Option<TrustedSnapshot>,
+	snapshot_restored: bool,
+	snapshot_request: Option<(RequestId, PeerId)>,
+
+	// The application state served along with snapshots at a message
+	snapshot_state: Option<(Hash, Vec<u8>)>,
+}
+
+impl Default for Context {
+	fn default() -> Self {
+		Self {
+			chain_id: 0,
+			chain_downloads: HashMap::default(),
+			next_query_round: 0,
+			state: SyncState::Idle,
+			target: None,
+			retries: 0,
+			retry: false,
+			chain_uploads: HashMap::default(),
+			publications: HashMap::default(),
+			publish_queue: VecDeque::default(),
+			message_downloads: HashMap::default(),
+			message_loads: HashMap::default(),
+			head_verifications: HashMap::default(),
+			batches: HashMap::default(),
+			batch_queue: VecDeque::default(),
+			batch_peers: Vec::default(),
+			next_batch_peer: 0,
+			pending_headers: OrphanPool::new(
+				SYNC_PENDING_POOL_SIZE,
+				Duration::from_millis(SYNC_PENDING_EXPIRY),
+			),
+			body_requests: HashMap::default(),
+			requested_bodies: HashSet::default(),
+			ancestor_requests: HashMap::default(),
+			reconciliations: HashMap::default(),
+			pending: OrphanPool::new(
+				SYNC_PENDING_POOL_SIZE,
+				Duration::from_millis(SYNC_PENDING_EXPIRY),
+			),
+			trusted_snapshot: None,
+			snapshot_restored: false,
+			snapshot_request: None,
+			snapshot_state: None,
+		}
+	}
 }
 
 // The state of a round of questioning regarding the longest chain
 struct SynchronizationRequest {
 	initiated_at: Instant,
 	peers_contacted: Vec<PeerId>,
-	results: Vec<Response>,
+
+	// The HEAD and height claimed by each peer that responded
+	results: HashMap<PeerId, (Hash, usize)>,
+
+	// Whether the round has already been decided
+	concluded: bool,
+}
+
+// The state of the publication of a message to the DHT
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Publication {
+	// When the message was last published successfully, if ever
+	published_at: Option<Instant>,
+
+	// The number of failed attempts since the last successful one
+	failures: u32,
+
+	// When the message should next be published
+	due_at: Instant,
+
+	// Whether the message is queued or being published
+	scheduled: bool,
+}
+
+// A range of headers of the longest chain to download from a peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Batch {
+	from_height: usize,
+	limit: usize,
+}
+
+// A HEAD agreed upon by peers, or claimed above it by a minority of peers,
+// which is being fetched for verification
+struct HeadVerification {
+	hash: Hash,
+	height: usize,
+
+	// The peers that claimed the HEAD
+	claimants: Vec<PeerId>,
+
+	// Whether the HEAD disputes the agreed HEAD, rather than being it
+	disputed: bool,
+}
+
+impl HeadVerification {
+	// Reports every peer that claimed the HEAD, once it failed verification
+	fn reject(self) -> Vec<Event> {
+		let hash = self.hash;
+		let disputed = self.disputed;
+
+		self.claimants
+			.into_iter()
+			.map(|peer| {
+				if disputed {
+					Event::HeadDisputed {
+						peer,
+						hash: hash.clone(),
+					}
+				} else {
+					Event::HeadClaimRejected {
+						peer,
+						hash: hash.clone(),
+					}
+				}
+			})
+			.collect()
+	}
 }
 
 impl Context {
 	/// Checks the status of requested operations on the context, and returns
-	/// the appropriate event if an operation was completed.
+	/// the appropriate events if an operation was completed. Downloaded
+	/// messages are checked against the consensus rules in ancestor order
+	/// before they are inserted into the runtime.
 	pub fn poll(
 		&mut self,
 		rt: &mut Rt,
-		request_response: &mut RRBehavior<Request, Response>,
+		consensus_rule: ConsensusRule,
+		behavior: &mut Behavior,
 		in_event: Option<BehaviorEvent>,
-	) -> (Result<Option<Event>, Error>, Option<BehaviorEvent>) {
+	) -> (Result<Vec<Event>, Error>, Option<BehaviorEvent>) {
 		match in_event {
 			Some(BehaviorEvent::Kad(e)) => match e {
-				KademliaEvent::OutboundQueryProgressed { id, result, .. } => {
-					// If the user requested to upload the chain, notify them that
-					// it was successful
-					if let Some(msg_hash) = self.chain_uploads.get(&id).cloned() {
-						self.chain_uploads.remove(&id);
-
-						if let QueryResult::PutRecord(Ok(_)) = result {
-							return (Ok(Some(Event::MessageCommitted(msg_hash.clone()))), None);
-						}
+				KademliaEvent::OutboundQueryProgressed {
+					id, result, step, ..
+				} => {
+					// A message was published, or failed to be. Schedule its
+					// next publication, and publish the next queued message
+					if let Some(hash) = self.chain_uploads.remove(&id) {
+						let event = match result {
+							QueryResult::PutRecord(Ok(_)) => {
+								self.published(&hash);
+
+								Event::MessageCommitted(hash)
+							}
+							_ => {
+								let failures = self.publish_failed(&hash);
+
+								Event::MessagePublishFailed { hash, failures }
+							}
+						};
+
+						let mut events = vec![event];
+						events.extend(self.dispatch_puts(rt, behavior.kad_mut()));
+
+						return (Ok(events), None);
+					}
+
+					// We previously requested to verify a HEAD agreed upon by our
+					// peers. Only download its chain if it is what they claimed
+					if let Some(verification) = self.head_verifications.remove(&id) {
+						let record = match result {
+							QueryResult::GetRecord(Ok(GetRecordOk::FoundRecord(record))) => record,
+							_ => return (Ok(self.abandon_head(verification)), None),
+						};
+
+						return match validate_record(&record.record, self.chain_id) {
+							// The disputed HEAD is genuine, and is caught up
+							// with in a later round
+							Ok(msg)
+								if msg.data().height() == verification.height
+									&& verification.disputed =>
+							{
+								(Ok(Vec::new()), None)
+							}
+							Ok(msg) if msg.data().height() == verification.height => {
+								// Download the headers between our HEAD and theirs
+								// in batches from the peers that agreed on it.
+								// Payloads follow once the headers connect.
+								let from_height = self.sync_from(rt);
+								self.download_range(
+									behavior.request_response_mut(),
+									verification.claimants.iter().collect(),
+									from_height,
+									verification.height,
+								);
+
+								self.state = SyncState::Downloading;
+
+								let mut events = vec![Event::LongestChainUpdated {
+									hash: verification.hash,
+									height: verification.height,
+								}];
+								events.extend(self.accept_header(rt, msg.header()));
+								self.fetch_bodies(rt, behavior.request_response_mut());
+
+								(Ok(events), None)
+							}
+							Ok(_) => (Ok(self.reject_head(verification)), None),
+							Err(error) => {
+								let rejection = Event::RecordRejected {
+									peer: record.peer,
+									error,
+								};
+
+								// Wait for another peer to serve an honest record
+								if !step.last {
+									self.head_verifications.insert(id, verification);
+
+									return (Ok(vec![rejection]), None);
+								}
+
+								let mut events = vec![rejection];
+								events.extend(self.abandon_head(verification));
+
+								(Ok(events), None)
+							}
+						};
 					}
 
 					// We previously requested to download a message.
 					// Use the according event type
-					if self.message_downloads.contains(&id) {
+					if let Some((hash, expected_height)) = self.message_downloads.remove(&id) {
 						// We successfully found the message
 						if let QueryResult::GetRecord(Ok(GetRecordOk::FoundRecord(record))) = result
 						{
-							if let Ok(msg) =
-								serde_json::from_slice::<Message>(record.record.value.as_slice())
-							{
-								rt.insert_message(msg.clone());
+							let msg = match validate_record(&record.record, self.chain_id) {
+								Ok(msg) => msg,
+								Err(error) => {
+									// Wait for another peer to serve an honest record
+									if !step.last {
+										self.message_downloads.insert(id, (hash, expected_height));
+									}
+
+									return (
+										Ok(vec![Event::RecordRejected {
+											peer: record.peer,
+											error,
+										}]),
+										None,
+									);
+								}
+							};
+
+							// Stop walking the chain if the message lies about its height
+							if !follows_height_rule(rt, &msg, Some(expected_height)) {
+								error!(
+									"Rejecting downloaded message {} with invalid height {}",
+									hex::encode(msg.hash()),
+									msg.data().height()
+								);
 
-								// Notify the user that the message was found
-								return (Ok(Some(Event::MessageLoaded(msg))), None);
+								return (Ok(Vec::new()), None);
 							}
+
+							return (Ok(self.accept(rt, consensus_rule, msg)), None);
 						}
 					} else if let Some(req_id) = self.message_loads.remove(&id) {
 						// We previously requested to load a message.
@@ -151,221 +524,2031 @@ impl Context {
 						// We successfully found the message
 						match result {
 							QueryResult::GetRecord(Ok(GetRecordOk::FoundRecord(record))) => {
-								if let Ok(msg) = serde_json::from_slice::<Message>(
-									record.record.value.as_slice(),
-								) {
-									rt.insert_message(msg.clone());
+								let msg = match validate_record(&record.record, self.chain_id) {
+									Ok(msg) => msg,
+									Err(error) => {
+										// Wait for another peer to serve an honest record
+										if !step.last {
+											self.message_loads.insert(id, req_id);
 
-									// Notify the user that the message was found
-									return (
-										Ok(Some(Event::MessageLoadCompleted { msg, req_id })),
-										None,
+											return (
+												Ok(vec![Event::RecordRejected {
+													peer: record.peer,
+													error,
+												}]),
+												None,
+											);
+										}
+
+										return (
+											Ok(vec![
+												Event::RecordRejected {
+													peer: record.peer,
+													error,
+												},
+												Event::MessageLoadFailed { req_id },
+											]),
+											None,
+										);
+									}
+								};
+
+								if !follows_height_rule(rt, &msg, None) {
+									error!(
+										"Rejecting loaded message {} with invalid height {}",
+										hex::encode(msg.hash()),
+										msg.data().height()
 									);
+
+									return (Ok(vec![Event::MessageLoadFailed { req_id }]), None);
+								}
+
+								// The message is handed to the user even if its
+								// history is not yet known, but only enters the
+								// runtime once it has been validated
+								let mut events = self.accept(rt, consensus_rule, msg.clone());
+								let rejected = events.iter().any(
+									|e| matches!(e, Event::MessageRejected { hash, .. } if hash == msg.hash()),
+								);
+
+								// Light clients keep the payloads they ask for
+								if rt.is_light() && rt.get_header(msg.hash()).is_some() {
+									rt.insert_message(msg.clone());
 								}
+
+								events.push(if rejected {
+									Event::MessageLoadFailed { req_id }
+								} else {
+									Event::MessageLoadCompleted { msg, req_id }
+								});
+
+								return (Ok(events), None);
 							}
 							_ => {}
 						}
 
-						return (Ok(Some(Event::MessageLoadFailed { req_id })), None);
+						return (Ok(vec![Event::MessageLoadFailed { req_id }]), None);
 					}
 
-					(Ok(None), None)
+					(Ok(Vec::new()), None)
 				}
-				_ => (Ok(None), None),
+				// Only store records from other peers if they are keyed by
+				// the hash of the message they contain
+				KademliaEvent::InboundRequest {
+					request:
+						InboundRequest::PutRecord {
+							source,
+							record: Some(record),
+							..
+						},
+				} => match validate_record(&record, self.chain_id) {
+					Ok(_) => (
+						behavior
+							.kad_mut()
+							.store_mut()
+							.put(record)
+							.map(|_| Vec::new())
+							.map_err(Error::KadError),
+						None,
+					),
+					Err(error) => (
+						Ok(vec![Event::RecordRejected {
+							peer: Some(source),
+							error,
+						}]),
+						None,
+					),
+				},
+				_ => (Ok(Vec::new()), None),
 			},
 			Some(BehaviorEvent::Rresponse(e)) => match e {
-				RREvent::Message { message, .. } => match message {
+				RREvent::Message { peer, message } => match message {
 					RRMessage::Request {
 						request, channel, ..
-					} => match request {
-						// A peer asked for the hash of the longest chain. Find it, and if it
-						// exists, respond
-						Request::LongestChain { query_round } => {
-							debug!("longest chain requested");
-
-							if let Some(longest_chain) =
-								rt.longest_chain().and_then(|hash| rt.get_message(hash))
-							{
-								let _ = request_response.send_response(
-									channel,
-									Response::LongestChain {
-										hash: longest_chain.hash().clone(),
-										height: longest_chain.data().height(),
-										query_round,
-									},
-								);
-							}
-
-							(Ok(None), None)
+					} => {
+						// Requests which cannot be served are dropped
+						if let Some(response) = self.respond(rt, &peer, request) {
+							let _ = behavior
+								.request_response_mut()
+								.send_response(channel, response);
 						}
-					},
-					RRMessage::Response { response, .. } => match response {
-						// A peer gave us the longest chain.
-						// Add it to the list of results, and
-						// if we have passed 50% of sampled
-						// peers, emit the event
+
+						(Ok(Vec::new()), None)
+					}
+					RRMessage::Response {
+						request_id,
+						response,
+					} => match response {
+						// A peer gave us the longest chain. Record its claim,
+						// and check whether the sampled peers agree on a HEAD
 						Response::LongestChain {
 							hash,
 							height,
 							query_round,
 						} => {
-							if let Some(query_data) = self.chain_downloads.get_mut(query_round) {
-								// Ensure that the query has not expired
-								if Instant::now() - query_data.initiated_at
-									< Duration::from_millis(SYNCHRONIZATION_TIMEOUT)
-								{
-									query_data.results.push(Response::LongestChain {
-										hash,
-										height,
-										query_round,
-									});
-
-									// Check if we have passed quorum, and if we have, notify the user
-									// of the new HEAD
-									if query_data.results.len() as f32
-										> query_data.peers_contacted.len() as f32 * SAMPLING_SIZE
-									{
-										// Get the response with the longest chain
-										let mut longest_chain = query_data
-											.results
-											.iter()
-											.filter_map(|msg| match msg {
-												Response::LongestChain { hash, height, .. } => {
-													Some((hash.clone(), height.clone()))
-												}
-											})
-											.collect::<Vec<(Hash, usize)>>();
-										longest_chain.sort_by_key(|msg| msg.1);
-
-										let (hash, height) =
-											longest_chain.remove(longest_chain.len() - 1);
+							let query_data = match self.chain_downloads.get_mut(&query_round) {
+								Some(query_data) => query_data,
+								None => return (Ok(Vec::new()), None),
+							};
 
-										return (
-											Ok(Some(Event::LongestChainUpdated { height, hash })),
-											None,
-										);
-									}
+							// Only count the first response of each sampled peer
+							// to rounds which have not expired
+							if query_data.concluded
+								|| Instant::now() - query_data.initiated_at
+									>= Duration::from_millis(SYNCHRONIZATION_TIMEOUT)
+								|| !query_data.peers_contacted.contains(&peer)
+								|| query_data.results.contains_key(&peer)
+							{
+								return (Ok(Vec::new()), None);
+							}
+
+							query_data.results.insert(peer, (hash, height));
+
+							self.tally(rt, behavior.kad_mut(), query_round);
+
+							(Ok(Vec::new()), None)
+						}
+						// A peer sent us payloads, or a message's history
+						Response::Messages { messages } => {
+							let mut events = Vec::new();
+
+							if let Some((_, hashes)) = self.body_requests.remove(&request_id) {
+								for hash in &hashes {
+									self.requested_bodies.remove(hash);
+								}
+
+								// Only accept what we asked for, in ancestor order
+								let mut messages = messages
+									.into_iter()
+									.filter(|msg| hashes.contains(msg.hash()))
+									.collect::<Vec<Message>>();
+								messages.sort_by_key(|msg| msg.data().height());
+
+								for msg in messages {
+									events.extend(self.accept(rt, consensus_rule, msg));
+								}
+							} else if self.ancestor_requests.remove(&request_id).is_some() {
+								for msg in messages.into_iter().take(SYNC_BATCH_SIZE).rev() {
+									events.extend(self.accept(rt, consensus_rule, msg));
+								}
+							}
+
+							self.fetch_bodies(rt, behavior.request_response_mut());
+
+							(Ok(events), None)
+						}
+						// A peer sent us a range of headers of its chain
+						Response::Headers { headers } => {
+							let mut events = Vec::new();
+
+							if let Some((_, batch)) = self.batches.remove(&request_id) {
+								// Only accept what we asked for, in ancestor order
+								for header in headers.into_iter().filter(|header| {
+									(batch.from_height..batch.from_height + batch.limit)
+										.contains(&header.height())
+								}) {
+									events.extend(self.accept_header(rt, header));
+								}
+
+								self.dispatch_batches(behavior.request_response_mut());
+								self.fetch_bodies(rt, behavior.request_response_mut());
+							}
+
+							(Ok(events), None)
+						}
+						// A peer told us where our messages differ. Fetch the
+						// messages we lack, and narrow down the rest
+						Response::Reconcile { summaries } => {
+							let (peer, rounds) = match self.reconciliations.remove(&request_id) {
+								Some(reconciliation) => reconciliation,
+								None => return (Ok(Vec::new()), None),
+							};
+
+							let (buckets, missing) = reconcile::diff(rt, summaries);
+							if !missing.is_empty() {
+								info!(
+									"found {} messages missing from peer {}",
+									missing.len(),
+									peer
+								);
+							}
+							self.fetch_missing(behavior.request_response_mut(), &peer, missing);
+
+							if !buckets.is_empty() && rounds + 1 < RECONCILE_MAX_ROUNDS {
+								let request_id = behavior
+									.request_response_mut()
+									.send_request(&peer, Request::Reconcile { buckets });
+								self.reconciliations.insert(request_id, (peer, rounds + 1));
+							}
+
+							(Ok(Vec::new()), None)
+						}
+						// A peer sent us the snapshot we asked for. Restore it if
+						// it is what we trust, and continue syncing from it
+						Response::Snapshot { snapshot } => {
+							match self.snapshot_request {
+								Some((id, _)) if id == request_id => self.snapshot_request = None,
+								_ => return (Ok(Vec::new()), None),
+							}
+
+							let trusted = match &self.trusted_snapshot {
+								Some(trusted) if !self.snapshot_restored => trusted.clone(),
+								_ => return (Ok(Vec::new()), None),
+							};
+
+							let mut snapshot = match snapshot {
+								Some(snapshot) => snapshot,
+								None => {
+									debug!("peer {} has no snapshot", peer);
+
+									return (Ok(Vec::new()), None);
 								}
+							};
+
+							if let Err(error) = snapshot.verify(&trusted, rt.lookback_factor()) {
+								warn!("Rejecting snapshot from peer {}: {}", peer, error);
+
+								return (Ok(vec![Event::SnapshotRejected { peer, error }]), None);
+							}
+
+							info!(
+								"restored snapshot {} at height {}",
+								hex::encode(&trusted.hash),
+								trusted.height
+							);
+							let state = snapshot.restore(rt);
+							self.snapshot_restored = true;
+							if let Some(state) = &state {
+								self.snapshot_state = Some((trusted.hash.clone(), state.clone()));
+							}
+
+							let mut events = vec![Event::SnapshotLoaded {
+								height: trusted.height,
+								hash: trusted.hash.clone(),
+								state,
+							}];
+
+							// Connect whatever was downloaded while the snapshot
+							// was awaited
+							events.extend(self.connect_headers(rt, &trusted.hash));
+							for child in self.pending.take_children(&trusted.hash) {
+								events.extend(self.accept(rt, consensus_rule, child));
 							}
+							self.fetch_bodies(rt, behavior.request_response_mut());
 
-							(Ok(None), None)
+							(Ok(events), None)
 						}
 					},
 				},
-				_ => (Ok(None), None),
+				// Retry failed ranges and payloads with the remaining peers,
+				// and fall back to the DHT for failed ancestor downloads
+				RREvent::OutboundFailure {
+					peer, request_id, ..
+				} => {
+					if let Some((_, batch)) = self.batches.remove(&request_id) {
+						self.batch_peers.retain(|batch_peer| batch_peer != &peer);
+						self.batch_queue.push_front(batch);
+						self.dispatch_batches(behavior.request_response_mut());
+					} else if let Some((_, hashes)) = self.body_requests.remove(&request_id) {
+						for hash in &hashes {
+							self.requested_bodies.remove(hash);
+						}
+
+						self.batch_peers.retain(|batch_peer| batch_peer != &peer);
+						self.fetch_bodies(rt, behavior.request_response_mut());
+					} else if self.reconciliations.remove(&request_id).is_some() {
+						debug!("reconciliation with peer {} failed", peer);
+					} else if matches!(self.snapshot_request, Some((id, _)) if id == request_id) {
+						debug!("snapshot request to peer {} failed", peer);
+						self.snapshot_request = None;
+					} else if let Some((hash, height)) = self.ancestor_requests.remove(&request_id)
+					{
+						if let Err(e) = self.download_msg(&hash, height, behavior.kad_mut()) {
+							return (Err(e), None);
+						}
+					}
+
+					(Ok(Vec::new()), None)
+				}
+				_ => (Ok(Vec::new()), None),
 			},
-			_ => (Ok(None), in_event),
+			_ => (Ok(Vec::new()), in_event),
 		}
 	}
 
-	/// Commits all transactions in the client's blockchain to the DHT.
-	pub fn upload_chain(&mut self, rt: &Rt, kad: &mut Kademlia<MemoryStore>) -> Result<(), Error> {
-		// Only continue synchronizing if some chain data exists
-		let longest_chain = if let Some(h) = rt.longest_chain() {
-			h
-		} else {
-			return Ok(());
-		};
+	// Builds the response to a request from the peer, or returns None if the
+	// request cannot be served and should be dropped. Full nodes sync their
+	// own chain headers first, but still serve GetMessages to peers which
+	// download ranges of the chain with their payloads.
+	fn respond(&self, rt: &Rt, peer: &PeerId, request: Request) -> Option<Response> {
+		match request {
+			// A peer asked for the hash of the longest chain. Find it, and if it
+			// exists, respond
+			Request::LongestChain { query_round } => {
+				debug!("longest chain requested");
 
-		info!("committing chain {} to DHT", hex::encode(longest_chain));
+				rt.head_header()
+					.map(|longest_chain| Response::LongestChain {
+						hash: longest_chain.hash().clone(),
+						height: longest_chain.height(),
+						query_round,
+					})
+			}
+			// A peer asked for a range of the longest chain
+			Request::GetMessages { from_height, limit } => {
+				let messages = rt
+					.chain_range(from_height, limit.min(SYNC_BATCH_SIZE))
+					.into_iter()
+					.cloned()
+					.collect();
 
-		// Write the message with hash has to the blockchain, as well as its predecessor
-		fn upload_chain_from(
-			this: &mut Context,
-			rt: &Rt,
-			curr: &Hash,
-			kad: &mut Kademlia<MemoryStore>,
-		) -> Result<(), Error> {
-			let msg = rt.get_message(&curr).ok_or(Error::MissingMessage)?;
-			let msg_bytes = serde_json::to_vec(&msg).map_err(|e| Error::SerializationError(e))?;
+				Some(Response::Messages { messages })
+			}
+			// A peer asked for a message and its history
+			Request::GetAncestors { hash, limit } => {
+				let messages = rt
+					.get_message(&hash)
+					.into_iter()
+					.chain(
+						rt.ancestors(&hash)
+							.map_while(|header| rt.get_message(header.hash())),
+					)
+					.take(limit.min(SYNC_BATCH_SIZE))
+					.cloned()
+					.collect();
 
-			println!("{}", msg_bytes.len());
+				Some(Response::Messages { messages })
+			}
+			// A peer asked for the headers making up a range of the
+			// longest chain
+			Request::GetHeaders { from_height, limit } => {
+				let headers = rt
+					.header_range(from_height, limit.min(SYNC_HEADERS_BATCH_SIZE))
+					.into_iter()
+					.cloned()
+					.collect();
 
-			// Write the transaction under its hash with its JSON serialization to the DHT
-			let q_id = kad.put_record(
-				Record::new(RecordKey::new(&msg.hash().as_ref()), msg_bytes),
-				DHT_QUORUM,
-			)?;
-			debug!(
-				"writing message {} to KAD DHT in query {:?}",
-				hex::encode(msg.hash().as_ref()),
-				q_id
-			);
-			this.chain_uploads.insert(q_id, msg.hash().clone());
+				Some(Response::Headers { headers })
+			}
+			// A peer asked for the payloads of some messages
+			Request::GetBodies { hashes } => {
+				let messages = hashes
+					.iter()
+					.take(SYNC_BATCH_SIZE)
+					.filter_map(|hash| rt.get_message(hash))
+					.cloned()
+					.collect();
 
-			if let Some(prev) = msg.data().prev() {
-				upload_chain_from(this, rt, prev, kad)
-			} else {
-				Ok(())
+				Some(Response::Messages { messages })
+			}
+			// A peer asked for a snapshot of the chain
+			Request::GetSnapshot { hash } => {
+				let state = self
+					.snapshot_state
+					.as_ref()
+					.filter(|(state_hash, _)| state_hash == &hash)
+					.map(|(_, state)| state.clone());
+				let snapshot = Snapshot::from_rt(rt, &hash, state);
+
+				Some(Response::Snapshot { snapshot })
 			}
+			// A peer asked which of its messages we disagree on. Requests
+			// exceeding the limits are dropped
+			Request::Reconcile { buckets } => match reconcile::respond(rt, &buckets) {
+				Some(summaries) => Some(Response::Reconcile { summaries }),
+				None => {
+					warn!(
+						"Dropping oversized or malformed reconciliation request from {}",
+						peer
+					);
+
+					None
+				}
+			},
+		}
+	}
+
+	// Reports the peers that claimed the HEAD once it failed verification.
+	// Synchronization only fails if the HEAD was the agreed HEAD.
+	fn reject_head(&mut self, verification: HeadVerification) -> Vec<Event> {
+		if !verification.disputed {
+			self.fail();
 		}
 
-		upload_chain_from(self, rt, longest_chain, kad)
+		verification.reject()
 	}
 
-	/// Initiates a chain synchronization round. Results are reported through the LongestChainUpdated event.
-	pub fn download_head(
-		&mut self,
-		request_response: &mut RRBehavior<Request, Response>,
-		sampling_pool: Vec<&PeerId>,
-	) -> Result<(), Error> {
-		info!("polling {} peers for head", sampling_pool.len());
+	// Gives up on a HEAD which could not be fetched for verification. A
+	// disputed HEAD may be genuine but not yet published to the DHT, so its
+	// peers are left alone, and only proven false claims are reported.
+	fn abandon_head(&mut self, verification: HeadVerification) -> Vec<Event> {
+		if verification.disputed {
+			return Vec::new();
+		}
 
-		// Contact x% of peers
-		let n_peers = sampling_pool.len();
-		let to_contact = sampling_pool
-			.into_iter()
-			.take((n_peers as f32 * SAMPLING_SIZE).ceil() as usize);
+		self.reject_head(verification)
+	}
 
-		// Take note of which round of questioning this is
-		let entry = SynchronizationRequest {
-			initiated_at: Instant::now(),
-			peers_contacted: to_contact
-				.clone()
-				.map(Clone::clone)
-				.collect::<Vec<PeerId>>(),
-			results: Vec::new(),
+	// Determines whether the peers sampled in the round agree on a HEAD, and
+	// if so, begins fetching it for verification. At least
+	// MIN_HEAD_RESPONDERS peers, or every sampled peer if fewer were sampled,
+	// must respond. A HEAD is agreed upon once it is claimed by a strict
+	// majority of them. Otherwise, once every sampled peer has responded,
+	// the fork-choice rule decides between the claims backed by the most
+	// peers. Claims above the agreed HEAD are verified as well, and their
+	// peers reported if the claims are proven false.
+	fn tally(&mut self, rt: &Rt, kad: &mut Kademlia<MemoryStore>, query_round: usize) {
+		let query_data = match self.chain_downloads.get_mut(&query_round) {
+			Some(query_data) => query_data,
+			None => return,
 		};
-		let entry_id = self.chain_downloads.len();
-		self.chain_downloads.push(entry);
 
-		// Request the longest chain from each peer
-		for peer in to_contact {
-			debug!("polling peer {} for head", peer);
+		let n_responses = query_data.results.len();
+		if n_responses < MIN_HEAD_RESPONDERS.min(query_data.peers_contacted.len()) {
+			return;
+		}
 
-			request_response.send_request(
-				peer,
-				Request::LongestChain {
-					query_round: entry_id,
+		// Count the peers backing each claim, refusing forks contradicting
+		// our checkpoints or history
+		let mut votes: HashMap<(&Hash, usize), usize> = HashMap::new();
+		for (hash, height) in query_data.results.values() {
+			if !rt.contradicts_checkpoint(hash, *height) && !rt.exceeds_reorg_depth(hash, *height) {
+				*votes.entry((hash, *height)).or_default() += 1;
+			}
+		}
+
+		let majority = votes
+			.iter()
+			.find(|(_, n_votes)| *n_votes * 2 > n_responses)
+			.map(|(claim, _)| *claim);
+		let agreed = match majority {
+			Some(claim) => Some(claim),
+			None if n_responses == query_data.peers_contacted.len() => {
+				let most_votes = votes.values().max().copied().unwrap_or_default();
+
+				votes
+					.iter()
+					.filter(|(_, n_votes)| **n_votes == most_votes)
+					.map(|(claim, _)| *claim)
+					.max_by(|(a, a_height), (b, b_height)| {
+						rt.fork_choice().compare(
+							rt,
+							Head {
+								hash: a,
+								height: *a_height,
+							},
+							Head {
+								hash: b,
+								height: *b_height,
+							},
+						)
+					})
+			}
+			None => None,
+		}
+		.map(|(hash, height)| (hash.clone(), height));
+
+		let (hash, height) = match agreed {
+			Some(agreed) => agreed,
+			None => {
+				// The remaining peers may still settle the vote
+				if n_responses == query_data.peers_contacted.len() {
+					warn!("sampled peers failed to agree on a HEAD");
+					query_data.concluded = true;
+					self.fail();
+				}
+
+				return;
+			}
+		};
+		query_data.concluded = true;
+		self.target = Some((hash.clone(), height));
+
+		info!(
+			"{} of {} sampled peers agreed on HEAD {}",
+			query_data
+				.results
+				.values()
+				.filter(|claim| claim.0 == hash && claim.1 == height)
+				.count(),
+			n_responses,
+			hex::encode(&hash)
+		);
+
+		// Peers lagging behind the agreed HEAD are not at fault, but those
+		// claiming to be ahead of it must back their claims
+		let mut claimants = Vec::new();
+		let mut disputes: HashMap<(Hash, usize), Vec<PeerId>> = HashMap::new();
+		for (peer, (claimed, claimed_height)) in query_data.results.iter() {
+			if claimed == &hash && *claimed_height == height {
+				claimants.push(*peer);
+			} else if *claimed_height > height && rt.get_header(claimed).is_none() {
+				disputes
+					.entry((claimed.clone(), *claimed_height))
+					.or_default()
+					.push(*peer);
+			}
+		}
+
+		for ((disputed, disputed_height), disputers) in disputes {
+			let q_id = kad.get_record(record_key(self.chain_id, &disputed));
+			self.head_verifications.insert(
+				q_id,
+				HeadVerification {
+					hash: disputed,
+					height: disputed_height,
+					claimants: disputers,
+					disputed: true,
 				},
 			);
 		}
 
-		Ok(())
-	}
+		// Nothing needs to be fetched if we already have the HEAD. Its
+		// payloads, if missing, are fetched in the background.
+		if rt.get_header(&hash).is_some() {
+			self.state = if rt.is_light() || rt.is_complete(&hash) {
+				SyncState::Synced
+			} else {
+				SyncState::Downloading
+			};
+			self.batch_peers = claimants;
 
-	/// Initiates a download of the chain with HEAD head.
-	pub fn download_msg(
-		&mut self,
-		head: &Hash,
-		kad: &mut Kademlia<MemoryStore>,
-	) -> Result<(), Error> {
-		let q_id = kad.get_record(RecordKey::new(&head.as_ref()));
-		self.message_downloads.insert(q_id);
+			return;
+		}
 
-		Ok(())
+		self.state = SyncState::Verifying;
+
+		let q_id = kad.get_record(record_key(self.chain_id, &hash));
+		self.head_verifications.insert(
+			q_id,
+			HeadVerification {
+				hash,
+				height,
+				claimants,
+				disputed: false,
+			},
+		);
 	}
 
-	/// Initiates a download of the singular message.
-	pub fn load_msg(
-		&mut self,
-		msg: &Hash,
-		kad: &mut Kademlia<MemoryStore>,
+	// Validates and inserts a downloaded message, along with any buffered
+	// descendants it connects, in ancestor order. Messages whose previous
+	// message is not yet known are buffered until it is downloaded.
+	fn accept(&mut self, rt: &mut Rt, consensus_rule: ConsensusRule, msg: Message) -> Vec<Event> {
+		let hash = msg.hash().clone();
+		if self.pending.contains(&hash) {
+			return Vec::new();
+		}
+
+		match msg::validate(rt, &msg, consensus_rule) {
+			Ok(_) => {}
+			Err(ValidationError::UnknownParent) => {
+				let prev = msg.data().prev().cloned();
+				let height = msg.data().height().checked_sub(1);
+
+				if let (Some(prev), Some(height)) = (prev, height) {
+					// Only ask for the previous message if it isn't already
+					// on its way
+					let awaited = self.pending.contains(&prev)
+						|| self.requested_bodies.contains(&prev)
+						|| self.is_scheduled(height);
+
+					self.pending.prune();
+					if self.pending.insert(msg) {
+						if awaited {
+							return Vec::new();
+						}
+
+						return vec![Event::MessagePending { hash, prev, height }];
+					}
+				}
+
+				let error = ValidationError::UnknownParent;
+				warn!(
+					"Rejecting downloaded message {}: {}",
+					hex::encode(&hash),
+					error
+				);
+
+				return vec![Event::MessageRejected { hash, error }];
+			}
+			Err(error) => {
+				warn!(
+					"Rejecting downloaded message {}: {}",
+					hex::encode(&hash),
+					error
+				);
+
+				return vec![Event::MessageRejected { hash, error }];
+			}
+		}
+
+		let mut events = self.insert(rt, msg);
+
+		// Validate the descendants waiting on the message, now that it is known
+		let mut to_connect = vec![hash];
+		while let Some(parent) = to_connect.pop() {
+			for child in self.pending.take_children(&parent) {
+				let hash = child.hash().clone();
+
+				if let Err(error) = msg::validate(rt, &child, consensus_rule) {
+					warn!(
+						"Rejecting downloaded message {}: {}",
+						hex::encode(&hash),
+						error
+					);
+					events.push(Event::MessageRejected { hash, error });
+
+					continue;
+				}
+
+				events.extend(self.insert(rt, child));
+				to_connect.push(hash);
+			}
+		}
+
+		events
+	}
+
+	// Inserts a validated message, connecting any headers waiting on it.
+	// Light clients only keep the header of messages they download.
+	fn insert(&mut self, rt: &mut Rt, msg: Message) -> Vec<Event> {
+		let hash = msg.hash().clone();
+
+		if rt.is_light() {
+			rt.insert_header(msg.header());
+		} else {
+			rt.insert_message(msg.clone());
+		}
+
+		let mut events = vec![Event::MessageLoaded(msg)];
+		events.extend(self.connect_headers(rt, &hash));
+
+		events
+	}
+
+	// Validates and inserts a downloaded header, along with any buffered
+	// descendants it connects. Headers whose previous header is not yet
+	// known are buffered until it is downloaded.
+	fn accept_header(&mut self, rt: &mut Rt, header: MessageHeader) -> Vec<Event> {
+		let hash = header.hash().clone();
+		if self.pending_headers.contains(&hash) {
+			return Vec::new();
+		}
+
+		match msg::validate_header(rt, &header) {
+			Ok(_) => {}
+			Err(ValidationError::UnknownParent) => {
+				let prev = header.prev().cloned();
+				let height = header.height().checked_sub(1);
+
+				if let (Some(prev), Some(height)) = (prev, height) {
+					// Only ask for the previous message if it isn't already
+					// on its way
+					let awaited = self.pending_headers.contains(&prev) || self.is_scheduled(height);
+
+					self.pending_headers.prune();
+					if self.pending_headers.insert(header) {
+						if awaited {
+							return Vec::new();
+						}
+
+						return vec![Event::MessagePending { hash, prev, height }];
+					}
+				}
+
+				let error = ValidationError::UnknownParent;
+				warn!(
+					"Rejecting downloaded header {}: {}",
+					hex::encode(&hash),
+					error
+				);
+
+				return vec![Event::MessageRejected { hash, error }];
+			}
+			Err(error) => {
+				warn!(
+					"Rejecting downloaded header {}: {}",
+					hex::encode(&hash),
+					error
+				);
+
+				return vec![Event::MessageRejected { hash, error }];
+			}
+		}
+
+		rt.insert_header(header.clone());
+		let mut events = vec![Event::HeaderLoaded(header)];
+		events.extend(self.connect_headers(rt, &hash));
+
+		events
+	}
+
+	// Validates and inserts the buffered headers descending from the
+	// message with the given hash, now that it is known
+	fn connect_headers(&mut self, rt: &mut Rt, parent: &Hash) -> Vec<Event> {
+		let mut events = Vec::new();
+
+		let mut to_connect = vec![parent.clone()];
+		while let Some(parent) = to_connect.pop() {
+			for child in self.pending_headers.take_children(&parent) {
+				let hash = child.hash().clone();
+
+				if let Err(error) = msg::validate_header(rt, &child) {
+					warn!(
+						"Rejecting downloaded header {}: {}",
+						hex::encode(&hash),
+						error
+					);
+					events.push(Event::MessageRejected { hash, error });
+
+					continue;
+				}
+
+				rt.insert_header(child.clone());
+				events.push(Event::HeaderLoaded(child));
+				to_connect.push(hash);
+			}
+		}
+
+		// Headers that never connected are dropped once their ranges are in
+		if self.batches.is_empty() && self.batch_queue.is_empty() {
+			self.pending_headers.clear();
+		}
+
+		events
+	}
+
+	// Requests the payloads missing from the best chain of headers from the
+	// batch peers in turn, keeping at most SYNC_MAX_INFLIGHT_BATCHES
+	// requests in flight. Light clients only fetch payloads on demand.
+	fn fetch_bodies(&mut self, rt: &Rt, request_response: &mut RRBehavior<Request, Response>) {
+		if rt.is_light() || self.batch_peers.is_empty() {
+			return;
+		}
+
+		let capacity = SYNC_MAX_INFLIGHT_BATCHES.saturating_sub(self.body_requests.len());
+		let missing = rt
+			.missing_bodies(
+				SYNC_BATCH_SIZE * SYNC_MAX_INFLIGHT_BATCHES + self.requested_bodies.len(),
+			)
+			.into_iter()
+			.filter(|hash| !self.requested_bodies.contains(hash))
+			.collect::<Vec<Hash>>();
+
+		for hashes in missing.chunks(SYNC_BATCH_SIZE).take(capacity) {
+			let peer = self.batch_peers[self.next_batch_peer % self.batch_peers.len()];
+			self.next_batch_peer = self.next_batch_peer.wrapping_add(1);
+
+			let request_id = request_response.send_request(
+				&peer,
+				Request::GetBodies {
+					hashes: hashes.to_vec(),
+				},
+			);
+			self.requested_bodies.extend(hashes.iter().cloned());
+			self.body_requests
+				.insert(request_id, (peer, hashes.to_vec()));
+		}
+	}
+
+	// Requests the messages with the given hashes from the peer, skipping
+	// those already known or on their way
+	fn fetch_missing(
+		&mut self,
+		request_response: &mut RRBehavior<Request, Response>,
+		peer: &PeerId,
+		missing: Vec<Hash>,
+	) {
+		let missing = missing
+			.into_iter()
+			.filter(|hash| !self.requested_bodies.contains(hash))
+			.collect::<Vec<Hash>>();
+
+		for hashes in missing.chunks(SYNC_BATCH_SIZE) {
+			let request_id = request_response.send_request(
+				peer,
+				Request::GetBodies {
+					hashes: hashes.to_vec(),
+				},
+			);
+			self.requested_bodies.extend(hashes.iter().cloned());
+			self.body_requests
+				.insert(request_id, (*peer, hashes.to_vec()));
+		}
+	}
+
+	/// Initiates a reconciliation of the messages known to the runtime with
+	/// those known to the peer. Ranges of heights in which the peers differ
+	/// are narrowed down, and the messages the peer knows but the runtime
+	/// does not are downloaded, whether or not they are part of the longest
+	/// chain. Light clients do not reconcile.
+	pub fn reconcile(
+		&mut self,
+		rt: &Rt,
+		request_response: &mut RRBehavior<Request, Response>,
+		peer: &PeerId,
+	) {
+		if rt.is_light() {
+			return;
+		}
+
+		let request_id = request_response.send_request(
+			peer,
+			Request::Reconcile {
+				buckets: reconcile::initial(rt),
+			},
+		);
+		self.reconciliations.insert(request_id, (*peer, 0));
+	}
+
+	/// Publishes the messages of the longest chain which have not yet been
+	/// published to the DHT, and republishes those whose records are about
+	/// to expire, or whose publication failed and is due to be retried. At
+	/// most DHT_MAX_INFLIGHT_PUTS messages are published at once, the rest
+	/// being published as earlier publications complete. Light clients do
+	/// not have the payloads of their chain, and publish nothing.
+	///
+	/// Returns an event for each message which could not be published.
+	pub fn publish_chain(&mut self, rt: &Rt, kad: &mut Kademlia<MemoryStore>) -> Vec<Event> {
+		if rt.is_light() {
+			return Vec::new();
+		}
+
+		let now = Instant::now();
+
+		// Queue the messages added to the chain since it was last published,
+		// oldest first
+		if let Some(head) = rt.longest_chain() {
+			let mut new = iter::once(head)
+				.chain(rt.ancestors(head).map(MessageHeader::hash))
+				.take_while(|hash| !self.publications.contains_key(*hash))
+				.cloned()
+				.collect::<Vec<Hash>>();
+			new.reverse();
+
+			for hash in new {
+				self.publications.insert(
+					hash.clone(),
+					Publication {
+						published_at: None,
+						failures: 0,
+						due_at: now,
+						scheduled: true,
+					},
+				);
+				self.publish_queue.push_back(hash);
+			}
+		}
+
+		// Queue the messages due for republication or retry
+		for (hash, publication) in self.publications.iter_mut() {
+			if !publication.scheduled && publication.due_at <= now {
+				publication.scheduled = true;
+				self.publish_queue.push_back(hash.clone());
+			}
+		}
+
+		self.dispatch_puts(rt, kad)
+	}
+
+	// Publishes queued messages until DHT_MAX_INFLIGHT_PUTS are in flight.
+	// Messages which cannot be published are scheduled for a retry, and
+	// reported in the returned events.
+	fn dispatch_puts(&mut self, rt: &Rt, kad: &mut Kademlia<MemoryStore>) -> Vec<Event> {
+		let mut events = Vec::new();
+
+		while self.chain_uploads.len() < DHT_MAX_INFLIGHT_PUTS {
+			let hash = match self.publish_queue.pop_front() {
+				Some(hash) => hash,
+				None => break,
+			};
+
+			// Forget messages which are no longer known
+			let msg = match rt.get_message(&hash) {
+				Some(msg) => msg,
+				None => {
+					self.publications.remove(&hash);
+
+					continue;
+				}
+			};
+
+			// Write the transaction under its hash with its JSON serialization to the DHT
+			let res = serde_json::to_vec(&msg)
+				.map_err(Error::SerializationError)
+				.and_then(|msg_bytes| {
+					kad.put_record(
+						Record::new(record_key(self.chain_id, &hash), msg_bytes),
+						DHT_QUORUM,
+					)
+					.map_err(Error::from)
+				});
+			let q_id = match res {
+				Ok(q_id) => q_id,
+				Err(e) => {
+					warn!("Failed to publish message {}: {}", hex::encode(&hash), e);
+
+					let failures = self.publish_failed(&hash);
+					events.push(Event::MessagePublishFailed { hash, failures });
+
+					continue;
+				}
+			};
+			debug!(
+				"writing message {} to KAD DHT in query {:?}",
+				hex::encode(hash.as_ref()),
+				q_id
+			);
+			self.chain_uploads.insert(q_id, hash);
+		}
+
+		events
+	}
+
+	// Schedules the republication of a message before its record expires
+	fn published(&mut self, hash: &Hash) {
+		let now = Instant::now();
+
+		if let Some(publication) = self.publications.get_mut(hash) {
+			publication.published_at = Some(now);
+			publication.failures = 0;
+			publication.due_at = now + Duration::from_millis(DHT_REPUBLISH_INTERVAL);
+			publication.scheduled = false;
+		}
+	}
+
+	// Schedules a retry of the publication of a message, backing off
+	// exponentially with each failure. Returns the number of failures.
+	fn publish_failed(&mut self, hash: &Hash) -> u32 {
+		let publication = match self.publications.get_mut(hash) {
+			Some(publication) => publication,
+			None => return 0,
+		};
+
+		publication.failures = publication.failures.saturating_add(1);
+		let backoff = DHT_PUT_RETRY_BASE
+			.saturating_mul(1 << (publication.failures - 1).min(32))
+			.min(DHT_PUT_RETRY_MAX);
+		publication.due_at = Instant::now() + Duration::from_millis(backoff);
+		publication.scheduled = false;
+
+		publication.failures
+	}
+
+	/// Initiates a chain synchronization round, polling a random sample of
+	/// the given peers for their HEAD. Results are reported through the
+	/// LongestChainUpdated event once the sampled peers agree on a HEAD, and
+	/// it has been verified. Failed rounds are retried by [`Context::tick`]
+	/// up to SYNC_MAX_RETRIES times.
+	pub fn download_head(
+		&mut self,
+		request_response: &mut RRBehavior<Request, Response>,
+		sampling_pool: Vec<&PeerId>,
+	) -> Result<(), Error> {
+		self.retries = 0;
+		self.retry = false;
+
+		self.sample_head(request_response, sampling_pool)
+	}
+
+	// Polls a random sample of the given peers for their HEAD
+	fn sample_head(
+		&mut self,
+		request_response: &mut RRBehavior<Request, Response>,
+		sampling_pool: Vec<&PeerId>,
+	) -> Result<(), Error> {
+		info!("polling {} peers for head", sampling_pool.len());
+
+		// Contact a random x% of peers
+		let n_peers = sampling_pool.len();
+		let to_contact = sampling_pool
+			.choose_multiple(
+				&mut rand::thread_rng(),
+				(n_peers as f32 * SAMPLING_SIZE).ceil() as usize,
+			)
+			.map(|peer| **peer)
+			.collect::<Vec<PeerId>>();
+
+		// Take note of which round of questioning this is
+		let entry = SynchronizationRequest {
+			initiated_at: Instant::now(),
+			peers_contacted: to_contact.clone(),
+			results: HashMap::new(),
+			concluded: false,
+		};
+		let entry_id = self.next_query_round;
+		self.next_query_round = self.next_query_round.wrapping_add(1);
+		self.chain_downloads.insert(entry_id, entry);
+
+		self.state = if to_contact.is_empty() {
+			SyncState::Idle
+		} else {
+			SyncState::Polling
+		};
+
+		// Request the longest chain from each peer
+		for peer in &to_contact {
+			debug!("polling peer {} for head", peer);
+
+			request_response.send_request(
+				peer,
+				Request::LongestChain {
+					query_round: entry_id,
+				},
+			);
+		}
+
+		Ok(())
+	}
+
+	/// Advances the synchronization state machine. Expired questioning
+	/// rounds are dropped, and failed synchronizations are retried with a
+	/// new sample of the given peers. Stalled payload downloads are resumed,
+	/// and downloads are marked as synced once they complete.
+	pub fn tick(
+		&mut self,
+		rt: &Rt,
+		request_response: &mut RRBehavior<Request, Response>,
+		sampling_pool: Vec<&PeerId>,
+	) -> Result<(), Error> {
+		let timeout = Duration::from_millis(SYNCHRONIZATION_TIMEOUT);
+		let now = Instant::now();
+
+		// Ask another peer for the snapshot if the last one failed to serve it
+		if let Some(peer) = sampling_pool.choose(&mut rand::thread_rng()) {
+			self.download_snapshot(rt, request_response, peer);
+		}
+
+		// Rounds which expired before their peers agreed have failed
+		let expired = self
+			.chain_downloads
+			.iter()
+			.filter(|(_, round)| now - round.initiated_at >= timeout)
+			.map(|(query_round, round)| (*query_round, round.concluded))
+			.collect::<Vec<(usize, bool)>>();
+		for (query_round, concluded) in expired {
+			self.chain_downloads.remove(&query_round);
+
+			if !concluded && self.state == SyncState::Polling && self.chain_downloads.is_empty() {
+				warn!("synchronization round {} expired", query_round);
+				self.fail();
+			}
+		}
+
+		if self.state == SyncState::Downloading {
+			let target_reached = self
+				.target
+				.as_ref()
+				.map(|(hash, _)| {
+					rt.get_header(hash).is_some() && (rt.is_light() || rt.is_complete(hash))
+				})
+				.unwrap_or(true);
+
+			if target_reached {
+				info!("synchronized the chain");
+				self.state = SyncState::Synced;
+				self.retries = 0;
+			} else if self.batches.is_empty()
+				&& self.batch_queue.is_empty()
+				&& self.body_requests.is_empty()
+			{
+				// Resume downloading whatever payloads are missing, or give
+				// up if there are none left to be found
+				self.fetch_bodies(rt, request_response);
+
+				if self.body_requests.is_empty() {
+					warn!("synchronization stalled");
+					self.fail();
+				}
+			}
+		}
+
+		if self.retry && self.state == SyncState::Idle {
+			self.retry = false;
+			self.retries += 1;
+			info!(
+				"retrying synchronization ({} of {})",
+				self.retries, SYNC_MAX_RETRIES
+			);
+
+			return self.sample_head(request_response, sampling_pool);
+		}
+
+		Ok(())
+	}
+
+	// Abandons the current synchronization, scheduling a retry if any are left
+	fn fail(&mut self) {
+		self.state = SyncState::Idle;
+		self.retry = self.retries < SYNC_MAX_RETRIES;
+	}
+
+	/// Gets the progress of the synchronization of the chain.
+	pub fn status(&self, rt: &Rt) -> SyncStatus {
+		SyncStatus {
+			state: self.state,
+			height: rt.head_header().map(|head| head.height()),
+			target_height: self.target.as_ref().map(|(_, height)| *height),
+			retries: self.retries,
+		}
+	}
+
+	/// Initiates a download of the headers of the longest chain from
+	/// from_height up to, but not including, to_height. The range is split
+	/// into batches, which are requested from the given peers in parallel.
+	/// The payloads of the chain are requested from the same peers as its
+	/// headers connect, unless the runtime is that of a light client.
+	pub fn download_range(
+		&mut self,
+		request_response: &mut RRBehavior<Request, Response>,
+		peers: Vec<&PeerId>,
+		from_height: usize,
+		to_height: usize,
+	) {
+		self.batch_peers = peers.into_iter().copied().collect();
+		self.batch_queue.extend(
+			(from_height..to_height)
+				.step_by(SYNC_HEADERS_BATCH_SIZE)
+				.map(|batch_from| Batch {
+					from_height: batch_from,
+					limit: SYNC_HEADERS_BATCH_SIZE.min(to_height - batch_from),
+				}),
+		);
+
+		self.dispatch_batches(request_response);
+	}
+
+	/// Initiates a download of the message with the given hash, which is
+	/// expected to sit at the given height, along with its ancestors from a
+	/// random peer. Falls back to the DHT if no peers are given.
+	pub fn download_ancestors(
+		&mut self,
+		hash: &Hash,
+		height: usize,
+		behavior: &mut Behavior,
+		peers: Vec<&PeerId>,
+	) -> Result<(), Error> {
+		let peer = match peers.choose(&mut rand::thread_rng()) {
+			Some(peer) => *peer,
+			None => return self.download_msg(hash, height, behavior.kad_mut()),
+		};
+
+		let request_id = behavior.request_response_mut().send_request(
+			peer,
+			Request::GetAncestors {
+				hash: hash.clone(),
+				limit: SYNC_BATCH_SIZE,
+			},
+		);
+		self.ancestor_requests
+			.insert(request_id, (hash.clone(), height));
+
+		Ok(())
+	}
+
+	// Requests queued batches from the batch peers in turn, keeping at most
+	// SYNC_MAX_INFLIGHT_BATCHES in flight
+	fn dispatch_batches(&mut self, request_response: &mut RRBehavior<Request, Response>) {
+		if self.batch_peers.is_empty() {
+			return;
+		}
+
+		while self.batches.len() < SYNC_MAX_INFLIGHT_BATCHES {
+			let batch = match self.batch_queue.pop_front() {
+				Some(batch) => batch,
+				None => break,
+			};
+
+			let peer = self.batch_peers[self.next_batch_peer % self.batch_peers.len()];
+			self.next_batch_peer = self.next_batch_peer.wrapping_add(1);
+
+			let request_id = request_response.send_request(
+				&peer,
+				Request::GetHeaders {
+					from_height: batch.from_height,
+					limit: batch.limit,
+				},
+			);
+			self.batches.insert(request_id, (peer, batch));
+		}
+	}
+
+	// Determines whether the header at the given height is being downloaded
+	// as part of a range
+	fn is_scheduled(&self, height: usize) -> bool {
+		self.awaits_snapshot(height)
+			|| self
+				.batches
+				.values()
+				.map(|(_, batch)| batch)
+				.chain(self.batch_queue.iter())
+				.any(|batch| (batch.from_height..batch.from_height + batch.limit).contains(&height))
+	}
+
+	// Determines whether the message at the given height is expected to
+	// arrive with the trusted snapshot
+	fn awaits_snapshot(&self, height: usize) -> bool {
+		match &self.trusted_snapshot {
+			Some(trusted) => !self.snapshot_restored && height <= trusted.height,
+			None => false,
+		}
+	}
+
+	// Gets the height from which the longest chain should be downloaded:
+	// above our HEAD, or above the trusted snapshot while it is awaited
+	fn sync_from(&self, rt: &Rt) -> usize {
+		let from_height = rt
+			.head_header()
+			.map(|head| head.height() + 1)
+			.unwrap_or_default();
+
+		match &self.trusted_snapshot {
+			Some(trusted) if !self.snapshot_restored => from_height.max(trusted.height + 1),
+			_ => from_height,
+		}
+	}
+
+	/// Sets the ID of the chain being synchronized, under which its messages
+	/// are stored in the DHT.
+	pub fn set_chain_id(&mut self, chain_id: usize) {
+		self.chain_id = chain_id;
+	}
+
+	/// Sets the snapshot from which to begin synchronizing the chain. Unless
+	/// the runtime already holds it, the snapshot is requested from peers,
+	/// and the messages before it are not downloaded.
+	pub fn set_trusted_snapshot(&mut self, rt: &Rt, trusted: Option<TrustedSnapshot>) {
+		self.snapshot_restored = trusted
+			.as_ref()
+			.map(|trusted| rt.is_complete(&trusted.hash))
+			.unwrap_or_default();
+		self.trusted_snapshot = trusted;
+	}
+
+	/// Sets the application state served along with snapshots of the chain
+	/// ending at the message with the indicated hash.
+	pub fn set_snapshot_state(&mut self, hash: Hash, state: Vec<u8>) {
+		self.snapshot_state = Some((hash, state));
+	}
+
+	/// Requests the trusted snapshot from the peer, unless it has already
+	/// been restored, or is being requested from another peer. Results are
+	/// reported through the SnapshotLoaded event.
+	pub fn download_snapshot(
+		&mut self,
+		rt: &Rt,
+		request_response: &mut RRBehavior<Request, Response>,
+		peer: &PeerId,
+	) {
+		let trusted = match &self.trusted_snapshot {
+			Some(trusted) => trusted,
+			None => return,
+		};
+
+		// The runtime may have been flushed since the snapshot was restored
+		self.snapshot_restored = rt.is_complete(&trusted.hash);
+		if self.snapshot_restored || self.snapshot_request.is_some() {
+			return;
+		}
+
+		info!(
+			"requesting snapshot {} from peer {}",
+			hex::encode(&trusted.hash),
+			peer
+		);
+
+		let request_id = request_response.send_request(
+			peer,
+			Request::GetSnapshot {
+				hash: trusted.hash.clone(),
+			},
+		);
+		self.snapshot_request = Some((request_id, *peer));
+	}
+
+	/// Initiates a download of the chain with HEAD head, which is expected to
+	/// sit at the given height.
+	pub fn download_msg(
+		&mut self,
+		head: &Hash,
+		height: usize,
+		kad: &mut Kademlia<MemoryStore>,
+	) -> Result<(), Error> {
+		// Siblings waiting on the same message share its download
+		if self.is_downloading(head) {
+			return Ok(());
+		}
+
+		let q_id = kad.get_record(record_key(self.chain_id, head));
+		self.message_downloads.insert(q_id, (head.clone(), height));
+
+		Ok(())
+	}
+
+	/// Determines whether the message with the given hash is being
+	/// downloaded, or has been downloaded and is waiting for its ancestors.
+	pub fn is_downloading(&self, hash: &Hash) -> bool {
+		self.pending.contains(hash)
+			|| self.requested_bodies.contains(hash)
+			|| self
+				.message_downloads
+				.values()
+				.chain(self.ancestor_requests.values())
+				.any(|(downloading, _)| downloading == hash)
+	}
+
+	/// Initiates a download of the singular message.
+	pub fn load_msg(
+		&mut self,
+		msg: &Hash,
+		kad: &mut Kademlia<MemoryStore>,
 		req_id: usize,
 	) -> Result<(), Error> {
-		let q_id = kad.get_record(RecordKey::new(&msg.as_ref()));
+		let q_id = kad.get_record(record_key(self.chain_id, msg));
 		self.message_loads.insert(q_id, req_id);
 
 		Ok(())
 	}
 }
+
+/// Determines whether the height of a downloaded message is one more than
+/// that of its parent, if the parent is known, or zero if it has no parent.
+/// Also checks the height against the height expected by the message that
+/// referenced it, if any.
+fn follows_height_rule(rt: &Rt, msg: &Message, expected_height: Option<usize>) -> bool {
+	if expected_height
+		.map(|height| height != msg.data().height())
+		.unwrap_or_default()
+	{
+		return false;
+	}
+
+	match msg.data().prev() {
+		Some(prev) => rt
+			.get_header(prev)
+			.map(|prev| prev.height().checked_add(1) == Some(msg.data().height()))
+			.unwrap_or(true),
+		None => msg.data().height() == 0,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{
+		super::{super::sys::msg::MessageData, RR_PROTOCOL_PREFIX},
+		*,
+	};
+	use libp2p::{
+		kad::store::MemoryStoreConfig,
+		request_response::{Config as RRConfig, ProtocolSupport},
+		StreamProtocol,
+	};
+	use std::error::Error;
+
+	#[test]
+	fn test_accept_in_ancestor_order() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+		let mut ctx = Context::default();
+		let rule: ConsensusRule = &|_, _| Ok(());
+
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		let planted = Message::try_from(MessageData::new(
+			Vec::new(),
+			Some(root.hash().clone()),
+			None,
+			None,
+			1,
+			1,
+			0,
+		))?;
+
+		// The HEAD is downloaded first, and waits on its parent
+		let events = ctx.accept(&mut rt, rule, planted.clone());
+		assert!(matches!(
+			events.as_slice(),
+			[Event::MessagePending { prev, height: 0, .. }] if prev == root.hash()
+		));
+		assert!(rt.get_message(planted.hash()).is_none());
+
+		// Once the parent arrives, the HEAD is validated against it, and
+		// rejected for answering no captcha
+		let events = ctx.accept(&mut rt, rule, root.clone());
+		assert!(matches!(
+			events.as_slice(),
+			[
+				Event::MessageLoaded(loaded),
+				Event::MessageRejected { hash, error: ValidationError::WrongCaptchaSource },
+			] if loaded == &root && hash == planted.hash()
+		));
+		assert!(rt.get_message(planted.hash()).is_none());
+		assert_eq!(rt.longest_chain(), Some(root.hash()));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_accept_rejects_inflated_height() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+		let mut ctx = Context::default();
+		let rule: ConsensusRule = &|_, _| Ok(());
+
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		rt.insert_message(root.clone());
+
+		// A child of a real parent claiming the greatest possible height,
+		// which would otherwise become the HEAD of the chain
+		let inflated = Message::try_from(MessageData::new(
+			Vec::new(),
+			Some(root.hash().clone()),
+			None,
+			rt.captcha_source_hash(root.hash()).cloned(),
+			usize::MAX,
+			1,
+			0,
+		))?;
+
+		let events = ctx.accept(&mut rt, rule, inflated.clone());
+		assert!(matches!(
+			events.as_slice(),
+			[Event::MessageRejected { hash, error: ValidationError::InvalidHeight }]
+				if hash == inflated.hash()
+		));
+		assert!(rt.get_header(inflated.hash()).is_none());
+		assert!(!ctx.pending.contains(inflated.hash()));
+		assert_eq!(rt.longest_chain(), Some(root.hash()));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_accept_awaits_scheduled() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+		let mut ctx = Context::default();
+		let rule: ConsensusRule = &|_, _| Ok(());
+
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		let child = Message::try_from(MessageData::new(
+			Vec::new(),
+			Some(root.hash().clone()),
+			None,
+			None,
+			1,
+			1,
+			0,
+		))?;
+
+		// The parent is part of a range being downloaded, so it isn't asked for
+		ctx.batch_queue.push_back(Batch {
+			from_height: 0,
+			limit: 1,
+		});
+		assert!(ctx.accept(&mut rt, rule, child.clone()).is_empty());
+		assert!(ctx.pending.contains(child.hash()));
+
+		// Receiving the same message twice changes nothing
+		assert!(ctx.accept(&mut rt, rule, child.clone()).is_empty());
+		assert_eq!(ctx.pending.len(), 1);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_accept_headers() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+		let mut ctx = Context::default();
+
+		// Build a chain whose headers are valid
+		let mut chain = Rt::default();
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		chain.insert_header(root.header());
+		let a = Message::try_from(MessageData::new(
+			Vec::new(),
+			Some(root.hash().clone()),
+			None,
+			chain.captcha_source_hash(root.hash()).cloned(),
+			1,
+			1,
+			0,
+		))?;
+		chain.insert_header(a.header());
+		let b = Message::try_from(MessageData::new(
+			Vec::new(),
+			Some(a.hash().clone()),
+			None,
+			chain.captcha_source_hash(a.hash()).cloned(),
+			2,
+			2,
+			0,
+		))?;
+
+		// Headers arriving out of order wait on the range being downloaded
+		ctx.batch_queue.push_back(Batch {
+			from_height: 0,
+			limit: 3,
+		});
+		assert!(ctx.accept_header(&mut rt, b.header()).is_empty());
+		assert!(ctx.accept_header(&mut rt, b.header()).is_empty());
+		assert_eq!(ctx.pending_headers.len(), 1);
+
+		let events = ctx.accept_header(&mut rt, root.header());
+		assert!(
+			matches!(events.as_slice(), [Event::HeaderLoaded(loaded)] if loaded == &root.header())
+		);
+
+		let events = ctx.accept_header(&mut rt, a.header());
+		assert!(matches!(
+			events.as_slice(),
+			[Event::HeaderLoaded(first), Event::HeaderLoaded(second)]
+				if first == &a.header() && second == &b.header()
+		));
+
+		// The chain is known, but its payloads are still missing
+		assert_eq!(rt.height(b.hash()), Some(2));
+		assert_eq!(rt.longest_chain(), None);
+		assert_eq!(
+			rt.missing_bodies(SYNC_BATCH_SIZE),
+			vec![root.hash().clone(), a.hash().clone(), b.hash().clone()]
+		);
+		assert!(ctx.pending_headers.is_empty());
+
+		// Headers are no longer buffered once the pool is full
+		let mut ctx = Context::default();
+		ctx.pending_headers = OrphanPool::new(0, Duration::from_millis(SYNC_PENDING_EXPIRY));
+		assert!(matches!(
+			ctx.accept_header(&mut Rt::default(), b.header()).as_slice(),
+			[Event::MessageRejected {
+				error: ValidationError::UnknownParent,
+				..
+			}]
+		));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_trusted_snapshot() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+		let mut ctx = Context::default();
+
+		// Build a chain whose headers are valid
+		let mut chain = Rt::default();
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		chain.insert_message(root.clone());
+		let a = Message::try_from(MessageData::new(
+			Vec::new(),
+			Some(root.hash().clone()),
+			None,
+			chain.captcha_source_hash(root.hash()).cloned(),
+			1,
+			1,
+			0,
+		))?;
+		chain.insert_message(a.clone());
+		let b = Message::try_from(MessageData::new(
+			Vec::new(),
+			Some(a.hash().clone()),
+			None,
+			chain.captcha_source_hash(a.hash()).cloned(),
+			2,
+			2,
+			0,
+		))?;
+
+		// Messages before the trusted snapshot are neither downloaded nor
+		// walked back to
+		let trusted = TrustedSnapshot {
+			height: 1,
+			hash: a.hash().clone(),
+			state_digest: None,
+		};
+		ctx.set_trusted_snapshot(&rt, Some(trusted.clone()));
+		assert_eq!(ctx.sync_from(&rt), 2);
+		assert!(ctx.accept_header(&mut rt, b.header()).is_empty());
+
+		// Headers waiting on the snapshot connect once it is restored
+		let mut snapshot = Snapshot::from_rt(&chain, a.hash(), None).ok_or("no snapshot")?;
+		assert!(snapshot.verify(&trusted, chain.lookback_factor()).is_ok());
+		snapshot.restore(&mut rt);
+
+		let events = ctx.connect_headers(&mut rt, a.hash());
+		assert!(
+			matches!(events.as_slice(), [Event::HeaderLoaded(loaded)] if loaded == &b.header())
+		);
+		assert_eq!(rt.longest_chain(), Some(a.hash()));
+		assert_eq!(rt.missing_bodies(SYNC_BATCH_SIZE), vec![b.hash().clone()]);
+
+		ctx.set_trusted_snapshot(&rt, Some(trusted));
+		assert!(!ctx.awaits_snapshot(1));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_publish_chain() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+		let mut ctx = Context::default();
+		let local_peer = PeerId::random();
+		let mut kad = Kademlia::new(local_peer, MemoryStore::new(local_peer));
+
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		let child = Message::try_from(MessageData::new(
+			Vec::new(),
+			Some(root.hash().clone()),
+			None,
+			None,
+			1,
+			1,
+			0,
+		))?;
+		rt.insert_message(root.clone());
+		rt.insert_message(child.clone());
+
+		assert!(ctx.publish_chain(&rt, &mut kad).is_empty());
+		assert_eq!(ctx.chain_uploads.len(), 2);
+		assert!(ctx.publish_queue.is_empty());
+
+		// Messages being published are not published again
+		assert!(ctx.publish_chain(&rt, &mut kad).is_empty());
+		assert_eq!(ctx.chain_uploads.len(), 2);
+
+		// Failures are retried later and later, and successes only once the
+		// record is about to expire
+		let failed_at = Instant::now();
+		assert_eq!(ctx.publish_failed(root.hash()), 1);
+		let first_retry = ctx.publications[root.hash()].due_at;
+		assert!(first_retry >= failed_at + Duration::from_millis(DHT_PUT_RETRY_BASE));
+		assert_eq!(ctx.publish_failed(root.hash()), 2);
+		assert!(ctx.publications[root.hash()].due_at > first_retry);
+
+		ctx.published(child.hash());
+		assert_eq!(ctx.publications[child.hash()].failures, 0);
+		assert!(
+			ctx.publications[child.hash()].due_at
+				>= failed_at + Duration::from_millis(DHT_REPUBLISH_INTERVAL)
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_publish_chain_failure() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+		let mut ctx = Context::default();
+		let local_peer = PeerId::random();
+
+		// Only one record fits in the local store
+		let mut kad = Kademlia::new(
+			local_peer,
+			MemoryStore::with_config(
+				local_peer,
+				MemoryStoreConfig {
+					max_records: 1,
+					..Default::default()
+				},
+			),
+		);
+
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		let a = Message::try_from(MessageData::new(
+			Vec::new(),
+			Some(root.hash().clone()),
+			None,
+			None,
+			1,
+			1,
+			0,
+		))?;
+		let b = Message::try_from(MessageData::new(
+			Vec::new(),
+			Some(a.hash().clone()),
+			None,
+			None,
+			2,
+			2,
+			0,
+		))?;
+		for msg in [&root, &a, &b] {
+			rt.insert_message(msg.clone());
+		}
+
+		// A failed publication does not hold back the rest of the chain
+		let events = ctx.publish_chain(&rt, &mut kad);
+		assert!(matches!(
+			events.as_slice(),
+			[
+				Event::MessagePublishFailed { hash: first, failures: 1 },
+				Event::MessagePublishFailed { hash: second, failures: 1 },
+			] if first == a.hash() && second == b.hash()
+		));
+		assert_eq!(ctx.chain_uploads.len(), 1);
+		assert!(ctx.publish_queue.is_empty());
+		assert_eq!(ctx.publications[b.hash()].failures, 1);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_download_msg() -> Result<(), Box<dyn Error>> {
+		let mut ctx = Context::default();
+		let local_peer = PeerId::random();
+		let mut kad = Kademlia::new(local_peer, MemoryStore::new(local_peer));
+
+		let parent: Hash = [1; 32].into();
+		assert!(!ctx.is_downloading(&parent));
+
+		// Orphans waiting on the same message only look it up once
+		ctx.download_msg(&parent, 1, &mut kad)?;
+		ctx.download_msg(&parent, 1, &mut kad)?;
+		assert!(ctx.is_downloading(&parent));
+		assert_eq!(ctx.message_downloads.len(), 1);
+
+		ctx.download_msg(&[2; 32].into(), 1, &mut kad)?;
+		assert_eq!(ctx.message_downloads.len(), 2);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_respond() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+		let ctx = Context::default();
+		let peer = PeerId::random();
+
+		// Nothing is served for the longest chain of an empty runtime
+		assert!(ctx
+			.respond(&rt, &peer, Request::LongestChain { query_round: 0 })
+			.is_none());
+
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		let child = Message::try_from(MessageData::new(
+			Vec::new(),
+			Some(root.hash().clone()),
+			None,
+			None,
+			1,
+			1,
+			0,
+		))?;
+		let grandchild = Message::try_from(MessageData::new(
+			Vec::new(),
+			Some(child.hash().clone()),
+			None,
+			None,
+			2,
+			2,
+			0,
+		))?;
+		let fork = Message::try_from(MessageData::new(
+			vec![1],
+			Some(root.hash().clone()),
+			None,
+			None,
+			1,
+			1,
+			0,
+		))?;
+		for msg in [&root, &child, &grandchild, &fork] {
+			rt.insert_message(msg.clone());
+		}
+
+		assert!(matches!(
+			ctx.respond(&rt, &peer, Request::LongestChain { query_round: 1 }),
+			Some(Response::LongestChain { hash, height: 2, query_round: 1 }) if &hash == grandchild.hash()
+		));
+
+		// Ranges of messages follow the longest chain, for peers that
+		// download them with their payloads
+		let range = |from_height, limit| match ctx.respond(
+			&rt,
+			&peer,
+			Request::GetMessages { from_height, limit },
+		) {
+			Some(Response::Messages { messages }) => messages
+				.iter()
+				.map(|msg| msg.hash().clone())
+				.collect::<Vec<Hash>>(),
+			_ => panic!("Invalid response. Expected messages."),
+		};
+		assert_eq!(
+			range(1, 5),
+			vec![child.hash().clone(), grandchild.hash().clone()]
+		);
+		assert_eq!(range(0, 1), vec![root.hash().clone()]);
+		assert!(range(3, 5).is_empty());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_tally() {
+		let rt = Rt::default();
+		let mut ctx = Context::default();
+		let local_peer = PeerId::random();
+		let mut kad = Kademlia::new(local_peer, MemoryStore::new(local_peer));
+
+		let peers = [PeerId::random(), PeerId::random(), PeerId::random()];
+		let agreed: Hash = [1; 32].into();
+		let disputed: Hash = [2; 32].into();
+
+		ctx.chain_downloads.insert(
+			0,
+			SynchronizationRequest {
+				initiated_at: Instant::now(),
+				peers_contacted: peers.to_vec(),
+				results: HashMap::new(),
+				concluded: false,
+			},
+		);
+
+		// A single peer is not enough to agree on a HEAD
+		ctx.chain_downloads
+			.get_mut(&0)
+			.unwrap()
+			.results
+			.insert(peers[0], (disputed.clone(), 100));
+		ctx.tally(&rt, &mut kad, 0);
+		assert!(ctx.head_verifications.is_empty());
+
+		// Nor is a split vote, while other peers may still settle it
+		ctx.chain_downloads
+			.get_mut(&0)
+			.unwrap()
+			.results
+			.insert(peers[1], (agreed.clone(), 1));
+		ctx.tally(&rt, &mut kad, 0);
+		assert!(ctx.head_verifications.is_empty());
+
+		// The majority wins over the higher claim, which must be verified
+		ctx.chain_downloads
+			.get_mut(&0)
+			.unwrap()
+			.results
+			.insert(peers[2], (agreed.clone(), 1));
+		ctx.tally(&rt, &mut kad, 0);
+
+		assert!(ctx.chain_downloads[&0].concluded);
+		assert_eq!(ctx.status(&rt).state, SyncState::Verifying);
+		assert_eq!(ctx.status(&rt).target_height, Some(1));
+		assert_eq!(ctx.head_verifications.len(), 2);
+		assert!(ctx.head_verifications.values().any(|verification| {
+			verification.hash == agreed
+				&& verification.claimants.len() == 2
+				&& !verification.disputed
+		}));
+		assert!(ctx.head_verifications.values().any(|verification| {
+			verification.hash == disputed
+				&& verification.claimants == vec![peers[0]]
+				&& verification.disputed
+		}));
+
+		// Disputed HEADs missing from the DHT may not be published yet, so
+		// peers are only reported for those proven false
+		let verification = ctx
+			.head_verifications
+			.drain()
+			.map(|(_, verification)| verification)
+			.find(|verification| verification.disputed)
+			.unwrap();
+		let unresolved = HeadVerification {
+			hash: verification.hash.clone(),
+			height: verification.height,
+			claimants: verification.claimants.clone(),
+			disputed: true,
+		};
+		assert!(ctx.abandon_head(unresolved).is_empty());
+		assert!(matches!(
+			ctx.reject_head(verification).as_slice(),
+			[Event::HeadDisputed { peer, hash }] if peer == &peers[0] && hash == &disputed
+		));
+		assert_eq!(ctx.status(&rt).state, SyncState::Verifying);
+	}
+
+	#[test]
+	fn test_tally_split() {
+		let rt = Rt::default();
+		let local_peer = PeerId::random();
+		let mut kad = Kademlia::new(local_peer, MemoryStore::new(local_peer));
+
+		let peers = [
+			PeerId::random(),
+			PeerId::random(),
+			PeerId::random(),
+			PeerId::random(),
+		];
+		let claims: [Hash; 3] = [[1; 32].into(), [2; 32].into(), [3; 32].into()];
+
+		let round = |results: Vec<(PeerId, (Hash, usize))>| SynchronizationRequest {
+			initiated_at: Instant::now(),
+			peers_contacted: results.iter().map(|(peer, _)| *peer).collect(),
+			results: results.into_iter().collect(),
+			concluded: false,
+		};
+
+		// Equally backed claims are decided by the fork-choice rule, which
+		// breaks ties between equally high HEADs by their hashes
+		let mut ctx = Context::default();
+		ctx.chain_downloads.insert(
+			0,
+			round(vec![
+				(peers[0], (claims[0].clone(), 5)),
+				(peers[1], (claims[0].clone(), 5)),
+				(peers[2], (claims[1].clone(), 5)),
+				(peers[3], (claims[1].clone(), 5)),
+			]),
+		);
+		ctx.tally(&rt, &mut kad, 0);
+
+		assert!(ctx.chain_downloads[&0].concluded);
+		assert_eq!(ctx.target, Some((claims[1].clone(), 5)));
+		assert_eq!(ctx.head_verifications.len(), 1);
+
+		// As are votes split between peers lagging behind one another, none
+		// of which are reported
+		let mut ctx = Context::default();
+		ctx.chain_downloads.insert(
+			0,
+			round(vec![
+				(peers[0], (claims[0].clone(), 3)),
+				(peers[1], (claims[1].clone(), 4)),
+				(peers[2], (claims[2].clone(), 5)),
+			]),
+		);
+		ctx.tally(&rt, &mut kad, 0);
+
+		assert_eq!(ctx.target, Some((claims[2].clone(), 5)));
+		assert_eq!(ctx.status(&rt).state, SyncState::Verifying);
+		assert!(ctx
+			.head_verifications
+			.values()
+			.all(|verification| verification.hash == claims[2] && !verification.disputed));
+	}
+
+	#[test]
+	fn test_tick() {
+		let rt = Rt::default();
+		let mut ctx = Context::default();
+		let mut request_response = RRBehavior::new(
+			[(
+				StreamProtocol::new(RR_PROTOCOL_PREFIX),
+				ProtocolSupport::Full,
+			)],
+			RRConfig::default(),
+		);
+
+		// A round which expires before its peers agree is dropped, and retried
+		let expired_at = Instant::now() - Duration::from_millis(SYNCHRONIZATION_TIMEOUT);
+		ctx.chain_downloads.insert(
+			0,
+			SynchronizationRequest {
+				initiated_at: expired_at,
+				peers_contacted: vec![PeerId::random()],
+				results: HashMap::new(),
+				concluded: false,
+			},
+		);
+		ctx.next_query_round = 1;
+		ctx.state = SyncState::Polling;
+
+		assert!(ctx.tick(&rt, &mut request_response, Vec::new()).is_ok());
+		assert!(!ctx.chain_downloads.contains_key(&0));
+		assert_eq!(ctx.status(&rt).retries, 1);
+
+		// Retries stop once exhausted
+		for _ in 0..SYNC_MAX_RETRIES {
+			ctx.fail();
+			assert!(ctx.tick(&rt, &mut request_response, Vec::new()).is_ok());
+		}
+		assert_eq!(ctx.status(&rt).retries, SYNC_MAX_RETRIES);
+		assert_eq!(ctx.status(&rt).state, SyncState::Idle);
+		assert!(!ctx.retry);
+	}
+
+	#[test]
+	fn test_accept_rejects_invalid() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+		let mut ctx = Context::default();
+		let rule: ConsensusRule = &|_, msg| {
+			if msg.data().data().is_empty() {
+				Ok(())
+			} else {
+				Err(ValidationError::RuleFailed("no data allowed".into()))
+			}
+		};
+
+		// Application rules apply to downloaded messages
+		let planted = Message::try_from(MessageData::new(vec![1], None, None, None, 0, 0, 0))?;
+		let events = ctx.accept(&mut rt, rule, planted.clone());
+		assert!(matches!(
+			events.as_slice(),
+			[Event::MessageRejected { hash, error: ValidationError::RuleFailed(_) }]
+				if hash == planted.hash()
+		));
+		assert!(rt.get_message(planted.hash()).is_none());
+
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		let events = ctx.accept(&mut rt, rule, root.clone());
+		assert!(matches!(events.as_slice(), [Event::MessageLoaded(loaded)] if loaded == &root));
+
+		Ok(())
+	}
+}
@@ -1,12 +1,22 @@
-use super::super::{crypto::hash::Hash, sys::msg::Message};
+use super::super::{
+	crypto::hash::Hash,
+	net::sync::SyncStatus,
+	sys::{msg::Message, rt::Rt},
+};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 /// RPC inputs to the CHUD CLI.
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Cmd {
 	SubmitMsg { req: SubmitMsgReq, req_id: usize },
+	ComposeAndSubmit { req: ComposeMsgReq, req_id: usize },
 	LoadMsg { req: LoadMsgReq, req_id: usize },
 	GetHead { req_id: usize },
+	GetChallenge { req_id: usize },
+	GetRejections { req_id: usize },
+	GetSyncStatus { req_id: usize },
+	SetSnapshotState { req: StateReq, req_id: usize },
 	Flush { req_id: usize },
 	Terminate,
 }
@@ -23,19 +33,154 @@ pub struct SubmitMsgReq {
 	pub(crate) timestamp: u128,
 }
 
+impl SubmitMsgReq {
+	/// Creates a request to submit a message with the given contents.
+	pub fn new(
+		data: Vec<u8>,
+		prev: Option<Hash>,
+		captcha_ans: Option<String>,
+		captcha_src: Option<Hash>,
+		height: usize,
+		timestamp: u128,
+	) -> Self {
+		Self {
+			data,
+			prev,
+			captcha_ans,
+			captcha_src,
+			height,
+			timestamp,
+		}
+	}
+}
+
+/// A request to submit a message extending the current longest chain. The
+/// previous message, height, timestamp, and captcha source are filled in by
+/// the client.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ComposeMsgReq {
+	pub(crate) data: Vec<u8>,
+	pub(crate) captcha_ans: Option<String>,
+}
+
+impl ComposeMsgReq {
+	/// Creates a request to submit the data, answering the current challenge
+	/// with the given captcha answer.
+	pub fn new(data: Vec<u8>, captcha_ans: Option<String>) -> Self {
+		Self { data, captcha_ans }
+	}
+}
+
+/// A request to serve the application state at the message with a particular
+/// hash along with snapshots of the chain ending at that message.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StateReq {
+	pub(crate) hash: Hash,
+	pub(crate) state: Vec<u8>,
+}
+
+impl StateReq {
+	/// Creates a request to serve the state at the message with the given hash.
+	pub fn new(hash: Hash, state: Vec<u8>) -> Self {
+		Self { hash, state }
+	}
+}
+
 /// A request to load a message with a particular hash.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct LoadMsgReq {
 	pub(crate) hash: Hash,
 }
 
+/// The information needed to compose the next message in the chain. A client
+/// only has to solve the captcha to fill out a [`SubmitMsgReq`].
+#[derive(Serialize, Deserialize, Clone, Hash, Eq, Debug, PartialEq)]
+pub struct Challenge {
+	/// The HEAD of the chain, which the next message should reference as its previous message
+	pub head: Option<Hash>,
+
+	/// The height of the next message
+	pub height: usize,
+
+	/// The hash of the message whose captcha the next message must answer
+	pub captcha_src: Option<Hash>,
+
+	/// The base64-encoded image of the captcha the next message must answer
+	pub captcha: Option<String>,
+}
+
+impl From<&Rt> for Challenge {
+	fn from(rt: &Rt) -> Self {
+		let head = rt.head_header();
+		let captcha_src = head.and_then(|head| rt.captcha_source_hash(head.hash()));
+
+		Self {
+			head: head.map(|head| head.hash().clone()),
+			height: head.map(|head| head.height() + 1).unwrap_or_default(),
+			captcha_src: captcha_src.cloned(),
+			captcha: captcha_src
+				.and_then(|src| rt.get_message(src))
+				.map(|src| String::from_utf8_lossy(src.data().new_captcha().data()).into_owned()),
+		}
+	}
+}
+
+/// The number of messages rejected by consensus, per rejection kind. See
+/// [`ValidationError::kind`](crate::net::msg::ValidationError::kind).
+pub type Rejections = BTreeMap<String, usize>;
+
 /// RPC outputs to the CHUD CLI.
 #[derive(Serialize, Clone, Hash, Eq, Debug, PartialEq)]
 pub enum CmdResp {
-	MsgSubmitted { hash: Hash, req_id: usize },
-	MsgLoaded { msg: Message, req_id: usize },
-	HeadLoaded { hash: Hash, req_id: usize },
-	MsgReceived { msg: Message },
-	RuntimeFlushed { req_id: usize },
-	Error { error: String, req_id: usize },
+	MsgSubmitted {
+		hash: Hash,
+		req_id: usize,
+	},
+	MsgLoaded {
+		msg: Message,
+		req_id: usize,
+	},
+	HeadLoaded {
+		hash: Hash,
+		req_id: usize,
+	},
+	Challenge {
+		challenge: Challenge,
+		req_id: usize,
+	},
+	MsgReceived {
+		msg: Message,
+	},
+	Reorg {
+		old_head: Hash,
+		new_head: Hash,
+		detached: Vec<Hash>,
+		attached: Vec<Hash>,
+	},
+	RejectionsLoaded {
+		counts: Rejections,
+		req_id: usize,
+	},
+	SyncStatus {
+		status: SyncStatus,
+		req_id: usize,
+	},
+	SyncProgress {
+		status: SyncStatus,
+	},
+	SnapshotLoaded {
+		height: usize,
+		hash: Hash,
+		state: Option<Vec<u8>>,
+	},
+	SnapshotStateSet {
+		req_id: usize,
+	},
+	RuntimeFlushed {
+		req_id: usize,
+	},
+	Error {
+		error: String,
+		req_id: usize,
+	},
 }
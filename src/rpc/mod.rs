@@ -5,7 +5,7 @@ use actix_web::{
 	HttpResponse, HttpResponseBuilder, Responder,
 };
 use async_channel::{Receiver, Sender};
-use cmd::{Cmd, CmdResp, LoadMsgReq, SubmitMsgReq};
+use cmd::{Cmd, CmdResp, ComposeMsgReq, LoadMsgReq, StateReq, SubmitMsgReq};
 use std::error::Error;
 
 pub mod cmd;
@@ -66,6 +66,45 @@ pub async fn submit_msg(
 	}
 }
 
+/// Submits a message extending the current longest chain to the network.
+#[cfg(not(target_arch = "wasm32"))]
+#[post("/compose_msg")]
+pub async fn compose_msg(
+	cmd_tx: Data<Sender<Cmd>>,
+	resp_rx: Data<Receiver<CmdResp>>,
+	Json(json): Json<ComposeMsgReq>,
+) -> impl Responder {
+	let req_id = instant::now() as usize;
+
+	cmd_tx
+		.send(Cmd::ComposeAndSubmit { req_id, req: json })
+		.await?;
+
+	loop {
+		match resp_rx.recv().await? {
+			CmdResp::MsgSubmitted {
+				hash: h,
+				req_id: resp_id,
+			} => {
+				if resp_id == req_id {
+					return Ok(HttpResponse::Ok().body(hex::encode(h)));
+				}
+			}
+			CmdResp::Error {
+				error,
+				req_id: resp_id,
+			} => {
+				if req_id == resp_id {
+					return Err(<Box<dyn Error>>::from(
+						format!("Failed to submit the message: {}", error).as_str(),
+					));
+				}
+			}
+			_ => continue,
+		}
+	}
+}
+
 /// Reads a message from the network.
 #[cfg(not(target_arch = "wasm32"))]
 #[get("/load_msg")]
@@ -138,3 +177,148 @@ pub async fn get_head(
 		}
 	}
 }
+
+/// Gets the captcha challenge that the next message in the chain must answer.
+#[cfg(not(target_arch = "wasm32"))]
+#[get("/challenge")]
+pub async fn get_challenge(
+	cmd_tx: Data<Sender<Cmd>>,
+	resp_rx: Data<Receiver<CmdResp>>,
+) -> impl Responder {
+	let req_id = instant::now() as usize;
+
+	cmd_tx.send(Cmd::GetChallenge { req_id }).await?;
+
+	loop {
+		match resp_rx.recv().await? {
+			CmdResp::Challenge {
+				challenge,
+				req_id: resp_id,
+			} => {
+				if resp_id == req_id {
+					return Ok(HttpResponse::Ok().json(challenge));
+				}
+			}
+			CmdResp::Error {
+				error,
+				req_id: resp_id,
+			} => {
+				if req_id == resp_id {
+					return Err(<Box<dyn Error>>::from(
+						format!("Failed to load the challenge: {}", error).as_str(),
+					));
+				}
+			}
+			_ => continue,
+		}
+	}
+}
+
+/// Gets the number of messages rejected by consensus, per rejection reason.
+#[cfg(not(target_arch = "wasm32"))]
+#[get("/rejections")]
+pub async fn get_rejections(
+	cmd_tx: Data<Sender<Cmd>>,
+	resp_rx: Data<Receiver<CmdResp>>,
+) -> impl Responder {
+	let req_id = instant::now() as usize;
+
+	cmd_tx.send(Cmd::GetRejections { req_id }).await?;
+
+	loop {
+		match resp_rx.recv().await? {
+			CmdResp::RejectionsLoaded {
+				counts,
+				req_id: resp_id,
+			} => {
+				if resp_id == req_id {
+					return Ok(HttpResponse::Ok().json(counts));
+				}
+			}
+			CmdResp::Error {
+				error,
+				req_id: resp_id,
+			} => {
+				if req_id == resp_id {
+					return Err(<Box<dyn Error>>::from(
+						format!("Failed to load the rejections: {}", error).as_str(),
+					));
+				}
+			}
+			_ => continue,
+		}
+	}
+}
+
+/// Gets the progress of the synchronization of the chain.
+#[cfg(not(target_arch = "wasm32"))]
+#[get("/sync_status")]
+pub async fn get_sync_status(
+	cmd_tx: Data<Sender<Cmd>>,
+	resp_rx: Data<Receiver<CmdResp>>,
+) -> impl Responder {
+	let req_id = instant::now() as usize;
+
+	cmd_tx.send(Cmd::GetSyncStatus { req_id }).await?;
+
+	loop {
+		match resp_rx.recv().await? {
+			CmdResp::SyncStatus {
+				status,
+				req_id: resp_id,
+			} => {
+				if resp_id == req_id {
+					return Ok(HttpResponse::Ok().json(status));
+				}
+			}
+			CmdResp::Error {
+				error,
+				req_id: resp_id,
+			} => {
+				if req_id == resp_id {
+					return Err(<Box<dyn Error>>::from(
+						format!("Failed to load the sync status: {}", error).as_str(),
+					));
+				}
+			}
+			_ => continue,
+		}
+	}
+}
+
+/// Sets the application state served to peers along with snapshots of the
+/// chain ending at the given message.
+#[cfg(not(target_arch = "wasm32"))]
+#[post("/snapshot_state")]
+pub async fn set_snapshot_state(
+	cmd_tx: Data<Sender<Cmd>>,
+	resp_rx: Data<Receiver<CmdResp>>,
+	Json(json): Json<StateReq>,
+) -> impl Responder {
+	let req_id = instant::now() as usize;
+
+	cmd_tx
+		.send(Cmd::SetSnapshotState { req_id, req: json })
+		.await?;
+
+	loop {
+		match resp_rx.recv().await? {
+			CmdResp::SnapshotStateSet { req_id: resp_id } => {
+				if resp_id == req_id {
+					return Ok(HttpResponse::Ok());
+				}
+			}
+			CmdResp::Error {
+				error,
+				req_id: resp_id,
+			} => {
+				if req_id == resp_id {
+					return Err(<Box<dyn Error>>::from(
+						format!("Failed to set the snapshot state: {}", error).as_str(),
+					));
+				}
+			}
+			_ => continue,
+		}
+	}
+}
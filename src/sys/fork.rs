@@ -0,0 +1,254 @@
+use super::{super::crypto::hash::Hash, rt::Rt};
+use std::{cmp::Ordering, fmt::Debug};
+
+/// A candidate HEAD of a chain. Candidates may be HEADs reported by peers
+/// which are not yet known to the runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Head<'a> {
+	/// The hash of the HEAD message
+	pub hash: &'a Hash,
+
+	/// The height of the HEAD message
+	pub height: usize,
+}
+
+/// A rule for choosing the canonical chain among competing HEADs.
+///
+/// # Examples
+///
+/// ```
+/// use chud::sys::{fork::{ForkChoice, Head}, rt::Rt};
+/// use std::cmp::Ordering;
+///
+/// /// Prefers the shortest chain.
+/// #[derive(Debug)]
+/// struct ShortestChain;
+///
+/// impl ForkChoice for ShortestChain {
+///     fn compare(&self, _rt: &Rt, a: Head, b: Head) -> Ordering {
+///         b.height.cmp(&a.height).then_with(|| a.hash.cmp(b.hash))
+///     }
+/// }
+/// ```
+pub trait ForkChoice: Debug + Send + Sync {
+	/// Compares two candidate HEADs, returning Ordering::Greater if `a`
+	/// should be preferred over `b`. Rules should only return
+	/// Ordering::Equal for identical candidates, so that the choice of
+	/// HEAD is deterministic.
+	fn compare(&self, rt: &Rt, a: Head, b: Head) -> Ordering;
+}
+
+/// Prefers the highest chain, breaking ties by the hash of the HEAD. The
+/// default fork-choice rule.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HighestChain;
+
+impl ForkChoice for HighestChain {
+	fn compare(&self, _rt: &Rt, a: Head, b: Head) -> Ordering {
+		a.height.cmp(&b.height).then_with(|| a.hash.cmp(b.hash))
+	}
+}
+
+/// Prefers the highest chain, breaking ties in favor of the HEAD that was
+/// seen first. HEADs that are not known to the runtime are treated as seen
+/// last.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FirstSeen;
+
+impl ForkChoice for FirstSeen {
+	fn compare(&self, rt: &Rt, a: Head, b: Head) -> Ordering {
+		let seen_at = |head: Head| rt.seen_at(head.hash).unwrap_or(u64::MAX);
+
+		a.height
+			.cmp(&b.height)
+			.then_with(|| seen_at(b).cmp(&seen_at(a)))
+			.then_with(|| a.hash.cmp(b.hash))
+	}
+}
+
+/// Prefers the chain with the most distinct captcha answers, and then the
+/// highest chain. HEADs whose payloads are not known to the runtime are
+/// counted as though every message in their chain answered a distinct
+/// captcha, such that they compare by [`HighestChain`] among themselves and
+/// the order stays consistent when known and unknown HEADs are mixed.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MostCaptchaAnswers;
+
+impl ForkChoice for MostCaptchaAnswers {
+	fn compare(&self, rt: &Rt, a: Head, b: Head) -> Ordering {
+		let answers = |head: Head| rt.captcha_answers(head.hash).unwrap_or(head.height);
+
+		answers(a)
+			.cmp(&answers(b))
+			.then_with(|| HighestChain.compare(rt, a, b))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{
+		super::msg::{Message, MessageData},
+		*,
+	};
+	use std::error::Error;
+
+	// Builds a message with no data on top of the given message
+	fn child_of(prev: &Message, captcha_ans: Option<&str>) -> Result<Message, Box<dyn Error>> {
+		Ok(Message::try_from(MessageData::new(
+			Vec::new(),
+			Some(prev.hash().clone()),
+			captcha_ans.map(str::to_owned),
+			None,
+			prev.data().height() + 1,
+			prev.data().timestamp() + 1,
+			0,
+		))?)
+	}
+
+	#[test]
+	fn test_highest_chain() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		let a = child_of(&root, None)?;
+		let b = child_of(&a, None)?;
+		let c = child_of(&root, None)?;
+
+		for msg in [&root, &a, &b, &c] {
+			rt.insert_message(msg.clone());
+		}
+
+		assert_eq!(rt.longest_chain(), Some(b.hash()));
+
+		let head = |msg: &Message| Head {
+			hash: msg.hash(),
+			height: msg.data().height(),
+		};
+		assert_eq!(
+			HighestChain.compare(&rt, head(&b), head(&c)),
+			Ordering::Greater
+		);
+		assert_eq!(
+			HighestChain.compare(&rt, head(&c), head(&b)),
+			Ordering::Less
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_first_seen() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+		rt.set_fork_choice(Box::new(FirstSeen));
+
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		let a = child_of(&root, None)?;
+		let b = child_of(&root, None)?;
+
+		rt.insert_message(root.clone());
+		rt.insert_message(a.clone());
+		rt.insert_message(b.clone());
+
+		// Equally high chains are decided by which was seen first
+		assert_eq!(rt.longest_chain(), Some(a.hash()));
+
+		// Even after a restart
+		let mut restored = serde_json::from_slice::<Rt>(&serde_json::to_vec(&rt)?)?;
+		restored.set_fork_choice(Box::new(FirstSeen));
+		assert_eq!(restored.seen_at(a.hash()), rt.seen_at(a.hash()));
+		assert_eq!(restored.seen_at(b.hash()), rt.seen_at(b.hash()));
+		assert_eq!(restored.longest_chain(), Some(a.hash()));
+
+		// Higher chains still win
+		let c = child_of(&b, None)?;
+		rt.insert_message(c.clone());
+
+		assert_eq!(rt.longest_chain(), Some(c.hash()));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_most_captcha_answers() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+		rt.set_fork_choice(Box::new(MostCaptchaAnswers));
+
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		let a = child_of(&root, Some("a"))?;
+		let b = child_of(&a, Some("a"))?;
+		let c = child_of(&root, Some("b"))?;
+		let d = child_of(&c, Some("c"))?;
+		let e = child_of(&d, Some("c"))?;
+
+		for msg in [&root, &a, &b, &c, &d] {
+			rt.insert_message(msg.clone());
+		}
+
+		// The chain with more distinct answers wins, despite equal heights
+		assert_eq!(rt.longest_chain(), Some(d.hash()));
+
+		// Repeated answers don't count, so height decides ties
+		rt.insert_message(e.clone());
+		assert_eq!(rt.longest_chain(), Some(e.hash()));
+		assert_eq!(rt.captcha_answers(b.hash()), Some(1));
+		assert_eq!(rt.captcha_answers(e.hash()), Some(2));
+
+		// HEADs that are not known compare by height and hash among
+		// themselves, and as if each of their messages answered a distinct
+		// captcha against known HEADs
+		let (x, y) = (Hash::from([1; 32]), Hash::from([2; 32]));
+		let (unknown_x, unknown_y) = (
+			|height| Head { hash: &x, height },
+			|height| Head { hash: &y, height },
+		);
+		let known = Head {
+			hash: e.hash(),
+			height: e.data().height(),
+		};
+		assert_eq!(
+			MostCaptchaAnswers.compare(&rt, unknown_x(3), unknown_y(2)),
+			Ordering::Greater
+		);
+		assert_eq!(
+			MostCaptchaAnswers.compare(&rt, unknown_x(2), unknown_y(2)),
+			Ordering::Less
+		);
+		assert_eq!(
+			MostCaptchaAnswers.compare(&rt, known, unknown_x(2)),
+			Ordering::Greater
+		);
+		assert_eq!(
+			MostCaptchaAnswers.compare(&rt, known, unknown_x(3)),
+			Ordering::Less
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_captcha_answers_out_of_order() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		let a = child_of(&root, Some("a"))?;
+		let b = child_of(&a, Some("a"))?;
+		let c = child_of(&b, Some("b"))?;
+
+		// Counts are updated as the ancestors of a message arrive
+		rt.insert_message(c.clone());
+		rt.insert_message(b.clone());
+		assert_eq!(rt.captcha_answers(c.hash()), Some(2));
+
+		rt.insert_message(a.clone());
+		rt.insert_message(root.clone());
+		assert_eq!(rt.captcha_answers(a.hash()), Some(1));
+		assert_eq!(rt.captcha_answers(c.hash()), Some(2));
+
+		// Only messages whose payloads are known have a count
+		let mut light = Rt::default();
+		light.insert_header(a.header());
+		assert_eq!(light.captcha_answers(a.hash()), None);
+
+		Ok(())
+	}
+}
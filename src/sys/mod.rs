@@ -1,6 +1,15 @@
-/// The number of digits of a hash to use to derive the lookback index for
-/// captcha answers from.
+/// The maximum number of messages to look back from a message's parent to
+/// find the captcha it answers. A message at height h answers the captcha of
+/// the ancestor at height h - 1 - d, where d is in [0, min(h, factor)).
 pub const CAPTCHA_ANS_LOOKBACK_FACTOR: usize = 5;
 
+/// The version of the scheme by which messages are hashed. Messages hashed
+/// under another scheme are invalid, so runtimes persisted under another
+/// version are discarded when loaded.
+pub const HASH_VERSION: u32 = 1;
+
+pub mod fork;
 pub mod msg;
 pub mod rt;
+pub mod snapshot;
+pub mod spec;
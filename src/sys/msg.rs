@@ -1,6 +1,9 @@
 use super::{
-	super::{captcha::Captcha, crypto::hash::Hash},
-	CAPTCHA_ANS_LOOKBACK_FACTOR,
+	super::{
+		captcha::{Captcha, CaptchaParams},
+		crypto::hash::Hash,
+	},
+	rt::Rt,
 };
 use serde::{Deserialize, Serialize};
 use serde_json::Error;
@@ -16,12 +19,16 @@ pub struct MessageData {
 	captcha_src: Option<Hash>,
 	height: usize,
 	timestamp: u128,
+
+	// Messages persisted before chains were told apart belong to chain 0
+	#[serde(default)]
+	chain_id: usize,
 }
 
 impl MessageData {
 	/// Constructs a new message in the context of a greater blockchain. Expects a height and answer to the
-	/// derived corresponding captcha, as well as a previous message, and arbitrary data. Generates a new
-	/// captcha to attach to the message.
+	/// derived corresponding captcha, as well as a previous message, arbitrary data, and the ID of the
+	/// chain the message belongs to. Generates a new captcha to attach to the message.
 	pub fn new(
 		data: Vec<u8>,
 		prev: Option<Hash>,
@@ -29,6 +36,7 @@ impl MessageData {
 		captcha_src: Option<Hash>,
 		height: usize,
 		timestamp: u128,
+		chain_id: usize,
 	) -> Self {
 		Self {
 			data,
@@ -38,6 +46,15 @@ impl MessageData {
 			captcha_src,
 			height,
 			timestamp,
+			chain_id,
+		}
+	}
+
+	/// Creates a builder for a message carrying the given data.
+	pub fn builder(data: Vec<u8>) -> MessageDataBuilder {
+		MessageDataBuilder {
+			data,
+			..Default::default()
 		}
 	}
 
@@ -76,23 +93,270 @@ impl MessageData {
 		self.timestamp
 	}
 
-	/// Calculates the hash of the transaction data.
+	/// Gets the ID of the chain the message belongs to.
+	pub fn chain_id(&self) -> usize {
+		self.chain_id
+	}
+
+	/// Calculates the hash of the transaction data. The hash commits to the
+	/// payload through its digest, such that the hash of a message can be
+	/// verified from its [`MessageHeader`] alone. The hash commits to the
+	/// chain ID, such that the message cannot be replayed on other chains.
 	pub fn hashed(&self) -> Result<Hash, Error> {
-		let encoded = serde_json::to_vec(&self)?;
-		Ok(blake3::hash(encoded.as_slice()).into())
+		hash_header(
+			self.prev(),
+			self.height,
+			self.timestamp,
+			self.captcha_src(),
+			&self.payload_digest(),
+			self.chain_id,
+		)
 	}
 
-	/// Calculates the hash of the transaction whose captcha this transaction
-	/// is answering.
-	pub fn lookback(&self) -> Option<u64> {
-		// Calculate the number of posts back to look for the answer based on
-		// the hash of the message
-		let h = self.hashed().ok()?;
-		let digits = hex::encode(h);
-		let relevant_digits = &digits[..CAPTCHA_ANS_LOOKBACK_FACTOR];
+	/// Calculates the digest of the payload of the message: its data, new
+	/// captcha, and captcha answer.
+	pub fn payload_digest(&self) -> Hash {
+		let mut hasher = blake3::Hasher::new();
+
+		// Length-prefix each part so that no two payloads share a preimage
+		for part in [
+			self.data.as_slice(),
+			self.new_captcha.data(),
+			self.new_captcha.answer().as_ref(),
+		] {
+			hasher.update(&(part.len() as u64).to_le_bytes());
+			hasher.update(part);
+		}
 
-		let lookback_bytes: [u8; 8] = hex::decode(relevant_digits).ok()?.try_into().ok()?;
-		Some(u64::from_le_bytes(lookback_bytes))
+		match self.captcha_ans() {
+			Some(ans) => {
+				hasher.update(&[1]);
+				hasher.update(ans.as_bytes());
+			}
+			None => {
+				hasher.update(&[0]);
+			}
+		}
+
+		hasher.finalize().into()
+	}
+
+	/// Calculates the number of messages before the parent of this message
+	/// whose captcha this message answers, given the lookback factor of the
+	/// chain. See [`lookback`].
+	pub fn lookback(&self, factor: usize) -> Option<usize> {
+		lookback(self.prev()?, self.height, factor)
+	}
+}
+
+/// A builder for [`MessageData`]. Fields that are not set default to those of
+/// a message with no predecessor.
+#[derive(Default, Debug)]
+pub struct MessageDataBuilder {
+	data: Vec<u8>,
+	prev: Option<Hash>,
+	captcha_ans: Option<String>,
+	captcha_src: Option<Hash>,
+	height: usize,
+	timestamp: u128,
+	chain_id: usize,
+	captcha: CaptchaParams,
+}
+
+impl MessageDataBuilder {
+	/// Sets the hash of the previous message in the chain.
+	pub fn prev(mut self, prev: Option<Hash>) -> Self {
+		self.prev = prev;
+		self
+	}
+
+	/// Sets the answer to the captcha of the message at captcha_src.
+	pub fn captcha_ans(mut self, captcha_ans: Option<String>) -> Self {
+		self.captcha_ans = captcha_ans;
+		self
+	}
+
+	/// Sets the hash of the message whose captcha the message answers.
+	pub fn captcha_src(mut self, captcha_src: Option<Hash>) -> Self {
+		self.captcha_src = captcha_src;
+		self
+	}
+
+	/// Sets the index of the message in the chain.
+	pub fn height(mut self, height: usize) -> Self {
+		self.height = height;
+		self
+	}
+
+	/// Sets the UNIX timestamp of the message, in milliseconds.
+	pub fn timestamp(mut self, timestamp: u128) -> Self {
+		self.timestamp = timestamp;
+		self
+	}
+
+	/// Sets the ID of the chain the message belongs to.
+	pub fn chain_id(mut self, chain_id: usize) -> Self {
+		self.chain_id = chain_id;
+		self
+	}
+
+	/// Sets the parameters with which the new captcha of the message is
+	/// generated.
+	pub fn captcha(mut self, captcha: CaptchaParams) -> Self {
+		self.captcha = captcha;
+		self
+	}
+
+	/// Fills in the previous message, height, captcha source, chain ID, and
+	/// captcha parameters such that the message extends the current longest
+	/// chain in the runtime, and stamps the message with the current time.
+	pub fn with_chain(self, rt: &Rt) -> Self {
+		let head = rt.head_header();
+		let captcha_src = head
+			.and_then(|head| rt.captcha_source_hash(head.hash()))
+			.cloned();
+
+		self.prev(head.map(|head| head.hash().clone()))
+			.height(head.map(|head| head.height() + 1).unwrap_or_default())
+			.captcha_src(captcha_src)
+			.timestamp(instant::now() as u128)
+			.chain_id(rt.chain_id())
+			.captcha(rt.captcha_params().clone())
+	}
+
+	/// Constructs the message data, generating a new captcha to attach to it.
+	pub fn build(self) -> MessageData {
+		MessageData {
+			data: self.data,
+			prev: self.prev,
+			new_captcha: Captcha::new(&self.captcha),
+			captcha_ans: self.captcha_ans,
+			captcha_src: self.captcha_src,
+			height: self.height,
+			timestamp: self.timestamp,
+			chain_id: self.chain_id,
+		}
+	}
+}
+
+/// Derives the number of messages before the parent `prev` of a message at
+/// `height` from which the captcha the message answers is sourced, given the
+/// lookback factor of the chain, by default [`CAPTCHA_ANS_LOOKBACK_FACTOR`].
+///
+/// The lookback is the first 8 bytes of the parent's hash, read as a little
+/// endian integer, modulo min(height, factor). It thus lies in
+/// [0, min(height, factor)), and never points past the first message in the
+/// chain. Returns None for messages at height 0, which answer no captcha.
+///
+/// [`CAPTCHA_ANS_LOOKBACK_FACTOR`]: super::CAPTCHA_ANS_LOOKBACK_FACTOR
+pub fn lookback(prev: &Hash, height: usize, factor: usize) -> Option<usize> {
+	let window = height.min(factor);
+	if window == 0 {
+		return None;
+	}
+
+	let mut lookback_bytes = [0; 8];
+	lookback_bytes.copy_from_slice(&prev[..8]);
+
+	Some((u64::from_le_bytes(lookback_bytes) % window as u64) as usize)
+}
+
+// The fields of a message from which its hash is calculated
+#[derive(Serialize)]
+struct HeaderPreimage<'a> {
+	prev: Option<&'a Hash>,
+	height: usize,
+	timestamp: u128,
+	captcha_src: Option<&'a Hash>,
+	payload_digest: &'a Hash,
+	chain_id: usize,
+}
+
+fn hash_header(
+	prev: Option<&Hash>,
+	height: usize,
+	timestamp: u128,
+	captcha_src: Option<&Hash>,
+	payload_digest: &Hash,
+	chain_id: usize,
+) -> Result<Hash, Error> {
+	let encoded = serde_json::to_vec(&HeaderPreimage {
+		prev,
+		height,
+		timestamp,
+		captcha_src,
+		payload_digest,
+		chain_id,
+	})?;
+
+	Ok(blake3::hash(encoded.as_slice()).into())
+}
+
+/// The compact part of a message, without its data or captcha image. Headers
+/// are enough to verify the hash of a message and the structure of the
+/// chain, while the payload may be fetched later and checked against the
+/// payload digest.
+#[derive(Clone, Hash, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct MessageHeader {
+	hash: Hash,
+	prev: Option<Hash>,
+	height: usize,
+	timestamp: u128,
+	captcha_src: Option<Hash>,
+	payload_digest: Hash,
+
+	// Headers persisted before chains were told apart belong to chain 0
+	#[serde(default)]
+	chain_id: usize,
+}
+
+impl MessageHeader {
+	/// Gets the hash of the message.
+	pub fn hash(&self) -> &Hash {
+		&self.hash
+	}
+
+	/// Gets hash of the previous message in the chain.
+	pub fn prev(&self) -> Option<&Hash> {
+		self.prev.as_ref()
+	}
+
+	/// Gets the index of the message in the chain.
+	pub fn height(&self) -> usize {
+		self.height
+	}
+
+	/// Gets the UNIX timestamp of the message.
+	pub fn timestamp(&self) -> u128 {
+		self.timestamp
+	}
+
+	/// Gets the hash of the message whose captcha the message answers.
+	pub fn captcha_src(&self) -> Option<&Hash> {
+		self.captcha_src.as_ref()
+	}
+
+	/// Gets the digest of the payload of the message.
+	pub fn payload_digest(&self) -> &Hash {
+		&self.payload_digest
+	}
+
+	/// Gets the ID of the chain the message belongs to.
+	pub fn chain_id(&self) -> usize {
+		self.chain_id
+	}
+
+	/// Calculates the hash of the message from its header. Equals
+	/// [`MessageHeader::hash`] for genuine headers.
+	pub fn hashed(&self) -> Result<Hash, Error> {
+		hash_header(
+			self.prev(),
+			self.height,
+			self.timestamp,
+			self.captcha_src(),
+			&self.payload_digest,
+			self.chain_id,
+		)
 	}
 }
 
@@ -124,6 +388,19 @@ impl Message {
 		&self.hash
 	}
 
+	/// Gets the header of the message.
+	pub fn header(&self) -> MessageHeader {
+		MessageHeader {
+			hash: self.hash.clone(),
+			prev: self.data.prev.clone(),
+			height: self.data.height,
+			timestamp: self.data.timestamp,
+			captcha_src: self.data.captcha_src.clone(),
+			payload_digest: self.data.payload_digest(),
+			chain_id: self.data.chain_id,
+		}
+	}
+
 	/// Deserializes the data inside the message to some deserializable type.
 	pub fn as_data<'a, T: Deserialize<'a>>(&'a self) -> Result<T, Error> {
 		serde_json::from_slice(self.data().data())
@@ -132,20 +409,134 @@ impl Message {
 
 #[cfg(test)]
 mod tests {
-	use super::*;
+	use super::{super::CAPTCHA_ANS_LOOKBACK_FACTOR, *};
 
 	#[test]
 	fn test_new() {
-		let data = MessageData::new(Vec::new(), None, None, None, 0, 0);
+		let data = MessageData::new(Vec::new(), None, None, None, 0, 0, 0);
 		assert_eq!(data.data, <Vec<u8>>::new());
 		assert_eq!(data.prev, None);
 		assert_eq!(data.captcha_ans, None);
 		assert_eq!(data.timestamp, 0);
 	}
 
+	#[test]
+	fn test_lookback() {
+		let data = MessageData::new(Vec::new(), None, None, None, 0, 0, 0);
+		assert_eq!(data.lookback(CAPTCHA_ANS_LOOKBACK_FACTOR), None);
+
+		let prev: Hash = [7; 32].into();
+		let data = MessageData::new(Vec::new(), Some(prev.clone()), None, None, 1, 0, 0);
+		assert_eq!(data.lookback(CAPTCHA_ANS_LOOKBACK_FACTOR), Some(0));
+
+		// The first 8 bytes of the parent's hash, here 0x0707070707070707,
+		// read as a little endian integer modulo min(height, factor)
+		for (height, expected) in (1..).zip([0, 1, 2, 3, 1, 1, 1]) {
+			assert_eq!(lookback(&prev, height, 5), Some(expected));
+		}
+
+		// Only those bytes are read
+		let mut bytes: [u8; 32] = [7; 32];
+		bytes[8..].copy_from_slice(&[0; 24]);
+		assert_eq!(lookback(&bytes.into(), 4, 5), Some(3));
+
+		// A different parent looks back elsewhere
+		let mut bytes: [u8; 32] = [0; 32];
+		bytes[0] = 3;
+		let other: Hash = bytes.into();
+		for (height, expected) in (1..).zip([0, 1, 0, 3, 3, 3, 3]) {
+			assert_eq!(lookback(&other, height, 5), Some(expected));
+		}
+
+		// Chains may look back further
+		for (height, expected) in [(31, 15), (32, 7), (40, 7)] {
+			assert_eq!(lookback(&prev, height, 32), Some(expected));
+		}
+	}
+
+	#[test]
+	fn test_builder() -> Result<(), Error> {
+		let data = MessageData::builder(vec![1, 2, 3]).build();
+		assert_eq!(data.data, vec![1, 2, 3]);
+		assert_eq!(data.prev, None);
+		assert_eq!(data.height, 0);
+
+		// Messages built on a chain should extend its HEAD
+		let mut rt = Rt::default();
+		let root = Message::try_from(data)?;
+		rt.insert_message(root.clone());
+
+		let data = MessageData::builder(Vec::new())
+			.with_chain(&rt)
+			.captcha_ans(Some("answer".into()))
+			.build();
+		assert_eq!(data.prev(), Some(root.hash()));
+		assert_eq!(data.captcha_src(), Some(root.hash()));
+		assert_eq!(data.captcha_ans(), Some("answer"));
+		assert_eq!(data.height(), 1);
+		assert!(data.timestamp() > root.data().timestamp());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_header() -> Result<(), Error> {
+		let root = Message::try_from(MessageData::new(vec![1], None, None, None, 0, 0, 0))?;
+		let header = root.header();
+		assert_eq!(header.hash(), root.hash());
+		assert_eq!(header.hashed()?, *root.hash());
+		assert_eq!(header.payload_digest(), &root.data().payload_digest());
+
+		// Headers commit to the payload of the message
+		let other = Message::try_from(MessageData::new(vec![2], None, None, None, 0, 0, 0))?;
+		assert_ne!(header.payload_digest(), other.header().payload_digest());
+		assert_ne!(header.hashed()?, *other.hash());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_chain_id() -> Result<(), Error> {
+		let data = MessageData::builder(vec![1]).build();
+		assert_eq!(data.chain_id(), 0);
+
+		// The same message on another chain has another hash
+		let other = MessageData::builder(vec![1]).chain_id(1).build();
+		let other = Message::try_from(MessageData {
+			new_captcha: data.new_captcha.clone(),
+			..other
+		})?;
+		let msg = Message::try_from(data)?;
+		assert_eq!(other.header().chain_id(), 1);
+		assert_ne!(msg.hash(), other.hash());
+		assert_eq!(other.header().hashed()?, *other.hash());
+
+		// Messages built on a chain belong to it
+		let mut rt = Rt::default();
+		rt.set_chain_id(1);
+		let data = MessageData::builder(Vec::new()).with_chain(&rt).build();
+		assert_eq!(data.chain_id(), 1);
+		assert_eq!(
+			MessageData::new(Vec::new(), None, None, None, 0, 0, 1).chain_id(),
+			1
+		);
+
+		// Messages persisted without a chain ID belong to chain 0
+		let mut persisted = serde_json::to_value(&data)?;
+		if let Some(fields) = persisted.as_object_mut() {
+			fields.remove("chain_id");
+		}
+		assert_eq!(
+			serde_json::from_value::<MessageData>(persisted)?.chain_id(),
+			0
+		);
+
+		Ok(())
+	}
+
 	#[test]
 	fn test_try_from() -> Result<(), Error> {
-		let data = MessageData::new(Vec::new(), None, None, None, 0, 0);
+		let data = MessageData::new(Vec::new(), None, None, None, 0, 0, 0);
 		let _ = Message::try_from(data)?;
 
 		Ok(())
@@ -1,88 +1,1006 @@
-use super::{super::crypto::hash::Hash, msg::Message};
-use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use super::{
+	super::{captcha::CaptchaParams, crypto::hash::Hash},
+	fork::{ForkChoice, Head, HighestChain},
+	msg::{self, Message, MessageHeader},
+	spec::{ChainSpec, Genesis},
+	CAPTCHA_ANS_LOOKBACK_FACTOR, HASH_VERSION,
+};
+use serde::{ser::SerializeStruct, Deserialize, Serialize, Serializer};
+use std::{
+	collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+	iter, mem,
+};
 
 /// A caching layer for the underlying DHT of messages in the CHUD network.
-#[derive(Default, Debug, Serialize, Deserialize, PartialEq, Eq)]
+/// Messages are arranged in a tree linked by their previous messages, in
+/// which the longest chain is chosen among the leaves by a [`ForkChoice`]
+/// rule.
+///
+/// The tree is built from message headers, whose payloads may arrive later,
+/// or never in light clients. Outside of light clients, only chains whose
+/// payloads are all known may become the longest chain. Chains are rooted
+/// at the genesis message of the [`ChainSpec`], or at any message with no
+/// previous message if there is no spec, or at a trusted root restored from
+/// a snapshot.
+#[derive(Debug, Deserialize)]
+#[serde(from = "SerializedRt")]
 pub struct Rt {
 	messages: HashMap<Hash, Message>,
-	chains: Vec<Chain>,
+
+	// The headers of all known messages, with or without their payloads
+	headers: HashMap<Hash, MessageHeader>,
+
+	// The messages trusted to root a chain without their history
+	roots: HashSet<Hash>,
+
+	// The position of each message in the tree
+	#[serde(skip)]
+	nodes: HashMap<Hash, Node>,
+
+	// The messages referencing each previous message, whether or not the
+	// previous message is known
+	#[serde(skip)]
+	children: HashMap<Hash, HashSet<Hash>>,
+
+	// The messages with no known children
+	#[serde(skip)]
+	heads: HashSet<Hash>,
+
+	// The messages whose payloads are known, by claimed height
+	#[serde(skip)]
+	by_height: BTreeMap<usize, BTreeSet<Hash>>,
+
+	// The number of messages inserted into the runtime
+	#[serde(skip)]
+	n_seen: u64,
+
+	// The rule used to choose the longest chain
+	#[serde(skip)]
+	fork_choice: Box<dyn ForkChoice>,
+
+	// Reorgs which have not yet been reported
+	#[serde(skip)]
+	reorgs: Vec<Reorg>,
+
+	// The hashes every valid chain must include, by height
+	#[serde(skip)]
+	checkpoints: HashMap<usize, Hash>,
+
+	// The maximum number of messages a reorg may detach from the chain
+	#[serde(skip)]
+	max_reorg_depth: Option<usize>,
+
+	// The HEAD of the longest chain
+	#[serde(skip)]
+	head: Option<Hash>,
+
+	// The hashes of the messages of the longest chain, by claimed height
+	#[serde(skip)]
+	canonical: BTreeMap<usize, Hash>,
+
+	// Whether chains may become the longest chain without their payloads
+	#[serde(skip)]
+	light: bool,
+
+	// The ID of the chain whose messages are stored
+	#[serde(skip)]
+	chain_id: usize,
+
+	// The only message with no previous message which may root a chain
+	#[serde(skip)]
+	genesis: Option<Hash>,
+
+	// The parameters of the chain's captchas
+	#[serde(skip)]
+	captcha_params: CaptchaParams,
+
+	// The maximum number of messages a captcha source may sit before the
+	// parent of the message answering it
+	#[serde(skip)]
+	lookback_factor: usize,
+
+	// The number of milliseconds by which message timestamps may be ahead
+	// of the local clock
+	#[serde(skip)]
+	timestamp_tolerance: u64,
+}
+
+impl Default for Rt {
+	fn default() -> Self {
+		Self {
+			messages: HashMap::default(),
+			headers: HashMap::default(),
+			roots: HashSet::default(),
+			nodes: HashMap::default(),
+			children: HashMap::default(),
+			heads: HashSet::default(),
+			by_height: BTreeMap::default(),
+			n_seen: 0,
+			fork_choice: Box::new(HighestChain),
+			reorgs: Vec::new(),
+			checkpoints: HashMap::default(),
+			max_reorg_depth: None,
+			head: None,
+			canonical: BTreeMap::default(),
+			light: false,
+			chain_id: 0,
+			genesis: None,
+			captcha_params: CaptchaParams::default(),
+			lookback_factor: CAPTCHA_ANS_LOOKBACK_FACTOR,
+			timestamp_tolerance: 0,
+		}
+	}
+}
+
+impl PartialEq for Rt {
+	fn eq(&self, other: &Self) -> bool {
+		// The tree is entirely derived from the headers
+		self.messages == other.messages
+			&& self.headers == other.headers
+			&& self.roots == other.roots
+	}
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
-struct Chain {
-	head: Hash,
-	messages: HashSet<Hash>,
+impl Eq for Rt {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Node {
+	// The number of known ancestors of the message, or its height if it is
+	// a trusted root
+	height: usize,
+
+	// The order in which the message was inserted into the runtime
+	seen_at: u64,
+
+	// Whether all ancestors of the message are known, back to a message
+	// with no previous message or a trusted root
+	rooted: bool,
+
+	// Whether the message or any of its ancestors contradicts a checkpoint
+	contradicts_checkpoint: bool,
+
+	// Whether the payload of the message is known
+	has_body: bool,
+
+	// Whether the payloads of the message and all of its ancestors are
+	// known, back to a message with no previous message
+	complete: bool,
+
+	// The number of distinct captcha answers in the chain ending with the
+	// message, among the messages whose payloads are known
+	answers: usize,
+}
+
+impl Serialize for Rt {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		// Messages are listed in the order they were seen, such that the
+		// tree is rebuilt with the same order
+		let mut seen = self.headers.keys().collect::<Vec<&Hash>>();
+		seen.sort_by_key(|hash| self.seen_at(hash));
+
+		let mut state = serializer.serialize_struct("Rt", 5)?;
+		state.serialize_field("version", &HASH_VERSION)?;
+		state.serialize_field("messages", &self.messages)?;
+		state.serialize_field("headers", &self.headers)?;
+		state.serialize_field("roots", &self.roots)?;
+		state.serialize_field("seen", &seen)?;
+		state.end()
+	}
+}
+
+// The persisted form of the runtime, from which the tree is rebuilt
+#[derive(Deserialize)]
+struct SerializedRt {
+	// The hash scheme of the messages, which predates versioning if missing
+	#[serde(default)]
+	version: u32,
+
+	messages: HashMap<Hash, Message>,
+
+	#[serde(default)]
+	headers: HashMap<Hash, MessageHeader>,
+
+	#[serde(default)]
+	roots: HashSet<Hash>,
+
+	#[serde(default)]
+	seen: Vec<Hash>,
+}
+
+impl From<SerializedRt> for Rt {
+	fn from(serialized: SerializedRt) -> Self {
+		let mut rt = Rt::default();
+
+		// None of the messages would hash correctly
+		if serialized.version != HASH_VERSION {
+			error!(
+				"Discarding the persisted chain, whose messages are hashed under version {} \
+				 of the hash scheme instead of version {}. It will be synchronized again.",
+				serialized.version, HASH_VERSION
+			);
+
+			return rt;
+		}
+
+		rt.roots = serialized.roots;
+
+		let mut headers = serialized.headers;
+		let mut messages = serialized.messages;
+
+		// Messages are inserted in the order they were seen, followed by any
+		// persisted before the order was
+		for hash in serialized.seen {
+			match (messages.remove(&hash), headers.remove(&hash)) {
+				(Some(msg), _) => rt.insert_message(msg),
+				(None, Some(header)) => rt.insert_header(header),
+				(None, None) => None,
+			};
+		}
+
+		for header in headers.into_values() {
+			rt.insert_header(header);
+		}
+
+		for msg in messages.into_values() {
+			rt.insert_message(msg);
+		}
+
+		// Rebuilding the tree is not a reorg
+		rt.reorgs.clear();
+
+		rt
+	}
+}
+
+/// A switch of the longest chain to a fork which does not extend the
+/// previous HEAD.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Reorg {
+	/// The HEAD of the chain before the switch
+	pub old_head: Hash,
+
+	/// The HEAD of the chain after the switch
+	pub new_head: Hash,
+
+	/// The messages which left the longest chain, from the old HEAD back
+	/// towards the common ancestor. Applications should roll these back in
+	/// this order.
+	pub detached: Vec<Hash>,
+
+	/// The messages which joined the longest chain, from the common
+	/// ancestor up to the new HEAD. Applications should replay these in
+	/// this order.
+	pub attached: Vec<Hash>,
+}
+
+/// A message which every valid chain must include at the given height.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Checkpoint {
+	/// The height of the message
+	pub height: usize,
+
+	/// The hash of the message
+	pub hash: Hash,
+}
+
+/// An iterator over the known ancestors of a message, from its previous
+/// message back towards the root of the chain.
+pub struct Ancestors<'a> {
+	rt: &'a Rt,
+	next: Option<&'a Hash>,
+}
+
+impl<'a> Iterator for Ancestors<'a> {
+	type Item = &'a MessageHeader;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let header = self.rt.headers.get(self.next?)?;
+		self.next = header.prev();
+
+		Some(header)
+	}
 }
 
 impl Rt {
 	/// Registers the message in the runtime, updating the consensus view if need be.
-	/// Assumes the message is valid per consensus rules.
-	pub fn insert_message(&mut self, msg: Message) {
-		self.update_head(msg.hash().clone(), msg.data().prev().cloned());
-		self.messages.insert(msg.hash().clone(), msg);
-	}
-
-	/// Advances the head that is the previous node referenced by the message, or else
-	/// inserts the message as a new head.
-	fn update_head(&mut self, new_head: Hash, prev: Option<Hash>) {
-		// Update head that is prev to the new message,
-		// or else insert the message as a new head
-		for chain in self.chains.iter_mut() {
-			if let Some(prev) = prev.as_ref() {
-				if prev == &chain.head {
-					chain.head = new_head.clone();
-					chain.messages.insert(new_head.clone());
-
-					return;
+	/// Assumes the message is valid per consensus rules. Messages may be
+	/// inserted before their previous message, in which case they are attached
+	/// to the tree once it arrives.
+	///
+	/// Returns the reorg caused by the message if the longest chain switched
+	/// to a fork. Reorgs are also queued until taken with
+	/// [`Rt::take_reorgs`].
+	pub fn insert_message(&mut self, msg: Message) -> Option<Reorg> {
+		let hash = msg.hash().clone();
+		if self.messages.contains_key(&hash) {
+			return None;
+		}
+
+		let header = msg.header();
+		self.messages.insert(hash.clone(), msg);
+		self.by_height
+			.entry(header.height())
+			.or_default()
+			.insert(hash.clone());
+
+		if !self.headers.contains_key(&hash) {
+			return self.insert_header(header);
+		}
+
+		// The payload completes a message whose header was already known
+		let parent_complete = self.roots.contains(&hash)
+			|| header
+				.prev()
+				.map(|prev| self.is_complete(prev))
+				.unwrap_or_else(|| self.is_genesis(&hash));
+		let parent_answers = header
+			.prev()
+			.and_then(|prev| self.nodes.get(prev))
+			.map(|parent| parent.answers)
+			.unwrap_or_default();
+		let answers = self.count_answers(&hash, parent_answers);
+		if let Some(node) = self.nodes.get_mut(&hash) {
+			node.has_body = true;
+			node.complete = parent_complete;
+			node.answers = answers;
+		}
+		self.reposition(&hash);
+
+		self.update_head()
+	}
+
+	/// Registers the header of a message in the runtime without its payload.
+	/// Assumes the header is valid per consensus rules. Outside of light
+	/// clients, the message cannot be part of the longest chain until its
+	/// payload is inserted with [`Rt::insert_message`].
+	///
+	/// Returns the reorg caused by the header, as with [`Rt::insert_message`].
+	pub fn insert_header(&mut self, header: MessageHeader) -> Option<Reorg> {
+		let hash = header.hash().clone();
+		if self.headers.contains_key(&hash) {
+			return None;
+		}
+
+		let prev = header.prev().cloned();
+		let contradicts_checkpoint = self.contradicts_checkpoint(&hash, header.height());
+		let has_body = self.messages.contains_key(&hash);
+		let is_root = self.roots.contains(&hash);
+		let is_genesis = prev.is_none() && self.is_genesis(&hash);
+		let height = header.height();
+		self.headers.insert(hash.clone(), header);
+
+		// Attach the message to its parent, or make it the root of a new subtree
+		let seen_at = self.n_seen;
+		self.n_seen += 1;
+
+		let parent = prev.as_ref().and_then(|prev| self.nodes.get(prev)).copied();
+		let answers = self.count_answers(
+			&hash,
+			parent.map(|parent| parent.answers).unwrap_or_default(),
+		);
+
+		let node = match parent {
+			Some(parent) => Node {
+				height: parent.height + 1,
+				seen_at,
+				rooted: parent.rooted || is_root,
+				contradicts_checkpoint: parent.contradicts_checkpoint || contradicts_checkpoint,
+				has_body,
+				complete: has_body && (parent.complete || is_root),
+				answers,
+			},
+			None => Node {
+				height: if is_root { height } else { 0 },
+				seen_at,
+				rooted: is_genesis || is_root,
+				contradicts_checkpoint,
+				has_body,
+				complete: has_body && (is_genesis || is_root),
+				answers,
+			},
+		};
+		self.nodes.insert(hash.clone(), node);
+
+		if let Some(prev) = prev {
+			self.heads.remove(&prev);
+			self.children.entry(prev).or_default().insert(hash.clone());
+		}
+
+		if self
+			.children
+			.get(&hash)
+			.map(HashSet::is_empty)
+			.unwrap_or(true)
+		{
+			self.heads.insert(hash.clone());
+		}
+
+		self.reposition(&hash);
+
+		self.update_head()
+	}
+
+	/// Registers the message as a trusted root of the chain, such that chains
+	/// descending from it may become the longest chain without its history.
+	/// Assumes the message is trusted, as is the case for the messages of a
+	/// verified [`Snapshot`](super::snapshot::Snapshot).
+	///
+	/// Returns the reorg caused by the message, as with [`Rt::insert_message`].
+	pub fn insert_root(&mut self, msg: Message) -> Option<Reorg> {
+		let hash = msg.hash().clone();
+
+		// Messages already in the tree become the root of their subtree
+		if self.roots.insert(hash.clone()) {
+			let height = self.headers.get(&hash).map(MessageHeader::height);
+
+			if let Some((node, height)) = self.nodes.get_mut(&hash).zip(height) {
+				node.height = height;
+				node.rooted = true;
+				node.complete = node.has_body;
+			}
+			if self.nodes.contains_key(&hash) {
+				self.reposition(&hash);
+			}
+		}
+
+		match self.insert_message(msg) {
+			Some(reorg) => Some(reorg),
+			None => self.update_head(),
+		}
+	}
+
+	// Determines whether the message with the indicated hash may root a
+	// chain without a previous message
+	fn is_genesis(&self, hash: &Hash) -> bool {
+		self.genesis
+			.as_ref()
+			.map(|genesis| genesis == hash)
+			.unwrap_or(true)
+	}
+
+	// Counts the distinct captcha answers in the chain ending with the
+	// message with the indicated hash, given the count of its parent's chain.
+	// Answers repeating that of an ancestor within the lookback window are
+	// not counted again. Messages answering the same captcha always fall in
+	// one window, so only as many ancestors as the lookback factor are walked.
+	fn count_answers(&self, hash: &Hash, parent_answers: usize) -> usize {
+		let captcha_ans = match self
+			.messages
+			.get(hash)
+			.and_then(|msg| msg.data().captcha_ans())
+		{
+			Some(captcha_ans) => captcha_ans,
+			None => return parent_answers,
+		};
+
+		let repeated = self
+			.ancestors(hash)
+			.take(self.lookback_factor)
+			.filter_map(|header| self.messages.get(header.hash()))
+			.any(|msg| msg.data().captcha_ans() == Some(captcha_ans));
+
+		if repeated {
+			parent_answers
+		} else {
+			parent_answers + 1
+		}
+	}
+
+	// Repositions the descendants of the message with the indicated hash,
+	// which may have arrived before it
+	fn reposition(&mut self, hash: &Hash) {
+		let mut to_update = vec![hash.clone()];
+		while let Some(parent) = to_update.pop() {
+			let parent_node = self.nodes[&parent];
+
+			for child in self.children.get(&parent).into_iter().flatten() {
+				let contradicts_checkpoint = self
+					.headers
+					.get(child)
+					.and_then(|child_header| self.checkpoints.get(&child_header.height()))
+					.map(|checkpoint| checkpoint != child)
+					.unwrap_or_default();
+
+				let is_root = self.roots.contains(child);
+				let answers = self.count_answers(child, parent_node.answers);
+
+				if let Some(child_node) = self.nodes.get_mut(child) {
+					child_node.height = parent_node.height + 1;
+					child_node.answers = answers;
+					child_node.rooted = parent_node.rooted || is_root;
+					child_node.contradicts_checkpoint =
+						parent_node.contradicts_checkpoint || contradicts_checkpoint;
+					child_node.complete = child_node.has_body && (parent_node.complete || is_root);
+
+					to_update.push(child.clone());
 				}
 			}
 		}
+	}
+
+	// Reselects the HEAD of the longest chain, queueing and returning the
+	// reorg if the chain switched to a fork
+	fn update_head(&mut self) -> Option<Reorg> {
+		let new_head = self.choose_head(!self.light);
+		let old_head = mem::replace(&mut self.head, new_head);
+		self.update_canonical();
+
+		let reorg = old_head
+			.zip(self.head.clone())
+			.and_then(|(old_head, new_head)| self.reorg(old_head, new_head));
+		if let Some(reorg) = &reorg {
+			self.reorgs.push(reorg.clone());
+		}
 
-		self.chains.push(Chain {
-			head: new_head.clone(),
-			messages: HashSet::from([new_head]),
-		});
+		reorg
 	}
 
-	/// Determines the longest chain in the runtime, returning None if no chains exist.
-	pub fn longest_chain(&self) -> Option<&Hash> {
-		let mut heads = self
-			.chains
-			.as_slice()
+	// Brings the index of the longest chain in line with the current HEAD.
+	// Only the messages attached since the last update are walked, unless
+	// the chain no longer shares any history with the indexed one.
+	fn update_canonical(&mut self) {
+		let head = match self.head_header() {
+			Some(head) => head,
+			None => {
+				self.canonical.clear();
+
+				return;
+			}
+		};
+
+		// Walk back from the HEAD until the index agrees with the chain
+		let mut met = false;
+		let mut attached = Vec::new();
+		for header in iter::once(head).chain(self.ancestors(head.hash())) {
+			if self.canonical.get(&header.height()) == Some(header.hash()) {
+				met = true;
+
+				break;
+			}
+
+			attached.push((header.height(), header.hash().clone()));
+		}
+
+		// Ancestors of the indexed chain may have arrived since
+		let extended = if met {
+			self.canonical
+				.values()
+				.next()
+				.into_iter()
+				.flat_map(|lowest| self.ancestors(lowest))
+				.map(|header| (header.height(), header.hash().clone()))
+				.collect::<Vec<(usize, Hash)>>()
+		} else {
+			Vec::new()
+		};
+
+		let head_height = head.height();
+		if !met {
+			self.canonical.clear();
+		}
+		if let Some(above) = head_height.checked_add(1) {
+			self.canonical.split_off(&above);
+		}
+		self.canonical.extend(attached.into_iter().chain(extended));
+	}
+
+	// Picks the HEAD preferred by the fork-choice rule among the chains
+	// which are fully known, honor every checkpoint, and do not rewind the
+	// current HEAD past the maximum reorg depth. Chains missing payloads are
+	// skipped if require_bodies is set.
+	fn choose_head(&self, require_bodies: bool) -> Option<Hash> {
+		self.heads
+			.iter()
+			.filter_map(|head| Some((head, self.nodes.get(head)?)))
+			.filter(|(_, node)| node.rooted && !node.contradicts_checkpoint)
+			.filter(|(_, node)| node.complete || !require_bodies)
+			.filter(|(hash, _)| {
+				self.max_reorg_depth
+					.map(|max_depth| !self.rewinds_past(hash, max_depth))
+					.unwrap_or(true)
+			})
+			.map(|(hash, node)| Head {
+				hash,
+				height: node.height,
+			})
+			.max_by(|a, b| self.fork_choice.compare(self, *a, *b))
+			.map(|head| head.hash.clone())
+	}
+
+	// Determines whether switching to the indicated HEAD would detach more
+	// than max_depth messages from the current chain. The candidate chain is
+	// only walked down to max_depth messages below the current HEAD, looking
+	// for a message of the current chain.
+	fn rewinds_past(&self, candidate: &Hash, max_depth: usize) -> bool {
+		let head_height = match self.head.as_ref().and_then(|head| self.height(head)) {
+			Some(head_height) => head_height,
+			None => return false,
+		};
+		let floor = head_height.saturating_sub(max_depth);
+
+		let meets_chain = self
+			.headers
+			.get(candidate)
 			.into_iter()
-			.filter_map(|chain| self.messages.get(&chain.head))
-			.collect::<Vec<&Message>>();
+			.chain(self.ancestors(candidate))
+			.take_while(|header| header.height() >= floor)
+			.any(|header| self.canonical.get(&header.height()) == Some(header.hash()));
 
-		heads.sort_by(|a, b| usize::cmp(&b.data().height(), &a.data().height()));
-		heads.get(0).map(|msg| msg.hash())
+		// Chains sharing no history with the current one detach all of it
+		!meets_chain && head_height >= max_depth
 	}
 
-	/// Gets the message in the current chain with the indicated hash.
-	/// Returns None if the message does not exist, or if it is not in
-	/// the current chain.
-	pub fn get_message(&self, hash: &Hash) -> Option<&Message> {
-		let chain = self
-			.longest_chain()
-			.and_then(|hash| self.chains.iter().find(|chain| &chain.head == hash))?;
+	// Rebuilds the tree from the stored headers, in the order they were seen
+	fn rebuild(&mut self) {
+		let old_head = self.head.clone();
+
+		let mut headers = mem::take(&mut self.headers)
+			.into_values()
+			.collect::<Vec<MessageHeader>>();
+		headers.sort_by_key(|header| self.seen_at(header.hash()));
+		let mut messages = mem::take(&mut self.messages);
+
+		let reorgs = mem::take(&mut self.reorgs);
+		let roots = mem::take(&mut self.roots);
+		self.flush();
+		self.roots = roots;
+		for header in headers {
+			match messages.remove(header.hash()) {
+				Some(msg) => self.insert_message(msg),
+				None => self.insert_header(header),
+			};
+		}
+
+		// Only report the switch between the old and new HEADs
+		self.reorgs = reorgs;
+		if let Some(reorg) = old_head
+			.zip(self.head.clone())
+			.and_then(|(old_head, new_head)| self.reorg(old_head, new_head))
+		{
+			self.reorgs.push(reorg);
+		}
+	}
 
-		if !chain.messages.contains(&hash) {
+	// Determines the messages detached and attached by switching HEADs,
+	// if the new HEAD does not extend the old one
+	fn reorg(&self, old_head: Hash, new_head: Hash) -> Option<Reorg> {
+		if old_head == new_head || self.is_ancestor(&old_head, &new_head) {
 			return None;
+		}
+
+		let common = self.common_ancestor(&old_head, &new_head);
+		let branch = |head: &Hash| {
+			iter::once(head)
+				.chain(self.ancestors(head).map(MessageHeader::hash))
+				.take_while(|hash| Some(*hash) != common)
+				.cloned()
+				.collect::<Vec<Hash>>()
 		};
 
-		self.messages.get(&hash)
+		let detached = branch(&old_head);
+		let mut attached = branch(&new_head);
+		attached.reverse();
+
+		Some(Reorg {
+			old_head,
+			new_head,
+			detached,
+			attached,
+		})
+	}
+
+	/// Takes all reorgs which have occurred since the last call, oldest first.
+	pub fn take_reorgs(&mut self) -> Vec<Reorg> {
+		mem::take(&mut self.reorgs)
+	}
+
+	/// Sets the rule used to choose the longest chain.
+	pub fn set_fork_choice(&mut self, fork_choice: Box<dyn ForkChoice>) {
+		self.fork_choice = fork_choice;
+		self.update_head();
+	}
+
+	/// Gets the rule used to choose the longest chain.
+	pub fn fork_choice(&self) -> &dyn ForkChoice {
+		self.fork_choice.as_ref()
+	}
+
+	/// Sets the messages every valid chain must include. Chains which
+	/// contradict a checkpoint are never chosen as the longest chain.
+	pub fn set_checkpoints(&mut self, checkpoints: impl IntoIterator<Item = Checkpoint>) {
+		self.checkpoints = checkpoints
+			.into_iter()
+			.map(|Checkpoint { height, hash }| (height, hash))
+			.collect();
+		self.rebuild();
+	}
+
+	/// Sets the maximum number of messages a reorg may detach from the
+	/// longest chain. Forks deeper than this are never switched to.
+	pub fn set_max_reorg_depth(&mut self, max_reorg_depth: Option<usize>) {
+		self.max_reorg_depth = max_reorg_depth;
+	}
+
+	/// Determines whether the message with the indicated hash and height
+	/// contradicts a checkpoint.
+	pub fn contradicts_checkpoint(&self, hash: &Hash, height: usize) -> bool {
+		self.checkpoints
+			.get(&height)
+			.map(|checkpoint| checkpoint != hash)
+			.unwrap_or_default()
+	}
+
+	/// Determines whether the message with the indicated hash and height
+	/// would fork the longest chain deeper than the maximum reorg depth.
+	/// Messages that are already known never do.
+	pub fn exceeds_reorg_depth(&self, hash: &Hash, height: usize) -> bool {
+		match self
+			.max_reorg_depth
+			.zip(self.head.as_ref().and_then(|head| self.height(head)))
+		{
+			Some((max_depth, head_height)) => {
+				!self.headers.contains_key(hash) && height.saturating_add(max_depth) <= head_height
+			}
+			None => false,
+		}
+	}
+
+	/// Determines the longest chain in the runtime per the fork-choice rule,
+	/// returning None if no chains exist. Only chains whose history is fully
+	/// known, and which honor every checkpoint, are considered. Outside of
+	/// light clients, this includes the payloads of the chain.
+	pub fn longest_chain(&self) -> Option<&Hash> {
+		self.head.as_ref()
+	}
+
+	/// Gets the message with the indicated hash, regardless of which chain it
+	/// is in. Returns None if the message does not exist.
+	pub fn get_message(&self, hash: &Hash) -> Option<&Message> {
+		self.messages.get(hash)
+	}
+
+	/// Gets the header of the message with the indicated hash, whether or not
+	/// its payload is known.
+	pub fn get_header(&self, hash: &Hash) -> Option<&MessageHeader> {
+		self.headers.get(hash)
+	}
+
+	/// Determines whether the payloads of the message with the indicated
+	/// hash and all of its ancestors are known.
+	pub fn is_complete(&self, hash: &Hash) -> bool {
+		self.nodes
+			.get(hash)
+			.map(|node| node.complete)
+			.unwrap_or_default()
+	}
+
+	/// Gets the hashes of up to limit messages whose payloads are missing
+	/// from the best chain of headers, in ascending order of height. These
+	/// are the payloads needed for the chain to become the longest chain.
+	pub fn missing_bodies(&self, limit: usize) -> Vec<Hash> {
+		let head = match self.choose_head(false) {
+			Some(head) => head,
+			None => return Vec::new(),
+		};
+
+		let mut missing = iter::once(&head)
+			.chain(self.ancestors(&head).map(MessageHeader::hash))
+			.take_while(|hash| !self.is_complete(hash))
+			.filter(|hash| !self.messages.contains_key(*hash))
+			.cloned()
+			.collect::<Vec<Hash>>();
+		missing.reverse();
+		missing.truncate(limit);
+
+		missing
+	}
+
+	/// Gets the hashes of all messages whose payloads are known, whether or
+	/// not they are part of the longest chain, with heights from from_height
+	/// up to, but not including, to_height. Hashes are ordered by height,
+	/// then by value.
+	pub fn messages_between(
+		&self,
+		from_height: usize,
+		to_height: usize,
+	) -> impl Iterator<Item = &Hash> {
+		self.by_height
+			.range(from_height..to_height.max(from_height))
+			.flat_map(|(_, hashes)| hashes)
+	}
+
+	/// Gets the greatest height of any message whose payload is known.
+	pub fn max_height(&self) -> Option<usize> {
+		self.by_height.keys().next_back().copied()
+	}
+
+	/// Sets whether the runtime is that of a light client, whose longest
+	/// chain does not require the payloads of its messages.
+	pub fn set_light(&mut self, light: bool) {
+		self.light = light;
+		self.update_head();
+	}
+
+	/// Determines whether the runtime is that of a light client.
+	pub fn is_light(&self) -> bool {
+		self.light
+	}
+
+	/// Sets the ID of the chain whose messages are stored. Messages of other
+	/// chains are rejected by consensus.
+	pub fn set_chain_id(&mut self, chain_id: usize) {
+		self.chain_id = chain_id;
+	}
+
+	/// Gets the ID of the chain whose messages are stored.
+	pub fn chain_id(&self) -> usize {
+		self.chain_id
+	}
+
+	/// Sets the spec of the chain: its genesis message, captcha parameters,
+	/// and consensus constants. Only chains rooted at the genesis message,
+	/// or at a trusted root, may become the longest chain. The checkpoints
+	/// of the spec are set with [`Rt::set_checkpoints`].
+	pub fn set_spec(&mut self, spec: &ChainSpec) {
+		self.genesis = Some(spec.genesis_hash().clone());
+		self.captcha_params = spec.captcha.clone();
+		self.lookback_factor = spec.lookback_factor;
+		self.timestamp_tolerance = spec.timestamp_tolerance;
+		self.rebuild();
+
+		if let Genesis::Message(genesis) = &spec.genesis {
+			self.insert_message(genesis.clone());
+		}
+	}
+
+	/// Gets the hash of the genesis message of the chain, if the chain has
+	/// a spec.
+	pub fn genesis(&self) -> Option<&Hash> {
+		self.genesis.as_ref()
+	}
+
+	/// Gets the parameters with which the captchas of new messages are
+	/// generated.
+	pub fn captcha_params(&self) -> &CaptchaParams {
+		&self.captcha_params
+	}
+
+	/// Gets the maximum number of messages to look back from a message's
+	/// parent to find the captcha it answers.
+	pub fn lookback_factor(&self) -> usize {
+		self.lookback_factor
+	}
+
+	/// Gets the number of milliseconds by which the timestamp of a message
+	/// may be ahead of the local clock.
+	pub fn timestamp_tolerance(&self) -> u64 {
+		self.timestamp_tolerance
+	}
+
+	/// Gets the number of known ancestors of the message with the indicated hash.
+	pub fn height(&self, hash: &Hash) -> Option<usize> {
+		self.nodes.get(hash).map(|node| node.height)
+	}
+
+	/// Gets the number of distinct captcha answers in the chain ending with
+	/// the message with the indicated hash, among the messages whose payloads
+	/// are known. Returns None if the payload of the message is not known.
+	pub fn captcha_answers(&self, hash: &Hash) -> Option<usize> {
+		self.nodes
+			.get(hash)
+			.filter(|node| node.has_body)
+			.map(|node| node.answers)
+	}
+
+	/// Gets the order in which the message with the indicated hash was
+	/// inserted into the runtime, relative to other messages. The order is
+	/// kept when the runtime is persisted.
+	pub fn seen_at(&self, hash: &Hash) -> Option<u64> {
+		self.nodes.get(hash).map(|node| node.seen_at)
+	}
+
+	/// Gets an iterator over the known ancestors of the message with the
+	/// indicated hash, from its previous message back towards the root.
+	pub fn ancestors(&self, hash: &Hash) -> Ancestors<'_> {
+		Ancestors {
+			rt: self,
+			next: self.headers.get(hash).and_then(MessageHeader::prev),
+		}
+	}
+
+	/// Gets up to limit messages of the longest chain, in ascending order of
+	/// height, starting with the message at from_height. Messages whose
+	/// payloads are not known are omitted.
+	pub fn chain_range(&self, from_height: usize, limit: usize) -> Vec<&Message> {
+		self.header_range(from_height, limit)
+			.into_iter()
+			.filter_map(|header| self.get_message(header.hash()))
+			.collect()
+	}
+
+	/// Gets the headers of up to limit messages of the longest chain, in
+	/// ascending order of height, starting with the message at from_height.
+	pub fn header_range(&self, from_height: usize, limit: usize) -> Vec<&MessageHeader> {
+		let to_height = from_height.saturating_add(limit);
+
+		self.canonical
+			.range(from_height..to_height)
+			.filter_map(|(_, hash)| self.headers.get(hash))
+			.collect()
+	}
+
+	/// Determines whether the message with hash a is a strict ancestor of the
+	/// message with hash b.
+	pub fn is_ancestor(&self, a: &Hash, b: &Hash) -> bool {
+		self.ancestors(b).any(|header| header.hash() == a)
 	}
 
-	/// Gets the head of the current blockchain.
+	/// Finds the most recent message that both messages descend from, or are.
+	/// Returns None if the messages share no known history.
+	pub fn common_ancestor(&self, a: &Hash, b: &Hash) -> Option<&Hash> {
+		let mut a = self.headers.get(a)?.hash();
+		let mut b = self.headers.get(b)?.hash();
+
+		// Walk back the deeper of the two messages until they meet
+		while a != b {
+			if self.nodes.get(a)?.height >= self.nodes.get(b)?.height {
+				a = self.ancestors(a).next()?.hash();
+			} else {
+				b = self.ancestors(b).next()?.hash();
+			}
+		}
+
+		Some(a)
+	}
+
+	/// Gets the message whose captcha a new message building on `prev` must
+	/// answer, per the captcha lookback rule. Returns None if `prev` or one
+	/// of the ancestors that must be walked is not known, or if the payload
+	/// of the message is not known.
+	pub fn captcha_source(&self, prev: &Hash) -> Option<&Message> {
+		self.get_message(self.captcha_source_hash(prev)?)
+	}
+
+	/// Gets the hash of the message whose captcha a new message building on
+	/// `prev` must answer. Only the headers of the chain need be known.
+	pub fn captcha_source_hash(&self, prev: &Hash) -> Option<&Hash> {
+		let parent = self.get_header(prev)?;
+		let lookback = msg::lookback(prev, parent.height().checked_add(1)?, self.lookback_factor)?;
+
+		let mut curr = parent;
+		for _ in 0..lookback {
+			curr = self.get_header(curr.prev()?)?;
+		}
+
+		Some(curr.hash())
+	}
+
+	/// Gets the head of the current blockchain. Returns None if its payload
+	/// is not known.
 	pub fn head(&self) -> Option<&Message> {
 		let longest = self.longest_chain()?;
-		self.messages.get(&longest)
+		self.messages.get(longest)
+	}
+
+	/// Gets the header of the head of the current blockchain.
+	pub fn head_header(&self) -> Option<&MessageHeader> {
+		let longest = self.longest_chain()?;
+		self.headers.get(longest)
 	}
 
 	/// Resets the runtime.
 	pub fn flush(&mut self) {
 		self.messages = HashMap::default();
-		self.chains = Vec::default();
+		self.headers = HashMap::default();
+		self.roots = HashSet::default();
+		self.nodes = HashMap::default();
+		self.children = HashMap::default();
+		self.heads = HashSet::default();
+		self.by_height = BTreeMap::default();
+		self.n_seen = 0;
+		self.reorgs = Vec::new();
+		self.head = None;
+		self.canonical = BTreeMap::default();
 	}
 }
 
@@ -91,11 +1009,24 @@ mod tests {
 	use super::{super::msg::MessageData, *};
 	use std::error::Error;
 
+	// Builds a message with no data on top of the given message
+	fn child_of(prev: &Message) -> Result<Message, Box<dyn Error>> {
+		Ok(Message::try_from(MessageData::new(
+			Vec::new(),
+			Some(prev.hash().clone()),
+			None,
+			None,
+			prev.data().height() + 1,
+			prev.data().timestamp() + 1,
+			0,
+		))?)
+	}
+
 	#[test]
 	fn test_default() {
 		let rt = Rt::default();
 		assert_eq!(rt.messages.len(), 0);
-		assert_eq!(rt.chains.len(), 0);
+		assert_eq!(rt.heads.len(), 0);
 	}
 
 	#[test]
@@ -103,25 +1034,14 @@ mod tests {
 		let mut rt = Rt::default();
 
 		// Generate blank message
-		let data = MessageData::new(Vec::new(), None, None, None, 0, 0);
+		let data = MessageData::new(Vec::new(), None, None, None, 0, 0, 0);
 		let msg = Message::try_from(data)?;
 
 		// Insert the message
 		rt.insert_message(msg.clone());
 
-		assert_eq!(
-			&rt.chains
-				.get(0)
-				.ok_or("Message not found in head list.")?
-				.head,
-			msg.hash()
-		);
-		assert!(rt
-			.chains
-			.get(0)
-			.ok_or("Message not found in head list.")?
-			.messages
-			.contains(&msg.hash()));
+		assert!(rt.heads.contains(msg.hash()));
+		assert_eq!(rt.height(msg.hash()), Some(0));
 		assert_eq!(
 			rt.messages
 				.get(&msg.hash())
@@ -137,7 +1057,7 @@ mod tests {
 		let mut rt = Rt::default();
 
 		// Generate blank message
-		let data = MessageData::new(Vec::new(), None, None, None, 0, 0);
+		let data = MessageData::new(Vec::new(), None, None, None, 0, 0, 0);
 		let msg = Message::try_from(data)?;
 
 		// Insert the message
@@ -149,11 +1069,11 @@ mod tests {
 		);
 
 		// Make another chain
-		let data2 = MessageData::new(Vec::new(), None, None, None, 0, 0);
+		let data2 = MessageData::new(Vec::new(), None, None, None, 0, 0, 0);
 		let msg2 = Message::try_from(data2)?;
 		rt.insert_message(msg2.clone());
 
-		let data3 = MessageData::new(Vec::new(), Some(msg2.hash().clone()), None, None, 1, 0);
+		let data3 = MessageData::new(Vec::new(), Some(msg2.hash().clone()), None, None, 1, 0, 0);
 		let msg3 = Message::try_from(data3)?;
 		rt.insert_message(msg3.clone());
 
@@ -161,7 +1081,7 @@ mod tests {
 			rt.longest_chain().ok_or("No longest chain found")?,
 			msg3.hash()
 		);
-		assert_eq!(rt.chains.len(), 2);
+		assert_eq!(rt.heads.len(), 2);
 
 		Ok(())
 	}
@@ -173,7 +1093,7 @@ mod tests {
 		assert!(rt.get_message(&[0; 32].into()).is_none());
 
 		// Generate blank message
-		let data = MessageData::new(Vec::new(), None, None, None, 0, 0);
+		let data = MessageData::new(Vec::new(), None, None, None, 0, 0, 0);
 		let msg = Message::try_from(data)?;
 
 		// Insert the message
@@ -182,27 +1102,500 @@ mod tests {
 		assert_eq!(rt.get_message(msg.hash()), Some(&msg));
 
 		// Make another chain
-		let data2 = MessageData::new(Vec::new(), None, None, None, 0, 0);
+		let data2 = MessageData::new(Vec::new(), None, None, None, 0, 0, 0);
 		let msg2 = Message::try_from(data2)?;
 		rt.insert_message(msg2.clone());
 
-		let data3 = MessageData::new(Vec::new(), Some(msg2.hash().clone()), None, None, 1, 0);
+		let data3 = MessageData::new(Vec::new(), Some(msg2.hash().clone()), None, None, 1, 0, 0);
 		let msg3 = Message::try_from(data3)?;
 		rt.insert_message(msg3.clone());
 
-		assert_eq!(rt.get_message(msg.hash()), None);
+		// Messages outside of the longest chain remain available
+		assert_eq!(rt.get_message(msg.hash()), Some(&msg));
 		assert_eq!(rt.get_message(msg2.hash()), Some(&msg2));
 		assert_eq!(rt.get_message(msg3.hash()), Some(&msg3));
 
 		Ok(())
 	}
 
+	#[test]
+	fn test_fork() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		let a = child_of(&root)?;
+		let b = child_of(&a)?;
+		rt.insert_message(root.clone());
+		rt.insert_message(a.clone());
+		rt.insert_message(b.clone());
+
+		// Fork from an ancestor of the HEAD
+		let c = child_of(&a)?;
+		rt.insert_message(c.clone());
+
+		assert_eq!(rt.height(c.hash()), Some(2));
+		assert_eq!(
+			rt.ancestors(c.hash())
+				.map(MessageHeader::hash)
+				.collect::<Vec<&Hash>>(),
+			vec![a.hash(), root.hash()]
+		);
+
+		// The fork becomes the longest chain once it overtakes the HEAD,
+		// without losing its shared history
+		let d = child_of(&c)?;
+		rt.insert_message(d.clone());
+
+		assert_eq!(rt.longest_chain(), Some(d.hash()));
+		assert_eq!(rt.get_message(b.hash()), Some(&b));
+		assert_eq!(rt.heads.len(), 2);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_reorg() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		let a = child_of(&root)?;
+		let b = child_of(&a)?;
+		assert_eq!(rt.insert_message(root.clone()), None);
+		assert_eq!(rt.insert_message(a.clone()), None);
+		assert_eq!(rt.insert_message(b.clone()), None);
+
+		// Connect a longer fork all at once
+		let c = Message::try_from(MessageData::new(
+			vec![1],
+			Some(root.hash().clone()),
+			None,
+			None,
+			1,
+			1,
+			0,
+		))?;
+		let d = child_of(&c)?;
+		let e = child_of(&d)?;
+		assert_eq!(rt.insert_message(e.clone()), None);
+		assert_eq!(rt.insert_message(d.clone()), None);
+
+		let reorg = Reorg {
+			old_head: b.hash().clone(),
+			new_head: e.hash().clone(),
+			detached: vec![b.hash().clone(), a.hash().clone()],
+			attached: vec![c.hash().clone(), d.hash().clone(), e.hash().clone()],
+		};
+		assert_eq!(rt.insert_message(c.clone()), Some(reorg.clone()));
+
+		// Extending the HEAD is not a reorg
+		assert_eq!(rt.insert_message(child_of(&e)?), None);
+
+		assert_eq!(rt.take_reorgs(), vec![reorg]);
+		assert!(rt.take_reorgs().is_empty());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_checkpoints() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		let a = child_of(&root)?;
+		let b = child_of(&a)?;
+		let c = Message::try_from(MessageData::new(
+			vec![1],
+			Some(root.hash().clone()),
+			None,
+			None,
+			1,
+			1,
+			0,
+		))?;
+		let d = child_of(&c)?;
+		let e = child_of(&d)?;
+
+		for msg in [&root, &a, &b, &c, &d, &e] {
+			rt.insert_message(msg.clone());
+		}
+		assert_eq!(rt.longest_chain(), Some(e.hash()));
+
+		// The longer fork contradicts the checkpoint, and is abandoned
+		rt.set_checkpoints([Checkpoint {
+			height: 1,
+			hash: a.hash().clone(),
+		}]);
+
+		assert_eq!(rt.longest_chain(), Some(b.hash()));
+		assert!(rt.contradicts_checkpoint(c.hash(), 1));
+		assert!(!rt.contradicts_checkpoint(a.hash(), 1));
+		assert_eq!(
+			rt.take_reorgs().pop().map(|reorg| reorg.new_head),
+			Some(b.hash().clone())
+		);
+
+		// Extending the fork doesn't help it
+		rt.insert_message(child_of(&e)?);
+		assert_eq!(rt.longest_chain(), Some(b.hash()));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_max_reorg_depth() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+		rt.set_max_reorg_depth(Some(1));
+
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		let a = child_of(&root)?;
+		let b = child_of(&a)?;
+		for msg in [&root, &a, &b] {
+			rt.insert_message(msg.clone());
+		}
+
+		// A fork detaching two messages is refused, however long it gets
+		let c = Message::try_from(MessageData::new(
+			vec![1],
+			Some(root.hash().clone()),
+			None,
+			None,
+			1,
+			1,
+			0,
+		))?;
+		let d = child_of(&c)?;
+		let e = child_of(&d)?;
+
+		assert!(rt.exceeds_reorg_depth(c.hash(), 1));
+		for msg in [&c, &d, &e] {
+			rt.insert_message(msg.clone());
+		}
+		assert_eq!(rt.longest_chain(), Some(b.hash()));
+
+		// A fork detaching one message is accepted
+		let f = Message::try_from(MessageData::new(
+			vec![2],
+			Some(a.hash().clone()),
+			None,
+			None,
+			2,
+			2,
+			0,
+		))?;
+		let g = child_of(&f)?;
+
+		assert!(!rt.exceeds_reorg_depth(f.hash(), 2));
+		rt.insert_message(f.clone());
+		rt.insert_message(g.clone());
+		assert_eq!(rt.longest_chain(), Some(g.hash()));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_out_of_order() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		let a = child_of(&root)?;
+		let b = child_of(&a)?;
+
+		// Messages with missing history are never the longest chain
+		rt.insert_message(b.clone());
+		rt.insert_message(a.clone());
+		assert_eq!(rt.longest_chain(), None);
+
+		// Until their history arrives
+		rt.insert_message(root.clone());
+		assert_eq!(rt.longest_chain(), Some(b.hash()));
+		assert_eq!(rt.height(b.hash()), Some(2));
+		assert_eq!(rt.heads.len(), 1);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_headers_first() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		let a = child_of(&root)?;
+		let b = child_of(&a)?;
+		for msg in [&root, &a, &b] {
+			rt.insert_header(msg.header());
+		}
+
+		// Chains are not chosen until their payloads are known
+		assert_eq!(rt.height(b.hash()), Some(2));
+		assert_eq!(rt.longest_chain(), None);
+		assert_eq!(
+			rt.missing_bodies(10),
+			vec![root.hash().clone(), a.hash().clone(), b.hash().clone()]
+		);
+		assert_eq!(rt.missing_bodies(1), vec![root.hash().clone()]);
+
+		rt.insert_message(root.clone());
+		rt.insert_message(b.clone());
+		assert_eq!(rt.longest_chain(), Some(root.hash()));
+		assert!(!rt.is_complete(b.hash()));
+		assert_eq!(rt.missing_bodies(10), vec![a.hash().clone()]);
+
+		rt.insert_message(a.clone());
+		assert_eq!(rt.longest_chain(), Some(b.hash()));
+		assert!(rt.is_complete(b.hash()));
+		assert!(rt.missing_bodies(10).is_empty());
+
+		// Light clients follow the headers alone
+		let mut light = Rt::default();
+		light.set_light(true);
+		for msg in [&root, &a, &b] {
+			light.insert_header(msg.header());
+		}
+		assert_eq!(light.longest_chain(), Some(b.hash()));
+		assert_eq!(light.head(), None);
+		assert_eq!(light.head_header(), Some(&b.header()));
+		assert!(light.chain_range(0, 10).is_empty());
+		assert_eq!(light.header_range(1, 1), vec![&a.header()]);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_chain_range() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+		assert!(rt.chain_range(0, 10).is_empty());
+
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		let a = child_of(&root)?;
+		let b = child_of(&a)?;
+		let c = child_of(&b)?;
+		for msg in [&root, &a, &b, &c] {
+			rt.insert_message(msg.clone());
+		}
+
+		assert_eq!(rt.chain_range(0, 10), vec![&root, &a, &b, &c]);
+		assert_eq!(rt.chain_range(1, 2), vec![&a, &b]);
+		assert_eq!(rt.chain_range(3, usize::MAX), vec![&c]);
+		assert!(rt.chain_range(4, 10).is_empty());
+		assert!(rt.chain_range(0, 0).is_empty());
+
+		// The range follows the longest chain across reorgs
+		let d = Message::try_from(MessageData::new(
+			vec![1],
+			Some(a.hash().clone()),
+			None,
+			None,
+			2,
+			2,
+			0,
+		))?;
+		let e = child_of(&d)?;
+		let f = child_of(&e)?;
+		for msg in [&f, &e, &d] {
+			rt.insert_message(msg.clone());
+		}
+
+		assert_eq!(rt.chain_range(0, 10), vec![&root, &a, &d, &e, &f]);
+		assert_eq!(rt.chain_range(2, 1), vec![&d]);
+		assert_eq!(rt.header_range(4, 10), vec![&f.header()]);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_is_ancestor() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		let a = child_of(&root)?;
+		let b = child_of(&a)?;
+		let c = child_of(&root)?;
+
+		for msg in [&root, &a, &b, &c] {
+			rt.insert_message(msg.clone());
+		}
+
+		assert!(rt.is_ancestor(root.hash(), b.hash()));
+		assert!(rt.is_ancestor(a.hash(), b.hash()));
+		assert!(!rt.is_ancestor(b.hash(), b.hash()));
+		assert!(!rt.is_ancestor(b.hash(), a.hash()));
+		assert!(!rt.is_ancestor(c.hash(), b.hash()));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_common_ancestor() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		let a = child_of(&root)?;
+		let b = child_of(&a)?;
+		let c = child_of(&b)?;
+		let d = child_of(&a)?;
+		let other = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+
+		for msg in [&root, &a, &b, &c, &d, &other] {
+			rt.insert_message(msg.clone());
+		}
+
+		assert_eq!(rt.common_ancestor(c.hash(), d.hash()), Some(a.hash()));
+		assert_eq!(rt.common_ancestor(d.hash(), c.hash()), Some(a.hash()));
+		assert_eq!(rt.common_ancestor(b.hash(), c.hash()), Some(b.hash()));
+		assert_eq!(rt.common_ancestor(c.hash(), c.hash()), Some(c.hash()));
+		assert_eq!(rt.common_ancestor(c.hash(), other.hash()), None);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_serde() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		let a = child_of(&root)?;
+		rt.insert_message(root);
+		rt.insert_message(a);
+
+		// The tree is rebuilt from the persisted messages
+		let ser = serde_json::to_vec(&rt)?;
+		assert_eq!(serde_json::from_slice::<Rt>(ser.as_slice())?, rt);
+
+		// Chains persisted under another hash scheme are discarded
+		let mut outdated = serde_json::to_value(&rt)?;
+		outdated
+			.as_object_mut()
+			.ok_or("the runtime is not serialized as an object")?
+			.remove("version");
+		assert_eq!(serde_json::from_value::<Rt>(outdated)?, Rt::default());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_insert_root() -> Result<(), Box<dyn Error>> {
+		let root = Message::try_from(MessageData::new(Vec::new(), None, None, None, 0, 0, 0))?;
+		let a = child_of(&root)?;
+		let b = child_of(&a)?;
+		let c = child_of(&b)?;
+
+		// Without its history, a chain cannot become the longest chain
+		let mut rt = Rt::default();
+		rt.insert_message(c.clone());
+		rt.insert_message(b.clone());
+		assert_eq!(rt.longest_chain(), None);
+
+		// Unless it is rooted at a trusted message
+		rt.insert_root(b.clone());
+		assert_eq!(rt.longest_chain(), Some(c.hash()));
+		assert_eq!(rt.height(c.hash()), Some(3));
+		assert!(rt.is_complete(c.hash()));
+
+		// Roots are persisted
+		let ser = serde_json::to_vec(&rt)?;
+		let de = serde_json::from_slice::<Rt>(ser.as_slice())?;
+		assert_eq!(de, rt);
+		assert_eq!(de.longest_chain(), Some(c.hash()));
+
+		// The history of a root may still arrive
+		rt.insert_message(root);
+		rt.insert_message(a);
+		assert_eq!(rt.longest_chain(), Some(c.hash()));
+		assert_eq!(rt.height(c.hash()), Some(3));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_genesis() -> Result<(), Box<dyn Error>> {
+		let genesis = Message::try_from(MessageData::new(vec![1], None, None, None, 0, 0, 0))?;
+		let a = child_of(&genesis)?;
+
+		// A competing root with a longer chain
+		let rival = Message::try_from(MessageData::new(vec![2], None, None, None, 0, 0, 0))?;
+		let b = child_of(&rival)?;
+		let c = child_of(&b)?;
+
+		let mut rt = Rt::default();
+		for msg in [&rival, &b, &c] {
+			rt.insert_message(msg.clone());
+		}
+		assert_eq!(rt.longest_chain(), Some(c.hash()));
+
+		// Once the genesis is known, only chains rooted at it are accepted
+		let mut spec = ChainSpec::new(Genesis::Message(genesis.clone()));
+		spec.lookback_factor = 2;
+		rt.set_spec(&spec);
+		assert_eq!(rt.genesis(), Some(genesis.hash()));
+		assert_eq!(rt.lookback_factor(), 2);
+		assert_eq!(rt.longest_chain(), Some(genesis.hash()));
+
+		rt.insert_message(a.clone());
+		assert_eq!(rt.longest_chain(), Some(a.hash()));
+
+		// Even if the genesis is only known by its hash
+		let mut rt = Rt::default();
+		rt.set_spec(&ChainSpec::new(Genesis::Hash(genesis.hash().clone())));
+		for msg in [&rival, &b, &c, &a] {
+			rt.insert_message(msg.clone());
+		}
+		assert_eq!(rt.longest_chain(), None);
+
+		rt.insert_message(genesis);
+		assert_eq!(rt.longest_chain(), Some(a.hash()));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_captcha_source() -> Result<(), Box<dyn Error>> {
+		let mut rt = Rt::default();
+
+		assert!(rt.captcha_source(&[0; 32].into()).is_none());
+
+		// Build a chain of messages
+		let data = MessageData::new(Vec::new(), None, None, None, 0, 0, 0);
+		let mut chain = vec![Message::try_from(data)?];
+		rt.insert_message(chain[0].clone());
+
+		for height in 1..10 {
+			let data = MessageData::new(
+				Vec::new(),
+				Some(chain[height - 1].hash().clone()),
+				None,
+				None,
+				height,
+				height as u128,
+				0,
+			);
+			let msg = Message::try_from(data)?;
+			rt.insert_message(msg.clone());
+			chain.push(msg);
+		}
+
+		// The only possible source for the message after the root is the root
+		assert_eq!(rt.captcha_source(chain[0].hash()), Some(&chain[0]));
+
+		// Sources must be within the lookback window of the parent
+		for (height, msg) in chain.iter().enumerate() {
+			let src = rt
+				.captcha_source(msg.hash())
+				.ok_or("No captcha source found")?;
+			let lookback = msg::lookback(msg.hash(), height + 1, CAPTCHA_ANS_LOOKBACK_FACTOR)
+				.ok_or("No lookback")?;
+
+			assert_eq!(src, &chain[height - lookback]);
+		}
+
+		Ok(())
+	}
+
 	#[test]
 	fn test_head() -> Result<(), Box<dyn Error>> {
 		let mut rt = Rt::default();
 
 		// Generate blank message
-		let data = MessageData::new(Vec::new(), None, None, None, 0, 0);
+		let data = MessageData::new(Vec::new(), None, None, None, 0, 0, 0);
 		let msg = Message::try_from(data)?;
 
 		// Insert the message
@@ -211,11 +1604,11 @@ mod tests {
 		assert_eq!(rt.head(), Some(&msg));
 
 		// Make another chain
-		let data2 = MessageData::new(Vec::new(), None, None, None, 0, 0);
+		let data2 = MessageData::new(Vec::new(), None, None, None, 0, 0, 0);
 		let msg2 = Message::try_from(data2)?;
 		rt.insert_message(msg2.clone());
 
-		let data3 = MessageData::new(Vec::new(), Some(msg2.hash().clone()), None, None, 1, 0);
+		let data3 = MessageData::new(Vec::new(), Some(msg2.hash().clone()), None, None, 1, 0, 0);
 		let msg3 = Message::try_from(data3)?;
 		rt.insert_message(msg3.clone());
 
@@ -0,0 +1,282 @@
+use super::{
+	super::crypto::hash::Hash,
+	msg::Message,
+	rt::{Checkpoint, Rt},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+	error::Error as StdError,
+	fmt::{Display, Error as FmtError, Formatter},
+	iter,
+};
+
+/// A point in the chain from which a node may begin synchronizing without
+/// downloading the messages before it. Snapshots served by peers are only
+/// accepted if they end at this message.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TrustedSnapshot {
+	/// The height of the message
+	pub height: usize,
+
+	/// The hash of the message
+	pub hash: Hash,
+
+	/// The hash of the application state at the message, if the application
+	/// restores its state from snapshots
+	pub state_digest: Option<Hash>,
+}
+
+impl From<&TrustedSnapshot> for Checkpoint {
+	fn from(trusted: &TrustedSnapshot) -> Self {
+		Self {
+			height: trusted.height,
+			hash: trusted.hash.clone(),
+		}
+	}
+}
+
+/// The reason a snapshot was refused.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+	/// The snapshot does not end at the trusted message
+	WrongHead,
+
+	/// The snapshot holds too few messages to validate the messages after it
+	TooShort,
+
+	/// A message does not reference the message before it in the snapshot
+	BrokenLink,
+
+	/// The hash of a message does not match its contents
+	InvalidHash,
+
+	/// The application state is missing or does not match its trusted hash
+	WrongState,
+}
+
+impl Display for Error {
+	fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+		match self {
+			Self::WrongHead => write!(f, "the snapshot does not end at the trusted message"),
+			Self::TooShort => write!(f, "the snapshot is missing messages"),
+			Self::BrokenLink => write!(f, "the snapshot messages do not form a chain"),
+			Self::InvalidHash => write!(f, "a message hash does not match its contents"),
+			Self::WrongState => write!(f, "the snapshot state does not match its trusted hash"),
+		}
+	}
+}
+
+impl StdError for Error {}
+
+/// The most recent messages of a chain up to a snapshot point, along with
+/// the application state at that point. The messages are enough to validate
+/// the messages after the snapshot, which may be synchronized as usual.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+	messages: Vec<Message>,
+	state: Option<Vec<u8>>,
+}
+
+impl Snapshot {
+	/// Takes a snapshot of the chain ending at the message with the indicated
+	/// hash from the runtime. Returns None if the payload of the message, or
+	/// of any message needed to validate its descendants, is not known.
+	pub fn from_rt(rt: &Rt, hash: &Hash, state: Option<Vec<u8>>) -> Option<Self> {
+		let head = rt.get_header(hash)?;
+		let len = window(head.height(), rt.lookback_factor());
+
+		let mut messages = iter::once(head)
+			.chain(rt.ancestors(hash))
+			.take(len)
+			.map(|header| rt.get_message(header.hash()).cloned())
+			.collect::<Option<Vec<Message>>>()?;
+		if messages.len() < len {
+			return None;
+		}
+		messages.reverse();
+
+		Some(Self { messages, state })
+	}
+
+	/// Gets the messages in the snapshot, in ascending order of height.
+	pub fn messages(&self) -> &[Message] {
+		self.messages.as_slice()
+	}
+
+	/// Gets the application state at the end of the snapshot.
+	pub fn state(&self) -> Option<&[u8]> {
+		self.state.as_deref()
+	}
+
+	/// Inserts the messages of the snapshot into the runtime, rooting the
+	/// chain at the oldest message. Returns the application state. Only
+	/// snapshots which have been verified should be restored.
+	pub fn restore(self, rt: &mut Rt) -> Option<Vec<u8>> {
+		let mut messages = self.messages.into_iter();
+
+		if let Some(root) = messages.next() {
+			rt.insert_root(root);
+		}
+		for msg in messages {
+			rt.insert_message(msg);
+		}
+
+		self.state
+	}
+
+	/// Checks that the snapshot is a chain of genuine messages ending at the
+	/// trusted message, long enough for the lookback factor of the chain,
+	/// and that its application state matches the trusted state, if any. The
+	/// state is dropped if none is trusted.
+	pub fn verify(
+		&mut self,
+		trusted: &TrustedSnapshot,
+		lookback_factor: usize,
+	) -> Result<(), Error> {
+		let head = self.messages.last().ok_or(Error::TooShort)?;
+		if head.hash() != &trusted.hash || head.data().height() != trusted.height {
+			return Err(Error::WrongHead);
+		}
+
+		if self.messages.len() != window(trusted.height, lookback_factor) {
+			return Err(Error::TooShort);
+		}
+
+		for msg in &self.messages {
+			if msg.data().hashed().ok().as_ref() != Some(msg.hash()) {
+				return Err(Error::InvalidHash);
+			}
+		}
+
+		for pair in self.messages.windows(2) {
+			if pair[1].data().prev() != Some(pair[0].hash())
+				|| pair[1].data().height() != pair[0].data().height() + 1
+			{
+				return Err(Error::BrokenLink);
+			}
+		}
+
+		match &trusted.state_digest {
+			Some(digest) => match &self.state {
+				Some(state) if Hash::from(blake3::hash(state)) == *digest => {}
+				_ => return Err(Error::WrongState),
+			},
+			None => self.state = None,
+		}
+
+		Ok(())
+	}
+}
+
+// Gets the number of messages a snapshot at the given height holds: those
+// whose captchas the messages after it may answer
+fn window(height: usize, lookback_factor: usize) -> usize {
+	height.saturating_add(1).min(lookback_factor)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{
+		super::{msg::MessageData, CAPTCHA_ANS_LOOKBACK_FACTOR},
+		*,
+	};
+	use std::error::Error as StdError;
+
+	// Builds a chain of the given length with no data
+	fn chain(len: usize) -> Result<Vec<Message>, Box<dyn StdError>> {
+		let mut chain = vec![Message::try_from(MessageData::new(
+			Vec::new(),
+			None,
+			None,
+			None,
+			0,
+			0,
+			0,
+		))?];
+
+		for height in 1..len {
+			let prev = chain[height - 1].hash().clone();
+			chain.push(Message::try_from(MessageData::new(
+				Vec::new(),
+				Some(prev),
+				None,
+				None,
+				height,
+				height as u128,
+				0,
+			))?);
+		}
+
+		Ok(chain)
+	}
+
+	#[test]
+	fn test_verify() -> Result<(), Box<dyn StdError>> {
+		let chain = chain(16)?;
+		let mut rt = Rt::default();
+		for msg in chain.iter() {
+			rt.insert_message(msg.clone());
+		}
+
+		let state = vec![1, 2, 3];
+		let mut trusted = TrustedSnapshot {
+			height: 10,
+			hash: chain[10].hash().clone(),
+			state_digest: Some(blake3::hash(&state).into()),
+		};
+
+		let snapshot =
+			Snapshot::from_rt(&rt, chain[10].hash(), Some(state.clone())).ok_or("no snapshot")?;
+		assert_eq!(snapshot.messages().len(), CAPTCHA_ANS_LOOKBACK_FACTOR);
+		assert_eq!(snapshot.messages().last(), Some(&chain[10]));
+		assert_eq!(
+			snapshot
+				.clone()
+				.verify(&trusted, CAPTCHA_ANS_LOOKBACK_FACTOR),
+			Ok(())
+		);
+
+		// Snapshots must end at the trusted message, and carry the trusted state
+		let mut other =
+			Snapshot::from_rt(&rt, chain[11].hash(), Some(state.clone())).ok_or("no snapshot")?;
+		assert_eq!(
+			other.verify(&trusted, CAPTCHA_ANS_LOOKBACK_FACTOR),
+			Err(Error::WrongHead)
+		);
+
+		let mut stateless = Snapshot::from_rt(&rt, chain[10].hash(), None).ok_or("no snapshot")?;
+		assert_eq!(
+			stateless.verify(&trusted, CAPTCHA_ANS_LOOKBACK_FACTOR),
+			Err(Error::WrongState)
+		);
+
+		// Nor may they skip messages
+		let mut broken = snapshot.clone();
+		broken.messages.remove(1);
+		broken.messages.insert(0, chain[4].clone());
+		assert_eq!(
+			broken.verify(&trusted, CAPTCHA_ANS_LOOKBACK_FACTOR),
+			Err(Error::BrokenLink)
+		);
+
+		// Untrusted state is dropped
+		trusted.state_digest = None;
+		let mut snapshot = snapshot;
+		assert_eq!(
+			snapshot.verify(&trusted, CAPTCHA_ANS_LOOKBACK_FACTOR),
+			Ok(())
+		);
+		assert_eq!(snapshot.state(), None);
+
+		// The chain continues from the snapshot
+		let mut restored = Rt::default();
+		assert_eq!(snapshot.restore(&mut restored), None);
+		assert_eq!(restored.longest_chain(), Some(chain[10].hash()));
+
+		restored.insert_message(chain[11].clone());
+		assert_eq!(restored.longest_chain(), Some(chain[11].hash()));
+		assert_eq!(restored.height(chain[11].hash()), Some(11));
+
+		Ok(())
+	}
+}
@@ -0,0 +1,222 @@
+use super::{
+	super::{captcha::CaptchaParams, crypto::hash::Hash},
+	msg::{Message, MessageData},
+	rt::Checkpoint,
+	CAPTCHA_ANS_LOOKBACK_FACTOR,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Error as SerdeError;
+use std::{
+	error::Error as StdError,
+	fmt::{Display, Error as FmtError, Formatter},
+};
+
+/// The message at which every valid chain is rooted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Genesis {
+	/// The genesis message itself, which the runtime starts with
+	Message(Message),
+
+	/// The hash of the genesis message, which is downloaded from peers
+	Hash(Hash),
+}
+
+impl Genesis {
+	/// Gets the hash of the genesis message.
+	pub fn hash(&self) -> &Hash {
+		match self {
+			Self::Message(msg) => msg.hash(),
+			Self::Hash(hash) => hash,
+		}
+	}
+}
+
+/// The reason a spec was refused.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+	/// The genesis message belongs to a chain other than the node's
+	WrongChain,
+
+	/// The lookback factor is zero, such that no captcha could be answered
+	InvalidLookbackFactor,
+
+	/// The hash of the genesis message does not match its contents
+	InvalidGenesisHash,
+
+	/// The genesis message has a previous message, or a nonzero height
+	GenesisNotRoot,
+}
+
+impl Display for Error {
+	fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+		match self {
+			Self::WrongChain => write!(f, "the genesis message belongs to another chain"),
+			Self::InvalidLookbackFactor => write!(f, "the lookback factor must be at least 1"),
+			Self::InvalidGenesisHash => {
+				write!(f, "the genesis message hash does not match its contents")
+			}
+			Self::GenesisNotRoot => write!(f, "the genesis message is not the root of a chain"),
+		}
+	}
+}
+
+impl StdError for Error {}
+
+/// The parameters of a chain which every node of the chain must agree on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainSpec {
+	/// The first message of the chain
+	pub genesis: Genesis,
+
+	/// The parameters with which the captchas of new messages are generated
+	#[serde(default)]
+	pub captcha: CaptchaParams,
+
+	/// The maximum number of messages to look back from a message's parent
+	/// to find the captcha it answers. See [`lookback`](super::msg::lookback).
+	#[serde(default = "default_lookback_factor")]
+	pub lookback_factor: usize,
+
+	/// The number of milliseconds by which the timestamp of a message may
+	/// be ahead of the local clock
+	#[serde(default)]
+	pub timestamp_tolerance: u64,
+
+	/// Messages which every valid chain must include
+	#[serde(default)]
+	pub checkpoints: Vec<Checkpoint>,
+}
+
+fn default_lookback_factor() -> usize {
+	CAPTCHA_ANS_LOOKBACK_FACTOR
+}
+
+impl ChainSpec {
+	/// Creates a spec for the chain rooted at the genesis message, with the
+	/// default parameters.
+	pub fn new(genesis: Genesis) -> Self {
+		Self {
+			genesis,
+			captcha: CaptchaParams::default(),
+			lookback_factor: CAPTCHA_ANS_LOOKBACK_FACTOR,
+			timestamp_tolerance: 0,
+			checkpoints: Vec::new(),
+		}
+	}
+
+	/// Creates a spec for a new chain with the given ID, generating its
+	/// genesis message with the current time.
+	pub fn generate(chain_id: usize) -> Result<Self, SerdeError> {
+		let genesis = Message::try_from(
+			MessageData::builder(Vec::new())
+				.chain_id(chain_id)
+				.timestamp(instant::now() as u128)
+				.build(),
+		)?;
+
+		Ok(Self::new(Genesis::Message(genesis)))
+	}
+
+	/// Gets the hash of the genesis message.
+	pub fn genesis_hash(&self) -> &Hash {
+		self.genesis.hash()
+	}
+
+	/// Determines whether the spec may be used by a node of the chain with
+	/// the given ID. Specs loaded from outside the node should be checked
+	/// before use. A genesis message given by its hash is only checked once
+	/// it is downloaded.
+	pub fn validate(&self, chain_id: usize) -> Result<(), Error> {
+		if self.lookback_factor == 0 {
+			return Err(Error::InvalidLookbackFactor);
+		}
+
+		if let Genesis::Message(genesis) = &self.genesis {
+			if genesis.data().hashed().ok().as_ref() != Some(genesis.hash()) {
+				return Err(Error::InvalidGenesisHash);
+			}
+
+			if genesis.data().prev().is_some() || genesis.data().height() != 0 {
+				return Err(Error::GenesisNotRoot);
+			}
+
+			if genesis.data().chain_id() != chain_id {
+				return Err(Error::WrongChain);
+			}
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_generate() -> Result<(), SerdeError> {
+		let spec = ChainSpec::generate(69)?;
+		let genesis = match &spec.genesis {
+			Genesis::Message(msg) => msg.clone(),
+			Genesis::Hash(_) => panic!("expected a genesis message"),
+		};
+		assert_eq!(genesis.data().chain_id(), 69);
+		assert_eq!(genesis.data().prev(), None);
+		assert_eq!(spec.genesis_hash(), genesis.hash());
+
+		// Specs survive being written to a file
+		let read = serde_json::from_slice::<ChainSpec>(&serde_json::to_vec(&spec)?)?;
+		assert_eq!(read, spec);
+
+		// Parameters which are left out take their defaults
+		let minimal = serde_json::from_str::<ChainSpec>(&format!(
+			"{{\"genesis\":{{\"hash\":\"{}\"}}}}",
+			hex::encode(genesis.hash())
+		))?;
+		assert_eq!(
+			minimal,
+			ChainSpec::new(Genesis::Hash(genesis.hash().clone()))
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_validate() -> Result<(), SerdeError> {
+		let spec = ChainSpec::generate(1)?;
+		assert_eq!(spec.validate(1), Ok(()));
+		assert_eq!(spec.validate(0), Err(Error::WrongChain));
+
+		let mut zero_lookback = spec.clone();
+		zero_lookback.lookback_factor = 0;
+		assert_eq!(zero_lookback.validate(1), Err(Error::InvalidLookbackFactor));
+
+		// The genesis message must hash to what it claims to
+		let mut json = serde_json::to_value(&spec)?;
+		json["genesis"]["message"]["hash"] = serde_json::to_value(Hash::from([1; 32]))?;
+		let forged = serde_json::from_value::<ChainSpec>(json)?;
+		assert_eq!(forged.validate(1), Err(Error::InvalidGenesisHash));
+
+		// And may not build on another message
+		let child = Message::try_from(
+			MessageData::builder(Vec::new())
+				.prev(Some(spec.genesis_hash().clone()))
+				.height(1)
+				.chain_id(1)
+				.build(),
+		)?;
+		assert_eq!(
+			ChainSpec::new(Genesis::Message(child)).validate(1),
+			Err(Error::GenesisNotRoot)
+		);
+
+		// Genesis messages given by their hash are checked once downloaded
+		assert_eq!(
+			ChainSpec::new(Genesis::Hash([1; 32].into())).validate(1),
+			Ok(())
+		);
+
+		Ok(())
+	}
+}
@@ -7,6 +7,7 @@ use chud::{
 	},
 };
 use std::error::Error;
+use wasm_bindgen::JsValue;
 use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
 
 wasm_bindgen_test_configure!(run_in_browser);
@@ -29,7 +30,7 @@ async fn test_client_start() -> Result<(), Box<dyn Error>> {
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen_test]
 async fn test_start() -> Result<(), Box<dyn Error>> {
-	chud::start(0, Vec::new());
+	chud::start(0, Vec::new(), JsValue::NULL, JsValue::NULL)?;
 
 	Ok(())
 }
@@ -37,9 +38,9 @@ async fn test_start() -> Result<(), Box<dyn Error>> {
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen_test]
 async fn test_submit_message() -> Result<(), Box<dyn Error>> {
-	chud::start(0, Vec::new());
+	chud::start(0, Vec::new(), JsValue::NULL, JsValue::NULL)?;
 	let msg_data =
-		serde_wasm_bindgen::to_value(&MessageData::new(Vec::new(), None, None, None, 0, 0))
+		serde_wasm_bindgen::to_value(&MessageData::new(Vec::new(), None, None, None, 0, 0, 0))
 			.map_err(|e| <serde_wasm_bindgen::Error as Into<Box<dyn Error>>>::into(e))?;
 	chud::submit_message(msg_data)
 		.await
@@ -50,8 +51,8 @@ async fn test_submit_message() -> Result<(), Box<dyn Error>> {
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen_test]
 async fn test_load_message() -> Result<(), Box<dyn Error>> {
-	chud::start(1, Vec::new());
-	let msg_data = MessageData::new(Vec::new(), None, None, None, 0, 0);
+	chud::start(1, Vec::new(), JsValue::NULL, JsValue::NULL)?;
+	let msg_data = MessageData::new(Vec::new(), None, None, None, 0, 0, 1);
 	let msg_data_js = serde_wasm_bindgen::to_value(&msg_data)
 		.map_err(|e| <serde_wasm_bindgen::Error as Into<Box<dyn Error>>>::into(e))?;
 	chud::flush()
@@ -73,8 +74,8 @@ async fn test_load_message() -> Result<(), Box<dyn Error>> {
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen_test]
 async fn test_get_head() -> Result<(), Box<dyn Error>> {
-	chud::start(0, Vec::new());
-	let msg_data = MessageData::new(Vec::new(), None, None, None, 0, 0);
+	chud::start(0, Vec::new(), JsValue::NULL, JsValue::NULL)?;
+	let msg_data = MessageData::new(Vec::new(), None, None, None, 0, 0, 0);
 	let msg_data_js = serde_wasm_bindgen::to_value(&msg_data)
 		.map_err(|e| <serde_wasm_bindgen::Error as Into<Box<dyn Error>>>::into(e))?;
 	let hash = chud::submit_message(msg_data_js)